@@ -5,6 +5,7 @@ static const uint NUM_WAVES = NUM_THREADS / 32;
 
 static const uint PRIMITIVE_LINE = 1;
 
+[[vk::image_format("rgba8")]]
 RWTexture2D<float4> render_target : register(u0, space0);
 
 struct Locals {
@@ -13,6 +14,7 @@ struct Locals {
     float2 viewport_extent;
     uint num_objects;
 };
+[[vk::push_constant]]
 ConstantBuffer<Locals> u_locals : register(b0, space1);
 
 struct Object {
@@ -36,11 +38,33 @@ float cdf(float x, float slope) {
     return saturate(x * slope + 0.5);
 }
 
-struct ObjectData {
-    uint2 primitives;
-    uint offset_data;
+// Sample grid of a tile group, threads of a wave cover a column of `GROUP_Y` samples.
+struct Tile {
+    float2 offset;
+    float2 extent;
+    float2 dxdy;
+    float2 unit;
+    // Sample position of the first thread in the column.
+    float2 column_start;
 };
-groupshared ObjectData local_objects[GROUP_X][GROUP_Y];
+
+Tile tile_setup(uint2 group_id, uint column) {
+    Tile tile;
+    tile.extent = u_locals.viewport_extent / u_locals.num_tiles;
+    tile.offset = u_locals.viewport_offset + tile.extent * group_id;
+    tile.dxdy = tile.extent / uint2(GROUP_X, GROUP_Y);
+    tile.unit = 1.0 / tile.dxdy;
+    tile.column_start = tile.offset + float2(column + 0.5, 0.0) * tile.dxdy;
+    return tile;
+}
+
+bool object_intersects(Object object, Tile tile) {
+    const float4 bbox = object.bbox - float4(tile.offset.x, tile.offset.y, tile.offset.x, tile.offset.y);
+    return (bbox.z >= 0.0)
+        && (bbox.w >= 0.0)
+        && (tile.extent.x >= bbox.x)
+        && (tile.extent.y >= bbox.y);
+}
 
 struct Intersection {
     float distance;
@@ -48,6 +72,54 @@ struct Intersection {
     float dx;
     float min_y;
 };
+
+// Intersect a line with the sample column, independent of the sample row.
+Intersection intersect_line(uint4 vertices, Tile tile) {
+    const float2 p0 = asfloat(vertices.xy) - tile.column_start;
+    const float2 p1 = asfloat(vertices.zw) - tile.column_start;
+
+    Intersection intersection;
+    intersection.min_y = min(p0.y, p1.y);
+    intersection.dx = 0.0;
+    intersection.distance = 0.0;
+    intersection.slope = 0.0;
+
+    const float max_y = max(p0.y, p1.y);
+    if (max_y >= 0.0) {
+        const float xx0 = clamp(p0.x, -0.5 * tile.dxdy.x, 0.5 * tile.dxdy.x);
+        const float xx1 = clamp(p1.x, -0.5 * tile.dxdy.x, 0.5 * tile.dxdy.x);
+        intersection.dx = (xx1 - xx0) * tile.unit.x;
+
+        const float t = line_raycast(p0.x, p1.x, 0.5 * (xx0 + xx1)); // raycast y direction at sample pos
+        const float d = line_eval(p0.y, p1.y, t) * tile.unit.y; // get x value at ray intersection
+        const float2 tangent = abs(p1 - p0);
+        intersection.distance = d;
+        intersection.slope = tangent.x / max(tangent.x, tangent.y);
+    }
+
+    return intersection;
+}
+
+// Signed coverage of the sample in `row` by an intersected line.
+float line_coverage(Intersection intersection, uint row, Tile tile) {
+    float cy = 1.0;
+    if (intersection.min_y < ((row + 1) * tile.dxdy.y)) {
+        cy = cdf(intersection.distance - (row + 0.5), intersection.slope);
+    }
+    return cy * intersection.dx;
+}
+
+void write_coverage(uint2 group_id, uint2 group_thread_id, float coverage) {
+    float color = saturate(coverage / 6.0);
+    const uint2 thread_id = group_id * uint2(GROUP_X, GROUP_Y) + group_thread_id;
+    render_target[thread_id.xy] = float4(color, color, color, 1.0);
+}
+
+struct ObjectData {
+    uint2 primitives;
+    uint offset_data;
+};
+groupshared ObjectData local_objects[GROUP_X][GROUP_Y];
 groupshared Intersection line_intersect[GROUP_X][GROUP_Y];
 
 [numthreads(GROUP_X * GROUP_Y, 1, 1)]
@@ -57,13 +129,7 @@ void test(
 ) {
     const uint lane = WaveGetLaneIndex();
     const uint2 group_thread_id = uint2(invocation_id.x / WaveGetLaneCount(), lane);
-    const float2 tile_goup_extent = u_locals.viewport_extent / u_locals.num_tiles;
-    const float2 tile_group_offset = u_locals.viewport_offset + tile_goup_extent * group_id.xy;
-
-    const float2 dxdy = tile_goup_extent / uint2(GROUP_X, GROUP_Y);
-    const float2 unit = 1.0 / dxdy;
-
-    const float2 wave_start = tile_group_offset + float2(group_thread_id.x + 0.5, 0.0) * dxdy;
+    const Tile tile = tile_setup(group_id.xy, group_thread_id.x);
 
     float coverage = 0.0;
 
@@ -72,11 +138,7 @@ void test(
         Object object;
         if (i + lane < u_locals.num_objects) {
             object = t_objects[i + lane];
-            const float4 bbox = object.bbox - float4(tile_group_offset.x, tile_group_offset.y, tile_group_offset.x, tile_group_offset.y);
-            intersection = (bbox.z >= 0.0)
-                && (bbox.w >= 0.0)
-                && (tile_goup_extent.x >= bbox.x)
-                && (tile_goup_extent.y >= bbox.y);
+            intersection = object_intersects(object, tile);
         }
 
         uint offset = WavePrefixCountBits(intersection);
@@ -91,43 +153,15 @@ void test(
             const ObjectData local_obj = local_objects[group_thread_id.x][o];
             float local_coverage = 0.0;
 
-            uint base = local_obj.offset_data;
             for (uint p = local_obj.primitives.x; p < local_obj.primitives.y; p += 32) {
                 if (p + lane < local_obj.primitives.y) {
                     const uint vertex_offset = local_obj.offset_data + (p - local_obj.primitives.x + lane);
-                    uint4 vertices = t_data[vertex_offset];
-                    const float2 p0 = asfloat(vertices.xy) - wave_start;
-                    const float2 p1 = asfloat(vertices.zw) - wave_start;
-
-                    Intersection line_intersection;
-                    line_intersection.min_y = min(p0.y, p1.y);
-                    line_intersection.dx = 0.0;
-
-                    const float max_y = max(p0.y, p1.y);
-                    if (max_y >= 0.0) {
-                        const float xx0 = clamp(p0.x, -0.5 * dxdy.x, 0.5 * dxdy.x);
-                        const float xx1 = clamp(p1.x, -0.5 * dxdy.x, 0.5 * dxdy.x);
-                        line_intersection.dx = (xx1 - xx0) * unit.x;
-
-                        const float t = line_raycast(p0.x, p1.x, 0.5 * (xx0 + xx1)); // raycast y direction at sample pos
-                        const float d = line_eval(p0.y, p1.y, t) * unit.y; // get x value at ray intersection
-                        const float2 tangent = abs(p1 - p0);
-                        const float m = tangent.x / max(tangent.x, tangent.y);
-                        line_intersection.distance = d;
-                        line_intersection.slope = m;
-                    }
-
-                    line_intersect[group_thread_id.x][group_thread_id.y] = line_intersection;
+                    line_intersect[group_thread_id.x][group_thread_id.y] = intersect_line(t_data[vertex_offset], tile);
                 }
 
                 const uint num_lanes = WaveActiveCountBits(p + lane < local_obj.primitives.y);
                 for (uint l = 0; l < num_lanes; l++) {
-                    Intersection local_intersection = line_intersect[group_thread_id.x][l];
-                    float cy = 1.0;
-                    if (local_intersection.min_y < ((group_thread_id.y + 1) * dxdy.y)) {
-                        cy = cdf(local_intersection.distance - (group_thread_id.y + 0.5), local_intersection.slope);
-                    }
-                    local_coverage += cy * local_intersection.dx;
+                    local_coverage += line_coverage(line_intersect[group_thread_id.x][l], group_thread_id.y, tile);
                 }
             }
 
@@ -135,7 +169,36 @@ void test(
         }
     }
 
-    float color = saturate(coverage / 6.0);
-    const uint2 thread_id = group_id.xy * uint2(GROUP_X, GROUP_Y) + group_thread_id;
-    render_target[thread_id.xy] = float4(color, color, color, 1.0);
+    write_coverage(group_id.xy, group_thread_id, coverage);
+}
+
+// Wave size independent variant of `test`, each thread evaluates its sample on its own.
+//
+// Used on devices which don't run with 32 lanes per wave, e.g software rasterizers.
+[numthreads(GROUP_X * GROUP_Y, 1, 1)]
+void test_scalar(
+    uint3 invocation_id: SV_GroupThreadID,
+    uint3 group_id: SV_GroupID
+) {
+    const uint2 group_thread_id = uint2(invocation_id.x / GROUP_Y, invocation_id.x % GROUP_Y);
+    const Tile tile = tile_setup(group_id.xy, group_thread_id.x);
+
+    float coverage = 0.0;
+
+    for (uint i = 0; i < u_locals.num_objects; i++) {
+        const Object object = t_objects[i];
+        if (!object_intersects(object, tile)) {
+            continue;
+        }
+
+        float local_coverage = 0.0;
+        for (uint p = object.primitives.x; p < object.primitives.y; p++) {
+            const uint4 vertices = t_data[object.offset_data + (p - object.primitives.x)];
+            local_coverage += line_coverage(intersect_line(vertices, tile), group_thread_id.y, tile);
+        }
+
+        coverage += saturate(local_coverage);
+    }
+
+    write_coverage(group_id.xy, group_thread_id, coverage);
 }
@@ -1,4 +1,5 @@
-use ragnarok::Error;
+use ragnarok::backend::Dx12;
+use ragnarok::{bytemuck, kurbo};
 use std::mem;
 use winit::{
     event::{Event, WindowEvent},
@@ -16,9 +17,13 @@ const TILES_X: u32 = WIDTH / GROUP_X;
 const TILES_Y: u32 = HEIGHT / GROUP_Y;
 
 const NUM_FRAMES: u32 = 2;
-const NUM_QUERIES: u32 = 2;
+const NUM_QUERIES: u32 = 8;
+
+const TARGET_HEIGHT: f32 = 200.0;
+const GUARD_BAND: f64 = 64.0;
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 struct Locals {
     num_tiles: [u32; 2],
     viewport_offset: [f32; 2],
@@ -26,6 +31,9 @@ struct Locals {
     num_objects: u32,
 }
 
+unsafe impl bytemuck::Zeroable for Locals {}
+unsafe impl bytemuck::Pod for Locals {}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let debug_handler = ragnarok::debug_logger_add();
 
@@ -34,7 +42,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     } else {
         ragnarok::DeviceCreateFlags::empty()
     };
-    let device = ragnarok::Device::new(device_flags)?;
+    let device = ragnarok::Device::new(device_flags, ragnarok::AdapterSelection::HighPerformance)?;
+    device.set_lost_callback(|reason| {
+        eprintln!("Device lost: {}", ragnarok::HResultDisplay(reason));
+    });
     let queue = device.create_queue(ragnarok::CmdBufferTy::Direct)?;
     let descriptor_heap = device.create_descriptor_heap(&ragnarok::DescriptorHeapDesc {
         num_views: 1024,
@@ -45,7 +56,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let sample_layout = device.create_pipeline_layout(&[
         // render target
         ragnarok::LayoutDesc::Descriptors(vec![ragnarok::BindingDesc {
-            ty: ragnarok::DescriptorTy::UAV,
+            ty: ragnarok::DescriptorTy::UAV(ragnarok::ViewTy::Texture),
             space: 0,
             bindings: 0..1,
         }]),
@@ -56,11 +67,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             num: (mem::size_of::<Locals>() / 4) as _,
         },
         // path data
-        ragnarok::LayoutDesc::Descriptors(vec![ragnarok::BindingDesc {
-            ty: ragnarok::DescriptorTy::SRV,
-            space: 1,
-            bindings: 0..3,
-        }]),
+        ragnarok::LayoutDesc::Descriptors(vec![
+            ragnarok::BindingDesc {
+                ty: ragnarok::DescriptorTy::SRV(ragnarok::ViewTy::StructuredBuffer),
+                space: 1,
+                bindings: 0..1,
+            },
+            ragnarok::BindingDesc {
+                ty: ragnarok::DescriptorTy::SRV(ragnarok::ViewTy::TypedBuffer),
+                space: 1,
+                bindings: 1..3,
+            },
+        ]),
     ])?;
 
     let sample_cs =
@@ -126,9 +144,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         },
     );
 
-    let svg_path_bez = ragnarok::parse_svg("assets/Ghostscript_Tiger.svg")?;
-    let svg_path = ragnarok::generate_gpu_data(&svg_path_bez);
-    dbg!(&svg_path.objects.len());
+    let aspect_ratio = WIDTH as f32 / HEIGHT as f32;
+    let viewport_extent = [aspect_ratio * TARGET_HEIGHT, TARGET_HEIGHT];
+
+    let svg_curves = ragnarok::parse_svg_curves("assets/Ghostscript_Tiger.svg")?;
+    let svg_lod = ragnarok::PathLod::with_scale_range(
+        svg_curves,
+        ragnarok::DEFAULT_SCREEN_TOLERANCE,
+        0.25,
+        64.0,
+    );
+    let view_scale = ragnarok::view_scale(viewport_extent, [WIDTH, HEIGHT]);
+    let svg_visible = ragnarok::cull_paths(
+        svg_lod.select(view_scale),
+        kurbo::Rect::new(0.0, 0.0, viewport_extent[0] as _, viewport_extent[1] as _),
+        GUARD_BAND,
+        ragnarok::flatten_tolerance(ragnarok::DEFAULT_SCREEN_TOLERANCE, view_scale),
+    );
+    let (svg_path, _) =
+        ragnarok::generate_gpu_data_normalized(&svg_visible, &ragnarok::Normalize::default());
     let svg_objects_cpu = device.create_buffer_committed(
         &ragnarok::BufferDesc {
             size: (svg_path.objects.len() * mem::size_of::<ragnarok::Object>()) as _,
@@ -208,16 +242,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     let upload_buffer = device.create_command_buffer(ragnarok::CmdBufferTy::Direct)?;
-    let upload_fence = device.create_semaphore()?;
-    upload_buffer.begin();
+    upload_buffer.begin()?;
     upload_buffer.copy_buffer(&buffer_cpu, &buffer_gpu);
     upload_buffer.copy_buffer(&svg_objects_cpu, &svg_objects_gpu);
     upload_buffer.copy_buffer(&svg_primitives_cpu, &svg_primitives_gpu);
     upload_buffer.copy_buffer(&svg_data_cpu, &svg_data_gpu);
-    upload_buffer.end();
-    queue.signal(&upload_fence, 1);
-    queue.submit(&[&upload_buffer]);
-    upload_fence.wait(1);
+    upload_buffer.end()?;
+    // Frames are submitted to the same queue after the upload, no need to block here.
+    queue.submit(&[&upload_buffer])?;
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
@@ -225,26 +257,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build(&event_loop)?;
 
     let swapchain = device.create_swapchain(&queue, &window, NUM_FRAMES)?;
-    let present_sync = device.create_semaphore()?;
 
-    let cmd_buffers = (0..NUM_FRAMES)
-        .map(|_| device.create_command_buffer(ragnarok::CmdBufferTy::Direct))
-        .collect::<Result<Box<_>, Error>>()?;
+    // Rasterize into the target image, copy it to the swapchain image and present.
+    let mut frame_graph = ragnarok::RenderGraph::new();
+    let target = frame_graph.import_image("target", 1);
+    let backbuffer = frame_graph.import_image("backbuffer", 1);
+    let raster_pass = frame_graph
+        .add_pass("raster", ragnarok::PassTy::Compute)
+        .write(target, ragnarok::RESOURCE_STATE_UNORDERED_ACCESS)
+        .id();
+    let blit_pass = frame_graph
+        .add_pass("blit", ragnarok::PassTy::Copy)
+        .read(target, ragnarok::RESOURCES_STATE_TRANSFER_SRC)
+        .write(backbuffer, ragnarok::RESOURCES_STATE_TRANSFER_DST)
+        .id();
+    frame_graph
+        .add_pass("present", ragnarok::PassTy::Present)
+        .read(backbuffer, ragnarok::RESOURCE_STATE_PRESENT);
+    let frame_plan = frame_graph.compile();
+    // Transient resources per frame in flight.
+    let mut frames = ragnarok::FrameRing::<Dx12, _>::new(&device, NUM_FRAMES as _, |_| {
+        let mut transient_pool = ragnarok::TransientPool::new();
+        transient_pool.prepare(&device, &frame_plan)?;
+        Ok(transient_pool)
+    })?;
 
-    let timer_queries = (0..NUM_FRAMES)
-        .map(|_| device.create_timer_queries(2))
-        .collect::<Result<Box<_>, Error>>()?;
-    let timer_buffer = device.create_buffer_committed(
-        &ragnarok::BufferDesc {
-            size: NUM_FRAMES * NUM_QUERIES * 8,
-            flags: 0,
-        },
-        ragnarok::HeapType::Readback,
-        ragnarok::RESOURCE_STATE_GENERAL,
-    )?;
+    let cmd_pool = device.create_command_pool(ragnarok::CmdBufferTy::Direct);
 
-    let timer_freq = queue.timing_frequency();
-    let mut tick = 0u64;
+    let profiler = ragnarok::GpuProfiler::new(&device, &queue, NUM_FRAMES as _, NUM_QUERIES)?;
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
@@ -260,93 +300,87 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 window.request_redraw();
             }
             Event::RedrawRequested(window_id) if window_id == window.id() => {
-                if tick >= NUM_FRAMES as u64 {
-                    present_sync.wait(tick - NUM_FRAMES as u64);
-
-                    let mut times = [0u64; NUM_QUERIES as usize];
-                    let times_raw = unsafe {
-                        std::slice::from_raw_parts_mut(times.as_mut_ptr() as *mut u8, times.len() * 8)
-                    };
-                    timer_buffer.copy_to_host(8 * NUM_QUERIES as isize * (tick % NUM_FRAMES as u64) as isize, times_raw);
-                    let dt0 = times[1] - times[0];
+                if let Err(err) = frames.begin_frame() {
+                    eprintln!("{}", err);
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
 
-                    window.set_title(&format!("ragnarok :: dt_0: {:.2}ms", dt0 as f64 / timer_freq as f64 * 1000.0));
+                profiler.begin_frame(frames.slot());
+                if let Some(scope) = ragnarok::ProfileScope::find(&profiler.results(), "frame/raster") {
+                    window.set_title(&format!(
+                        "ragnarok :: raster: {:.2}ms (avg {:.2}ms)",
+                        scope.duration_ms, scope.stats.mean_ms
+                    ));
                 }
 
                 let frame = swapchain.acquire();
                 let frame_image = &swapchain.render_targets()[frame];
-                let cmd_buf = &cmd_buffers[frame];
-                let timer_query = &timer_queries[frame];
-
-                cmd_buf.begin();
-                cmd_buf.resource_barrier(&[
-                    d3d12::ResourceBarrier::transition(
-                        *frame_image.resource(),
-                        0,
-                        ragnarok::RESOURCE_STATE_PRESENT,
-                        ragnarok::RESOURCES_STATE_TRANSFER_DST,
-                        0,
-                    ),
-                    d3d12::ResourceBarrier::transition(
-                        *image_gpu.resource(),
-                        0,
-                        ragnarok::RESOURCES_STATE_TRANSFER_SRC,
-                        ragnarok::RESOURCE_STATE_UNORDERED_ACCESS,
-                        0,
-                    ),
-                ]);
-                cmd_buf.bind_descriptor_heap(&descriptor_heap);
-                cmd_buf.set_compute_root_signature(sample_layout);
-                cmd_buf.set_compute_root_descriptor_table(0, descriptor_pool.view_gpu(1));
-                cmd_buf.set_compute_root_descriptor_table(2, descriptor_pool.view_gpu(2));
-
-                let aspect_ratio = WIDTH as f32 / HEIGHT as f32;
-                let target_height = 200.0;
-                let locals = Locals {
-                    num_tiles: [TILES_X, TILES_Y],
-                    viewport_offset: [0.0, 0.0],
-                    viewport_extent: [aspect_ratio * target_height, target_height],
-                    num_objects: svg_path.objects.len() as _,
+                let cmd_buf = match cmd_pool.acquire() {
+                    Ok(cmd_buf) => cmd_buf,
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
                 };
-                unsafe {
-                    cmd_buf.SetComputeRoot32BitConstants(
-                        1,
-                        (mem::size_of::<Locals>() / 4) as _,
-                        &locals as *const _ as _,
-                        0,
-                    );
-                }
 
-                cmd_buf.set_pipeline_state(sample_pipeline);
-                cmd_buf.timestamp(timer_query, 0);
-                cmd_buf.dispatch([TILES_X, TILES_Y, 1]);
-                cmd_buf.timestamp(timer_query, 1);
-                cmd_buf.resource_barrier(&[d3d12::ResourceBarrier::transition(
-                    *image_gpu.resource(),
-                    0,
-                    ragnarok::RESOURCE_STATE_UNORDERED_ACCESS,
-                    ragnarok::RESOURCES_STATE_TRANSFER_SRC,
-                    0,
-                )]);
-                cmd_buf.copy_image(&image_gpu, frame_image);
-                cmd_buf.resource_barrier(&[d3d12::ResourceBarrier::transition(
-                    *frame_image.resource(),
-                    0,
-                    ragnarok::RESOURCES_STATE_TRANSFER_DST,
-                    ragnarok::RESOURCE_STATE_PRESENT,
-                    0,
-                )]);
-                cmd_buf.copy_timestamps(timer_query, 0..2, &timer_buffer, 8 * NUM_QUERIES * frame as u32);
-                cmd_buf.end();
+                let signal_value = frames.signal_value();
+                let mut frame_resources =
+                    ragnarok::GraphResources::new(&frame_plan, frames.resources());
+                frame_resources.bind_image(target, &image_gpu);
+                frame_resources.bind_image(backbuffer, frame_image);
 
-                queue.submit(&[&cmd_buf]);
-                queue.signal(&present_sync, tick);
-
-                swapchain.present();
-
-                tick += 1;
+                if let Err(err) = cmd_buf.begin() {
+                    eprintln!("{}", err);
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                let frame_scope = profiler.scope(&cmd_buf, "frame");
+                cmd_buf.record_graph(&frame_graph, &frame_resources, |pass, cmd_buf, resources| {
+                    if pass == raster_pass {
+                        cmd_buf.bind_descriptor_heap(&descriptor_heap);
+                        cmd_buf.bind_compute_pipeline(&sample_pipeline, &sample_layout);
+                        cmd_buf.bind_descriptor_table(0, descriptor_pool.view_gpu(1));
+                        cmd_buf.bind_unordered_image(
+                            resources.image(target),
+                            ragnarok::ALL_SUBRESOURCES,
+                        );
+                        cmd_buf.bind_descriptor_table(2, descriptor_pool.view_gpu(2));
+                        cmd_buf.push_constants(
+                            1,
+                            &Locals {
+                                num_tiles: [TILES_X, TILES_Y],
+                                viewport_offset: [0.0, 0.0],
+                                viewport_extent,
+                                num_objects: svg_path.objects.len() as _,
+                            },
+                        );
+                        let _scope = profiler.scope(cmd_buf, "raster");
+                        cmd_buf.dispatch(TILES_X, TILES_Y, 1);
+                    } else if pass == blit_pass {
+                        cmd_buf.copy_image(resources.image(target), resources.image(backbuffer));
+                    }
+                });
+                drop(frame_scope);
+                profiler.end_frame(&cmd_buf);
+                let result =
+                    profiler.frame_submitted(cmd_buf.end().and_then(|_| queue.submit(&[&cmd_buf])));
+                drop(frame_resources);
+                let result = result
+                    .and(frames.end_frame(&queue))
+                    .and_then(|_| swapchain.present());
+                cmd_pool.retire(Some(cmd_buf), frames.semaphore(), signal_value);
+                if let Err(err) = result {
+                    eprintln!("{}", err);
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
             }
             Event::LoopDestroyed => {
+                if let Err(err) = device.wait_idle() {
+                    eprintln!("{}", err);
+                }
                 ragnarok::debug_logger_remove(debug_handler);
             }
             _ => (),
@@ -0,0 +1,225 @@
+//! Renders the sample scene with the Vulkan backend without a window.
+//!
+//! Runs on software implementations, e.g with Mesa lavapipe:
+//!
+//! `VK_ICD_FILENAMES=/usr/share/vulkan/icd.d/lvp_icd.x86_64.json cargo run --example headless --no-default-features --features vulkan`
+//!
+//! The rendered image is written to `headless.ppm`.
+
+use ragnarok::backend::*;
+use ragnarok::{bytemuck, kurbo, BindingDesc, DescriptorTy, Error, LayoutDesc, ViewTy};
+use std::io::Write;
+use std::mem;
+
+const GROUP_X: u32 = 8;
+const GROUP_Y: u32 = 32;
+
+const WIDTH: u32 = GROUP_X * 128;
+const HEIGHT: u32 = GROUP_Y * 32;
+
+const TILES_X: u32 = WIDTH / GROUP_X;
+const TILES_Y: u32 = HEIGHT / GROUP_Y;
+
+const TARGET_HEIGHT: f32 = 200.0;
+const GUARD_BAND: f64 = 64.0;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Locals {
+    num_tiles: [u32; 2],
+    viewport_offset: [f32; 2],
+    viewport_extent: [f32; 2],
+    num_objects: u32,
+}
+
+unsafe impl bytemuck::Zeroable for Locals {}
+unsafe impl bytemuck::Pod for Locals {}
+
+fn upload<T>(
+    device: &VulkanDevice,
+    cmd_buf: &VulkanCommandBuffer,
+    data: &[T],
+) -> Result<(VulkanBuffer, VulkanBuffer), Error> {
+    let data = unsafe { ragnarok::as_u8_slice(data) };
+    let buffer_cpu = device.create_buffer(data.len() as _, HeapType::Upload)?;
+    let buffer_gpu = device.create_buffer(data.len() as _, HeapType::Device)?;
+    buffer_cpu.copy_from_host(0, data);
+    cmd_buf.copy_buffer(&buffer_cpu, &buffer_gpu);
+    Ok((buffer_cpu, buffer_gpu))
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let device = VulkanDevice::new()?;
+    let subgroup_size = device.subgroup_size();
+    println!(
+        "adapter: {} (subgroup size {})",
+        device.adapter_name(),
+        subgroup_size
+    );
+
+    // The wave variant assumes 32 lanes per wave.
+    let entry_point = if subgroup_size == 32 {
+        "test"
+    } else {
+        "test_scalar"
+    };
+    let sample_cs =
+        VulkanShader::new_from_path("sample_cs", "assets/sample.hlsl", entry_point, "cs_6_0")?;
+    let sample_layout = device.create_pipeline_layout(&[
+        LayoutDesc::Descriptors(vec![BindingDesc {
+            ty: DescriptorTy::UAV(ViewTy::Texture),
+            space: 0,
+            bindings: 0..1,
+        }]),
+        LayoutDesc::Constant {
+            space: 1,
+            binding: 0,
+            num: (mem::size_of::<Locals>() / 4) as _,
+        },
+        LayoutDesc::Descriptors(vec![
+            BindingDesc {
+                ty: DescriptorTy::SRV(ViewTy::StructuredBuffer),
+                space: 1,
+                bindings: 0..1,
+            },
+            BindingDesc {
+                ty: DescriptorTy::SRV(ViewTy::TypedBuffer),
+                space: 1,
+                bindings: 1..3,
+            },
+        ]),
+    ])?;
+    let sample_pipeline = device.create_compute_pipeline(&sample_cs, &sample_layout)?;
+
+    let aspect_ratio = WIDTH as f32 / HEIGHT as f32;
+    let viewport_extent = [aspect_ratio * TARGET_HEIGHT, TARGET_HEIGHT];
+
+    let svg_curves = ragnarok::parse_svg_curves("assets/Ghostscript_Tiger.svg")?;
+    let svg_lod = ragnarok::PathLod::with_scale_range(
+        svg_curves,
+        ragnarok::DEFAULT_SCREEN_TOLERANCE,
+        0.25,
+        64.0,
+    );
+    let view_scale = ragnarok::view_scale(viewport_extent, [WIDTH, HEIGHT]);
+    let svg_visible = ragnarok::cull_paths(
+        svg_lod.select(view_scale),
+        kurbo::Rect::new(0.0, 0.0, viewport_extent[0] as _, viewport_extent[1] as _),
+        GUARD_BAND,
+        ragnarok::flatten_tolerance(ragnarok::DEFAULT_SCREEN_TOLERANCE, view_scale),
+    );
+    let (svg_path, _) =
+        ragnarok::generate_gpu_data_normalized(&svg_visible, &ragnarok::Normalize::default());
+    println!("objects: {}", svg_path.objects.len());
+
+    let queue = device.create_queue(QueueTy::Compute)?;
+    let semaphore = device.create_semaphore()?;
+
+    let upload_buffer = device.create_command_buffer(QueueTy::Compute)?;
+    upload_buffer.begin()?;
+    let (_svg_objects_cpu, svg_objects_gpu) = upload(&device, &upload_buffer, &svg_path.objects)?;
+    let (_svg_primitives_cpu, svg_primitives_gpu) =
+        upload(&device, &upload_buffer, &svg_path.primitives)?;
+    let (_svg_data_cpu, svg_data_gpu) = upload(&device, &upload_buffer, &svg_path.data)?;
+    upload_buffer.end()?;
+    queue.submit(&[&upload_buffer])?;
+    queue.signal(&semaphore, 1)?;
+    semaphore.wait(1)?;
+
+    let image_gpu = device.create_image_2d(
+        Extent {
+            width: WIDTH,
+            height: HEIGHT,
+            depth: 1,
+        },
+        TexelFormat::Rgba8Unorm,
+    )?;
+    let image_cpu = device.create_buffer((WIDTH * HEIGHT * 4) as _, HeapType::Readback)?;
+
+    let svg_primitives_view =
+        device.create_buffer_view(&svg_primitives_gpu, TexelFormat::R32Uint)?;
+    let svg_data_view = device.create_buffer_view(&svg_data_gpu, TexelFormat::Rgba32Uint)?;
+
+    let descriptor_pool = device.create_descriptor_pool(
+        2,
+        &[
+            (DescriptorTy::UAV(ViewTy::Texture), 1),
+            (DescriptorTy::SRV(ViewTy::StructuredBuffer), 1),
+            (DescriptorTy::SRV(ViewTy::TypedBuffer), 2),
+        ],
+    )?;
+    let target_table = descriptor_pool.allocate_table(&sample_layout, 0)?;
+    device.write_image(
+        &target_table,
+        0,
+        DescriptorTy::UAV(ViewTy::Texture),
+        &image_gpu,
+    );
+    let svg_table = descriptor_pool.allocate_table(&sample_layout, 2)?;
+    device.write_buffer(
+        &svg_table,
+        0,
+        DescriptorTy::SRV(ViewTy::StructuredBuffer),
+        &svg_objects_gpu,
+    );
+    device.write_texel_buffer(
+        &svg_table,
+        1,
+        DescriptorTy::SRV(ViewTy::TypedBuffer),
+        &svg_primitives_view,
+    );
+    device.write_texel_buffer(
+        &svg_table,
+        2,
+        DescriptorTy::SRV(ViewTy::TypedBuffer),
+        &svg_data_view,
+    );
+
+    let timer_queries = device.create_timer_queries(2)?;
+    let timer_buffer = device.create_buffer(16, HeapType::Readback)?;
+
+    let locals = Locals {
+        num_tiles: [TILES_X, TILES_Y],
+        viewport_offset: [0.0, 0.0],
+        viewport_extent,
+        num_objects: svg_path.objects.len() as _,
+    };
+
+    let cmd_buf = device.create_command_buffer(QueueTy::Compute)?;
+    cmd_buf.begin()?;
+    cmd_buf.bind_compute_pipeline(&sample_pipeline, &sample_layout);
+    cmd_buf.bind_descriptor_table(0, &target_table);
+    cmd_buf.push_constants(1, &locals);
+    cmd_buf.bind_descriptor_table(2, &svg_table);
+    cmd_buf.timestamp(&timer_queries, 0);
+    cmd_buf.dispatch(TILES_X, TILES_Y, 1);
+    cmd_buf.timestamp(&timer_queries, 1);
+    cmd_buf.copy_image_to_buffer(&image_gpu, &image_cpu);
+    cmd_buf.copy_timestamps(&timer_queries, 0..2, &timer_buffer, 0);
+    cmd_buf.end()?;
+    queue.submit(&[&cmd_buf])?;
+    queue.signal(&semaphore, 2)?;
+    semaphore.wait(2)?;
+
+    let mut times = [0u64; 2];
+    timer_buffer.copy_to_host(0, bytemuck::cast_slice_mut(&mut times));
+    // Unwritten queries read as zero, don't underflow.
+    println!(
+        "dt_0: {:.2}ms",
+        times[1].saturating_sub(times[0]) as f64 / queue.timing_frequency() as f64 * 1000.0
+    );
+
+    let mut pixels = vec![0u8; (WIDTH * HEIGHT * 4) as usize];
+    image_cpu.copy_to_host(0, &mut pixels);
+    let covered = pixels.chunks_exact(4).filter(|texel| texel[0] > 0).count();
+    println!("covered pixels: {}/{}", covered, WIDTH * HEIGHT);
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create("headless.ppm")?);
+    write!(file, "P6\n{} {}\n255\n", WIDTH, HEIGHT)?;
+    for texel in pixels.chunks_exact(4) {
+        file.write_all(&texel[..3])?;
+    }
+
+    device.wait_idle()?;
+    Ok(())
+}
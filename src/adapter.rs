@@ -0,0 +1,127 @@
+//! Adapter descriptions and selection policies, independent of the backend.
+
+/// Description of a physical adapter.
+#[derive(Debug, Clone)]
+pub struct AdapterDesc {
+    /// Index in the adapter enumeration order of the system.
+    pub index: u32,
+    pub name: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    /// Dedicated video memory in bytes.
+    pub dedicated_memory: u64,
+    /// Software adapter (e.g WARP).
+    pub software: bool,
+}
+
+/// Policy for choosing an adapter on device creation.
+#[derive(Debug, Clone, Default)]
+pub enum AdapterSelection {
+    /// Hardware adapter with the most dedicated memory.
+    #[default]
+    HighPerformance,
+    /// Hardware adapter with the least dedicated memory, usually an integrated GPU.
+    LowPower,
+    /// Adapter at the given position of `Device::enumerate_adapters`.
+    Index(usize),
+    /// First adapter containing the given name, ignoring case.
+    Name(String),
+}
+
+impl AdapterSelection {
+    /// Select an adapter from the list, returns the position in `adapters`.
+    ///
+    /// Software adapters are only chosen for `HighPerformance` and `LowPower`
+    /// if no hardware adapter is available.
+    pub fn select(&self, adapters: &[AdapterDesc]) -> Option<usize> {
+        // Hardware adapters first, stable on enumeration order.
+        let rank = |desc: &AdapterDesc| desc.software as u8;
+
+        match *self {
+            AdapterSelection::HighPerformance => adapters
+                .iter()
+                .enumerate()
+                .min_by_key(|(i, desc)| (rank(desc), std::cmp::Reverse(desc.dedicated_memory), *i))
+                .map(|(i, _)| i),
+            AdapterSelection::LowPower => adapters
+                .iter()
+                .enumerate()
+                .min_by_key(|(i, desc)| (rank(desc), desc.dedicated_memory, *i))
+                .map(|(i, _)| i),
+            AdapterSelection::Index(index) => {
+                if index < adapters.len() {
+                    Some(index)
+                } else {
+                    None
+                }
+            }
+            AdapterSelection::Name(ref name) => {
+                let name = name.to_lowercase();
+                adapters
+                    .iter()
+                    .position(|desc| desc.name.to_lowercase().contains(&name))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adapter(index: u32, name: &str, dedicated_memory: u64, software: bool) -> AdapterDesc {
+        AdapterDesc {
+            index,
+            name: name.to_string(),
+            vendor_id: 0,
+            device_id: 0,
+            dedicated_memory,
+            software,
+        }
+    }
+
+    fn adapters() -> Vec<AdapterDesc> {
+        vec![
+            adapter(0, "Intel(R) UHD Graphics 630", 128 << 20, false),
+            adapter(1, "Microsoft Basic Render Driver", 0, true),
+            adapter(2, "NVIDIA GeForce RTX 2080", 8 << 30, false),
+            adapter(3, "NVIDIA GeForce RTX 2080", 8 << 30, false),
+        ]
+    }
+
+    #[test]
+    fn high_performance() {
+        assert_eq!(
+            AdapterSelection::HighPerformance.select(&adapters()),
+            Some(2)
+        );
+        assert_eq!(AdapterSelection::default().select(&adapters()), Some(2));
+    }
+
+    #[test]
+    fn low_power() {
+        assert_eq!(AdapterSelection::LowPower.select(&adapters()), Some(0));
+    }
+
+    #[test]
+    fn software_fallback() {
+        let software = vec![adapter(0, "Microsoft Basic Render Driver", 0, true)];
+        assert_eq!(AdapterSelection::HighPerformance.select(&software), Some(0));
+        assert_eq!(AdapterSelection::LowPower.select(&software), Some(0));
+        assert_eq!(AdapterSelection::HighPerformance.select(&[]), None);
+    }
+
+    #[test]
+    fn index() {
+        assert_eq!(AdapterSelection::Index(1).select(&adapters()), Some(1));
+        assert_eq!(AdapterSelection::Index(4).select(&adapters()), None);
+    }
+
+    #[test]
+    fn name() {
+        let selection = AdapterSelection::Name("geforce".to_string());
+        assert_eq!(selection.select(&adapters()), Some(2));
+        let selection = AdapterSelection::Name("Radeon".to_string());
+        assert_eq!(selection.select(&adapters()), None);
+    }
+}
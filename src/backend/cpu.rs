@@ -0,0 +1,375 @@
+//! In-memory backend executing commands on the CPU at submission.
+
+use super::*;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+type Memory = Arc<Mutex<Vec<u8>>>;
+
+pub struct Cpu;
+
+impl Backend for Cpu {
+    type Device = CpuDevice;
+    type Queue = CpuQueue;
+    type CommandBuffer = CpuCommandBuffer;
+    type Buffer = CpuBuffer;
+    type Image = CpuImage;
+    type Semaphore = CpuSemaphore;
+    type TimerQueries = CpuTimerQueries;
+}
+
+pub struct CpuDevice {
+    epoch: Instant,
+}
+
+impl CpuDevice {
+    pub fn new() -> Self {
+        CpuDevice {
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl Default for CpuDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BackendDevice<Cpu> for CpuDevice {
+    fn create_queue(&self, ty: QueueTy) -> Result<CpuQueue, Error> {
+        Ok(CpuQueue {
+            ty,
+            epoch: self.epoch,
+        })
+    }
+
+    fn create_command_buffer(&self, ty: QueueTy) -> Result<CpuCommandBuffer, Error> {
+        Ok(CpuCommandBuffer {
+            ty,
+            commands: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn create_semaphore(&self) -> Result<CpuSemaphore, Error> {
+        Ok(CpuSemaphore {
+            value: Arc::new((Mutex::new(0), Condvar::new())),
+        })
+    }
+
+    fn create_buffer(&self, size: u64, _heap: HeapType) -> Result<CpuBuffer, Error> {
+        Ok(CpuBuffer {
+            memory: Arc::new(Mutex::new(vec![0; size as usize])),
+        })
+    }
+
+    fn create_image_2d(&self, extent: Extent, format: TexelFormat) -> Result<CpuImage, Error> {
+        let size = extent.width * extent.height * extent.depth * format.texel_size();
+        Ok(CpuImage {
+            memory: Arc::new(Mutex::new(vec![0; size as usize])),
+            extent,
+            format,
+        })
+    }
+
+    fn create_timer_queries(&self, num: usize) -> Result<CpuTimerQueries, Error> {
+        Ok(CpuTimerQueries {
+            values: Arc::new(Mutex::new(vec![0; num])),
+        })
+    }
+}
+
+pub struct CpuBuffer {
+    memory: Memory,
+}
+
+impl CpuBuffer {
+    pub fn size(&self) -> usize {
+        self.memory.lock().unwrap().len()
+    }
+}
+
+impl BackendBuffer for CpuBuffer {
+    fn copy_from_host(&self, offset: usize, data: &[u8]) {
+        let mut memory = self.memory.lock().unwrap();
+        memory[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    fn copy_to_host(&self, offset: usize, data: &mut [u8]) {
+        let memory = self.memory.lock().unwrap();
+        data.copy_from_slice(&memory[offset..offset + data.len()]);
+    }
+}
+
+pub struct CpuImage {
+    memory: Memory,
+    extent: Extent,
+    format: TexelFormat,
+}
+
+impl CpuImage {
+    pub fn extent(&self) -> Extent {
+        self.extent
+    }
+
+    pub fn format(&self) -> TexelFormat {
+        self.format
+    }
+
+    /// Read back the tightly packed image content.
+    pub fn copy_to_host(&self, data: &mut [u8]) {
+        data.copy_from_slice(&self.memory.lock().unwrap());
+    }
+
+    /// Write tightly packed image content.
+    pub fn copy_from_host(&self, data: &[u8]) {
+        self.memory.lock().unwrap().copy_from_slice(data);
+    }
+}
+
+pub struct CpuTimerQueries {
+    values: Arc<Mutex<Vec<u64>>>,
+}
+
+pub struct CpuSemaphore {
+    value: Arc<(Mutex<u64>, Condvar)>,
+}
+
+impl CpuSemaphore {
+    // Timeline semaphores never move backwards, smaller values are ignored.
+    fn signal(&self, value: u64) {
+        let (ref lock, ref cond) = *self.value;
+        let mut current = lock.lock().unwrap();
+        *current = (*current).max(value);
+        cond.notify_all();
+    }
+}
+
+impl BackendSemaphore for CpuSemaphore {
+    fn wait(&self, value: u64) -> Result<(), Error> {
+        let (ref lock, ref cond) = *self.value;
+        let mut current = lock.lock().unwrap();
+        while *current < value {
+            current = cond.wait(current).unwrap();
+        }
+        Ok(())
+    }
+
+    fn completed_value(&self) -> u64 {
+        *self.value.0.lock().unwrap()
+    }
+}
+
+enum Command {
+    Copy {
+        src: Memory,
+        dst: Memory,
+    },
+    Timestamp {
+        values: Arc<Mutex<Vec<u64>>>,
+        query: usize,
+    },
+    ResolveTimestamps {
+        values: Arc<Mutex<Vec<u64>>>,
+        range: Range<usize>,
+        dst: Memory,
+        offset: usize,
+    },
+}
+
+pub struct CpuCommandBuffer {
+    ty: QueueTy,
+    commands: Mutex<Vec<Command>>,
+}
+
+impl CpuCommandBuffer {
+    fn record(&self, command: Command) {
+        self.commands.lock().unwrap().push(command);
+    }
+}
+
+impl BackendCommandBuffer<Cpu> for CpuCommandBuffer {
+    fn begin(&self) -> Result<(), Error> {
+        self.commands.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn end(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn copy_buffer(&self, src: &CpuBuffer, dst: &CpuBuffer) {
+        assert!(!Arc::ptr_eq(&src.memory, &dst.memory));
+        assert_eq!(src.size(), dst.size());
+        self.record(Command::Copy {
+            src: src.memory.clone(),
+            dst: dst.memory.clone(),
+        });
+    }
+
+    fn copy_image(&self, src: &CpuImage, dst: &CpuImage) {
+        assert!(!Arc::ptr_eq(&src.memory, &dst.memory));
+        assert_eq!(src.extent, dst.extent);
+        assert_eq!(src.format, dst.format);
+        self.record(Command::Copy {
+            src: src.memory.clone(),
+            dst: dst.memory.clone(),
+        });
+    }
+
+    fn timestamp(&self, queries: &CpuTimerQueries, query: usize) {
+        assert!(query < queries.values.lock().unwrap().len());
+        self.record(Command::Timestamp {
+            values: queries.values.clone(),
+            query,
+        });
+    }
+
+    fn copy_timestamps(
+        &self,
+        queries: &CpuTimerQueries,
+        range: Range<usize>,
+        buffer: &CpuBuffer,
+        buffer_offset: u64,
+    ) {
+        self.record(Command::ResolveTimestamps {
+            values: queries.values.clone(),
+            range,
+            dst: buffer.memory.clone(),
+            offset: buffer_offset as _,
+        });
+    }
+}
+
+pub struct CpuQueue {
+    ty: QueueTy,
+    epoch: Instant,
+}
+
+impl BackendQueue<Cpu> for CpuQueue {
+    fn submit(&self, cmd_buffers: &[&CpuCommandBuffer]) -> Result<(), Error> {
+        for cmd_buffer in cmd_buffers {
+            assert_eq!(cmd_buffer.ty, self.ty);
+            for command in cmd_buffer.commands.lock().unwrap().iter() {
+                match *command {
+                    Command::Copy { ref src, ref dst } => {
+                        let src = src.lock().unwrap();
+                        dst.lock().unwrap().copy_from_slice(&src);
+                    }
+                    Command::Timestamp { ref values, query } => {
+                        values.lock().unwrap()[query] = self.epoch.elapsed().as_nanos() as u64;
+                    }
+                    Command::ResolveTimestamps {
+                        ref values,
+                        ref range,
+                        ref dst,
+                        offset,
+                    } => {
+                        let values = values.lock().unwrap();
+                        let mut dst = dst.lock().unwrap();
+                        for (i, value) in values[range.clone()].iter().enumerate() {
+                            let start = offset + 8 * i;
+                            dst[start..start + 8].copy_from_slice(&value.to_le_bytes());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn signal(&self, semaphore: &CpuSemaphore, value: u64) -> Result<(), Error> {
+        semaphore.signal(value);
+        Ok(())
+    }
+
+    fn timing_frequency(&self) -> u64 {
+        1_000_000_000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records `record` into a new command buffer and waits for its execution.
+    fn execute<B: Backend, F>(device: &B::Device, record: F)
+    where
+        F: FnOnce(&B::CommandBuffer),
+    {
+        let queue = device.create_queue(QueueTy::Direct).unwrap();
+        let semaphore = device.create_semaphore().unwrap();
+        let cmd_buffer = device.create_command_buffer(QueueTy::Direct).unwrap();
+
+        cmd_buffer.begin().unwrap();
+        record(&cmd_buffer);
+        cmd_buffer.end().unwrap();
+        queue.submit(&[&cmd_buffer]).unwrap();
+        queue.signal(&semaphore, 1).unwrap();
+        semaphore.wait(1).unwrap();
+        assert_eq!(semaphore.completed_value(), 1);
+    }
+
+    fn read_u64<B: Backend>(buffer: &B::Buffer, offset: usize) -> u64 {
+        let mut data = [0; 8];
+        buffer.copy_to_host(offset, &mut data);
+        u64::from_le_bytes(data)
+    }
+
+    fn copy_buffer<B: Backend>(device: &B::Device) {
+        let src = device.create_buffer(16, HeapType::Upload).unwrap();
+        let dst = device.create_buffer(16, HeapType::Device).unwrap();
+        let data = (0..16).collect::<Vec<u8>>();
+        src.copy_from_host(0, &data);
+
+        execute::<B, _>(device, |cmd_buffer| cmd_buffer.copy_buffer(&src, &dst));
+
+        let mut result = [0; 16];
+        dst.copy_to_host(0, &mut result);
+        assert_eq!(&result[..], &data[..]);
+    }
+
+    fn timestamps<B: Backend>(device: &B::Device) {
+        let queries = device.create_timer_queries(2).unwrap();
+        let readback = device.create_buffer(24, HeapType::Readback).unwrap();
+
+        execute::<B, _>(device, |cmd_buffer| {
+            cmd_buffer.timestamp(&queries, 0);
+            cmd_buffer.timestamp(&queries, 1);
+            cmd_buffer.copy_timestamps(&queries, 0..2, &readback, 8);
+        });
+
+        // Resolved at the offset, leaving the preceding bytes untouched.
+        assert_eq!(read_u64::<B>(&readback, 0), 0);
+        let begin = read_u64::<B>(&readback, 8);
+        let end = read_u64::<B>(&readback, 16);
+        assert!(begin <= end);
+    }
+
+    #[test]
+    fn cpu_copy_buffer() {
+        copy_buffer::<Cpu>(&CpuDevice::new());
+    }
+
+    #[test]
+    fn cpu_timestamps() {
+        timestamps::<Cpu>(&CpuDevice::new());
+    }
+
+    #[test]
+    fn cpu_timing_frequency() {
+        let queue = CpuDevice::new().create_queue(QueueTy::Compute).unwrap();
+        assert_eq!(queue.timing_frequency(), 1_000_000_000);
+    }
+
+    #[test]
+    fn cpu_semaphore_monotonic() {
+        let device = CpuDevice::new();
+        let queue = device.create_queue(QueueTy::Direct).unwrap();
+        let semaphore = device.create_semaphore().unwrap();
+        queue.signal(&semaphore, 2).unwrap();
+        queue.signal(&semaphore, 1).unwrap();
+        assert_eq!(semaphore.completed_value(), 2);
+        semaphore.wait(2).unwrap();
+    }
+}
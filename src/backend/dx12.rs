@@ -0,0 +1,167 @@
+//! D3D12 implementation of the backend traits on top of the native wrappers.
+//!
+//! Resources are created in the common state and rely on implicit state
+//! promotion for copies.
+
+use super::*;
+use crate::{
+    Buffer, BufferDesc, CmdBufferTy, CommandBuffer, Device, Format, Image, ImageDesc, ImageType,
+    Queue, Semaphore, TimerQueries,
+};
+use winapi::shared::dxgiformat::*;
+use winapi::um::d3d12::{
+    D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS, D3D12_RESOURCE_STATE_COMMON,
+    D3D12_RESOURCE_STATE_COPY_DEST, D3D12_RESOURCE_STATE_GENERIC_READ,
+};
+
+pub struct Dx12;
+
+impl Backend for Dx12 {
+    type Device = Device;
+    type Queue = Queue;
+    type CommandBuffer = CommandBuffer;
+    type Buffer = Buffer;
+    type Image = Image;
+    type Semaphore = Semaphore;
+    type TimerQueries = TimerQueries;
+}
+
+impl QueueTy {
+    pub fn as_d3d12(&self) -> CmdBufferTy {
+        match self {
+            QueueTy::Direct => CmdBufferTy::Direct,
+            QueueTy::Compute => CmdBufferTy::Compute,
+            QueueTy::Copy => CmdBufferTy::Copy,
+        }
+    }
+}
+
+impl TexelFormat {
+    pub fn as_dxgi(&self) -> Format {
+        match self {
+            TexelFormat::Rgba8Unorm => DXGI_FORMAT_R8G8B8A8_UNORM,
+            TexelFormat::R32Uint => DXGI_FORMAT_R32_UINT,
+            TexelFormat::R32Float => DXGI_FORMAT_R32_FLOAT,
+            TexelFormat::Rgba32Uint => DXGI_FORMAT_R32G32B32A32_UINT,
+            TexelFormat::Rgba32Float => DXGI_FORMAT_R32G32B32A32_FLOAT,
+        }
+    }
+}
+
+impl BackendDevice<Dx12> for Device {
+    fn create_queue(&self, ty: QueueTy) -> Result<Queue, Error> {
+        Device::create_queue(self, ty.as_d3d12())
+    }
+
+    fn create_command_buffer(&self, ty: QueueTy) -> Result<CommandBuffer, Error> {
+        Device::create_command_buffer(self, ty.as_d3d12())
+    }
+
+    fn create_semaphore(&self) -> Result<Semaphore, Error> {
+        Device::create_semaphore(self)
+    }
+
+    fn create_buffer(&self, size: u64, heap: HeapType) -> Result<Buffer, Error> {
+        // Upload and readback heaps require fixed resource states.
+        let (flags, initial) = match heap {
+            HeapType::Device => (
+                D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+                D3D12_RESOURCE_STATE_COMMON,
+            ),
+            HeapType::Upload => (0, D3D12_RESOURCE_STATE_GENERIC_READ),
+            HeapType::Readback => (0, D3D12_RESOURCE_STATE_COPY_DEST),
+        };
+        self.create_buffer_committed(
+            &BufferDesc {
+                flags,
+                size: size as _,
+            },
+            heap,
+            initial,
+        )
+    }
+
+    fn create_image_2d(&self, extent: Extent, format: TexelFormat) -> Result<Image, Error> {
+        self.create_image_committed(
+            &ImageDesc {
+                ty: ImageType::D2,
+                flags: D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+                format: format.as_dxgi(),
+                extent,
+                mip_levels: 1,
+            },
+            HeapType::Device,
+            D3D12_RESOURCE_STATE_COMMON,
+        )
+    }
+
+    fn create_timer_queries(&self, num: usize) -> Result<TimerQueries, Error> {
+        Device::create_timer_queries(self, num)
+    }
+}
+
+impl BackendQueue<Dx12> for Queue {
+    fn submit(&self, cmd_buffers: &[&CommandBuffer]) -> Result<(), Error> {
+        Queue::submit(self, cmd_buffers)
+    }
+
+    fn signal(&self, semaphore: &Semaphore, value: u64) -> Result<(), Error> {
+        Queue::signal(self, semaphore, value)
+    }
+
+    fn timing_frequency(&self) -> u64 {
+        Queue::timing_frequency(self)
+    }
+}
+
+impl BackendCommandBuffer<Dx12> for CommandBuffer {
+    fn begin(&self) -> Result<(), Error> {
+        CommandBuffer::begin(self)
+    }
+
+    fn end(&self) -> Result<(), Error> {
+        CommandBuffer::end(self)
+    }
+
+    fn copy_buffer(&self, src: &Buffer, dst: &Buffer) {
+        CommandBuffer::copy_buffer(self, src, dst)
+    }
+
+    fn copy_image(&self, src: &Image, dst: &Image) {
+        CommandBuffer::copy_image(self, src, dst)
+    }
+
+    fn timestamp(&self, queries: &TimerQueries, query: usize) {
+        CommandBuffer::timestamp(self, queries, query)
+    }
+
+    fn copy_timestamps(
+        &self,
+        queries: &TimerQueries,
+        range: Range<usize>,
+        buffer: &Buffer,
+        buffer_offset: u64,
+    ) {
+        CommandBuffer::copy_timestamps(self, queries, range, buffer, buffer_offset as _)
+    }
+}
+
+impl BackendBuffer for Buffer {
+    fn copy_from_host(&self, offset: usize, data: &[u8]) {
+        Buffer::copy_from_host(self, offset as _, data)
+    }
+
+    fn copy_to_host(&self, offset: usize, data: &mut [u8]) {
+        Buffer::copy_to_host(self, offset as _, data)
+    }
+}
+
+impl BackendSemaphore for Semaphore {
+    fn wait(&self, value: u64) -> Result<(), Error> {
+        Semaphore::wait(self, value)
+    }
+
+    fn completed_value(&self) -> u64 {
+        self.fence.get_value()
+    }
+}
@@ -0,0 +1,122 @@
+//! Backend abstraction over devices, queues, command buffers and resources.
+//!
+//! Code written against the `Backend` traits runs on the D3D12 implementation
+//! (`Dx12`, requires the `d3d12` feature), the Vulkan implementation (`Vulkan`,
+//! requires the `vulkan` feature) as well as on the `Cpu` backend, which
+//! executes copies, buffer mapping and timestamp writes in memory.
+
+mod cpu;
+#[cfg(feature = "d3d12")]
+mod dx12;
+#[cfg(feature = "vulkan")]
+mod vulkan;
+
+pub use self::cpu::*;
+#[cfg(feature = "d3d12")]
+pub use self::dx12::*;
+#[cfg(feature = "vulkan")]
+pub use self::vulkan::*;
+
+use crate::Error;
+use std::ops::Range;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeapType {
+    Device,
+    Upload,
+    Readback,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Extent {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QueueTy {
+    Direct,
+    Compute,
+    Copy,
+}
+
+/// Texel formats supported by all backends.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TexelFormat {
+    Rgba8Unorm,
+    R32Uint,
+    R32Float,
+    Rgba32Uint,
+    Rgba32Float,
+}
+
+impl TexelFormat {
+    pub fn texel_size(&self) -> u32 {
+        match self {
+            TexelFormat::Rgba8Unorm | TexelFormat::R32Uint | TexelFormat::R32Float => 4,
+            TexelFormat::Rgba32Uint | TexelFormat::Rgba32Float => 16,
+        }
+    }
+}
+
+pub trait Backend: Sized + 'static {
+    type Device: BackendDevice<Self>;
+    type Queue: BackendQueue<Self>;
+    type CommandBuffer: BackendCommandBuffer<Self>;
+    type Buffer: BackendBuffer;
+    type Image;
+    type Semaphore: BackendSemaphore;
+    type TimerQueries;
+}
+
+pub trait BackendDevice<B: Backend> {
+    fn create_queue(&self, ty: QueueTy) -> Result<B::Queue, Error>;
+    fn create_command_buffer(&self, ty: QueueTy) -> Result<B::CommandBuffer, Error>;
+    fn create_semaphore(&self) -> Result<B::Semaphore, Error>;
+
+    /// Create a buffer of `size` bytes, device local buffers allow shader writes.
+    fn create_buffer(&self, size: u64, heap: HeapType) -> Result<B::Buffer, Error>;
+    /// Create a two dimensional, single mip level image in device memory.
+    fn create_image_2d(&self, extent: Extent, format: TexelFormat) -> Result<B::Image, Error>;
+    fn create_timer_queries(&self, num: usize) -> Result<B::TimerQueries, Error>;
+}
+
+pub trait BackendQueue<B: Backend> {
+    fn submit(&self, cmd_buffers: &[&B::CommandBuffer]) -> Result<(), Error>;
+    fn signal(&self, semaphore: &B::Semaphore, value: u64) -> Result<(), Error>;
+    /// Number of timestamp ticks per second.
+    fn timing_frequency(&self) -> u64;
+}
+
+pub trait BackendCommandBuffer<B: Backend> {
+    fn begin(&self) -> Result<(), Error>;
+    fn end(&self) -> Result<(), Error>;
+
+    /// Copy the whole content of `src` to `dst`, both buffers must be of equal size.
+    fn copy_buffer(&self, src: &B::Buffer, dst: &B::Buffer);
+    /// Copy the whole content of `src` to `dst`, both images must have equal extent and format.
+    fn copy_image(&self, src: &B::Image, dst: &B::Image);
+
+    fn timestamp(&self, queries: &B::TimerQueries, query: usize);
+    /// Resolve timestamps as consecutive `u64` values into `buffer`.
+    fn copy_timestamps(
+        &self,
+        queries: &B::TimerQueries,
+        range: Range<usize>,
+        buffer: &B::Buffer,
+        buffer_offset: u64,
+    );
+}
+
+pub trait BackendBuffer {
+    /// Map the buffer and write `data` at `offset`.
+    fn copy_from_host(&self, offset: usize, data: &[u8]);
+    /// Map the buffer and read `data.len()` bytes at `offset`.
+    fn copy_to_host(&self, offset: usize, data: &mut [u8]);
+}
+
+pub trait BackendSemaphore {
+    fn wait(&self, value: u64) -> Result<(), Error>;
+    fn completed_value(&self) -> u64;
+}
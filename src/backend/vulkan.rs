@@ -0,0 +1,1416 @@
+//! Vulkan implementation of the backend traits, requires Vulkan 1.2.
+//!
+//! Shaders are compiled from HLSL to SPIR-V with DXC. Register spaces map to
+//! descriptor sets and register indices to bindings, root constants are
+//! declared as `[[vk::push_constant]]` in the shader.
+//!
+//! All work is submitted to a single queue, resources are allocated with
+//! dedicated memory and images are kept in the general layout. Commands
+//! are separated by full pipeline barriers.
+
+use super::*;
+use crate::{DescriptorTy, HResult, LayoutDesc, ViewTy};
+use ash::version::{DeviceV1_0, DeviceV1_2, EntryV1_0, InstanceV1_0, InstanceV1_1};
+use ash::vk;
+use bytemuck::Pod;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Mutex};
+use std::{fs::File, io::Read, path::Path, ptr};
+
+// `E_NOTIMPL`, reported if no adapter supports the required features.
+const E_NOTIMPL: HResult = 0x8000_4001_u32 as _;
+
+pub struct Vulkan;
+
+impl Backend for Vulkan {
+    type Device = VulkanDevice;
+    type Queue = VulkanQueue;
+    type CommandBuffer = VulkanCommandBuffer;
+    type Buffer = VulkanBuffer;
+    type Image = VulkanImage;
+    type Semaphore = VulkanSemaphore;
+    type TimerQueries = VulkanTimerQueries;
+}
+
+impl From<vk::Result> for Error {
+    fn from(result: vk::Result) -> Self {
+        Error::Vulkan {
+            result: result.as_raw(),
+        }
+    }
+}
+
+impl TexelFormat {
+    pub fn as_vk(&self) -> vk::Format {
+        match self {
+            TexelFormat::Rgba8Unorm => vk::Format::R8G8B8A8_UNORM,
+            TexelFormat::R32Uint => vk::Format::R32_UINT,
+            TexelFormat::R32Float => vk::Format::R32_SFLOAT,
+            TexelFormat::Rgba32Uint => vk::Format::R32G32B32A32_UINT,
+            TexelFormat::Rgba32Float => vk::Format::R32G32B32A32_SFLOAT,
+        }
+    }
+}
+
+/// Instance and device handles shared by all objects created from a `VulkanDevice`.
+struct Shared {
+    _entry: ash::Entry,
+    instance: ash::Instance,
+    device: ash::Device,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    queue_family: u32,
+    // Queue access requires external synchronization.
+    queue: Mutex<vk::Queue>,
+}
+
+impl Shared {
+    fn memory_type(&self, type_bits: u32, heap: HeapType) -> Option<u32> {
+        let (required, preferred) = match heap {
+            HeapType::Device => (
+                vk::MemoryPropertyFlags::empty(),
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ),
+            HeapType::Upload => (
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ),
+            HeapType::Readback => (
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                vk::MemoryPropertyFlags::HOST_CACHED,
+            ),
+        };
+
+        let num_types = self.memory_properties.memory_type_count as usize;
+        let types = &self.memory_properties.memory_types[..num_types];
+        let supported = |i: usize, flags: vk::MemoryPropertyFlags| {
+            type_bits & (1 << i) != 0 && types[i].property_flags.contains(flags)
+        };
+
+        (0..num_types)
+            .find(|&i| supported(i, required | preferred))
+            .or_else(|| (0..num_types).find(|&i| supported(i, required)))
+            .map(|i| i as u32)
+    }
+
+    fn allocate(
+        &self,
+        requirements: vk::MemoryRequirements,
+        heap: HeapType,
+    ) -> Result<vk::DeviceMemory, Error> {
+        let memory_type = self
+            .memory_type(requirements.memory_type_bits, heap)
+            .ok_or(Error::Vulkan {
+                result: vk::Result::ERROR_OUT_OF_DEVICE_MEMORY.as_raw(),
+            })?;
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type);
+        Ok(unsafe { self.device.allocate_memory(&info, None)? })
+    }
+
+    // Record and execute commands immediately, blocks until completion.
+    fn immediate<F>(&self, record: F) -> Result<(), Error>
+    where
+        F: FnOnce(vk::CommandBuffer),
+    {
+        unsafe {
+            let pool = self.device.create_command_pool(
+                &vk::CommandPoolCreateInfo::builder()
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                    .queue_family_index(self.queue_family),
+                None,
+            )?;
+            let result = (|| {
+                let cmd_buffer = self.device.allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::builder()
+                        .command_pool(pool)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(1),
+                )?[0];
+                self.device.begin_command_buffer(
+                    cmd_buffer,
+                    &vk::CommandBufferBeginInfo::builder()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )?;
+                record(cmd_buffer);
+                self.device.end_command_buffer(cmd_buffer)?;
+
+                let queue = self.queue.lock().unwrap();
+                let cmd_buffers = [cmd_buffer];
+                let submit = vk::SubmitInfo::builder().command_buffers(&cmd_buffers);
+                self.device
+                    .queue_submit(*queue, &[submit.build()], vk::Fence::null())?;
+                self.device.queue_wait_idle(*queue)
+            })();
+            self.device.destroy_command_pool(pool, None);
+            Ok(result?)
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            self.device.destroy_device(None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+pub struct VulkanDevice {
+    shared: Arc<Shared>,
+    name: String,
+    subgroup_size: u32,
+    timestamp_period: f32,
+}
+
+impl VulkanDevice {
+    /// Create a device on the first Vulkan 1.2 capable adapter, preferring discrete GPUs.
+    ///
+    /// Software implementations like lavapipe are selected if no GPU is available.
+    pub fn new() -> Result<Self, Error> {
+        let entry = ash::Entry::new().map_err(|_| Error::AdapterNotFound)?;
+
+        let app_name = CString::new("ragnarok").unwrap();
+        let app_info = vk::ApplicationInfo::builder()
+            .application_name(&app_name)
+            .engine_name(&app_name)
+            .api_version(vk::make_version(1, 2, 0));
+        let instance = unsafe {
+            entry
+                .create_instance(
+                    &vk::InstanceCreateInfo::builder().application_info(&app_info),
+                    None,
+                )
+                .map_err(|err| match err {
+                    ash::InstanceError::VkError(result) => Error::from(result),
+                    ash::InstanceError::LoadError(_) => Error::AdapterNotFound,
+                })?
+        };
+
+        match unsafe { Self::with_instance(entry, instance.clone()) } {
+            Ok(device) => Ok(device),
+            Err(err) => {
+                unsafe { instance.destroy_instance(None) };
+                Err(err)
+            }
+        }
+    }
+
+    unsafe fn with_instance(entry: ash::Entry, instance: ash::Instance) -> Result<Self, Error> {
+        let rank = |ty| match ty {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+            vk::PhysicalDeviceType::CPU => 3,
+            _ => 4,
+        };
+
+        let mut candidates = Vec::new();
+        let mut missing_features = false;
+        for physical_device in instance.enumerate_physical_devices()? {
+            let properties = instance.get_physical_device_properties(physical_device);
+            if vk::version_minor(properties.api_version) < 2 {
+                continue;
+            }
+            // Storage images are written without a declared format by the shaders.
+            let features = instance.get_physical_device_features(physical_device);
+            if features.shader_storage_image_write_without_format == vk::FALSE {
+                missing_features = true;
+                continue;
+            }
+            let queue_family = instance
+                .get_physical_device_queue_family_properties(physical_device)
+                .iter()
+                .position(|family| family.queue_flags.contains(vk::QueueFlags::COMPUTE));
+            if let Some(queue_family) = queue_family {
+                candidates.push((physical_device, properties, queue_family as u32));
+            }
+        }
+        let (physical_device, properties, queue_family) = candidates
+            .into_iter()
+            .min_by_key(|(_, properties, _)| rank(properties.device_type))
+            .ok_or(if missing_features {
+                Error::Unsupported { hr: E_NOTIMPL }
+            } else {
+                Error::AdapterNotFound
+            })?;
+
+        let mut subgroup = vk::PhysicalDeviceSubgroupProperties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::builder().push_next(&mut subgroup);
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+
+        let priorities = [1.0];
+        let queue_info = vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(queue_family)
+            .queue_priorities(&priorities);
+        let features =
+            vk::PhysicalDeviceFeatures::builder().shader_storage_image_write_without_format(true);
+        let mut features12 = vk::PhysicalDeviceVulkan12Features::builder().timeline_semaphore(true);
+        let device = instance.create_device(
+            physical_device,
+            &vk::DeviceCreateInfo::builder()
+                .queue_create_infos(&[queue_info.build()])
+                .enabled_features(&features)
+                .push_next(&mut features12),
+            None,
+        )?;
+        let queue = device.get_device_queue(queue_family, 0);
+        let memory_properties = instance.get_physical_device_memory_properties(physical_device);
+        let name = CStr::from_ptr(properties.device_name.as_ptr())
+            .to_string_lossy()
+            .into_owned();
+
+        Ok(VulkanDevice {
+            shared: Arc::new(Shared {
+                _entry: entry,
+                instance,
+                device,
+                memory_properties,
+                queue_family,
+                queue: Mutex::new(queue),
+            }),
+            name,
+            subgroup_size: subgroup.subgroup_size,
+            timestamp_period: properties.limits.timestamp_period,
+        })
+    }
+
+    /// Name of the selected adapter.
+    pub fn adapter_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of lanes per subgroup (wave).
+    pub fn subgroup_size(&self) -> u32 {
+        self.subgroup_size
+    }
+
+    pub fn create_pipeline_layout(
+        &self,
+        descs: &[LayoutDesc],
+    ) -> Result<VulkanPipelineLayout, Error> {
+        let device = &self.shared.device;
+
+        let mut set_layouts = Vec::new();
+        let mut push_constant_ranges = Vec::new();
+        let mut parameters = Vec::with_capacity(descs.len());
+        let mut constants_offset = 0;
+
+        let result = (|| {
+            for desc in descs {
+                match desc {
+                    LayoutDesc::Descriptors(ref bindings) => {
+                        let space = bindings.first().map_or(0, |binding| binding.space);
+                        assert!(bindings.iter().all(|binding| binding.space == space));
+
+                        let layout_bindings = bindings
+                            .iter()
+                            .flat_map(|binding| {
+                                binding.bindings.clone().map(move |i| {
+                                    vk::DescriptorSetLayoutBinding::builder()
+                                        .binding(i)
+                                        .descriptor_type(binding.ty.as_vk())
+                                        .descriptor_count(1)
+                                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                                        .build()
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        let set_layout = unsafe {
+                            device.create_descriptor_set_layout(
+                                &vk::DescriptorSetLayoutCreateInfo::builder()
+                                    .bindings(&layout_bindings),
+                                None,
+                            )?
+                        };
+                        set_layouts.push((space, set_layout));
+                        parameters.push(Parameter::Table {
+                            set: space,
+                            layout: set_layout,
+                        });
+                    }
+                    // Push constants don't have a register, space and binding are ignored.
+                    LayoutDesc::Constant { num, .. } => {
+                        let size = 4 * num;
+                        push_constant_ranges.push(
+                            vk::PushConstantRange::builder()
+                                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                                .offset(constants_offset)
+                                .size(size)
+                                .build(),
+                        );
+                        parameters.push(Parameter::Constants {
+                            offset: constants_offset,
+                            size,
+                        });
+                        constants_offset += size;
+                    }
+                }
+            }
+
+            // Descriptor sets are addressed by index, fill unused spaces with empty sets.
+            let num_sets = set_layouts
+                .iter()
+                .map(|(space, _)| space + 1)
+                .max()
+                .unwrap_or(0);
+            let mut layouts = vec![vk::DescriptorSetLayout::null(); num_sets as usize];
+            for (space, set_layout) in &set_layouts {
+                assert_eq!(layouts[*space as usize], vk::DescriptorSetLayout::null());
+                layouts[*space as usize] = *set_layout;
+            }
+            for layout in &mut layouts {
+                if *layout == vk::DescriptorSetLayout::null() {
+                    *layout = unsafe {
+                        device.create_descriptor_set_layout(
+                            &vk::DescriptorSetLayoutCreateInfo::builder(),
+                            None,
+                        )?
+                    };
+                    set_layouts.push((!0, *layout));
+                }
+            }
+
+            let layout = unsafe {
+                device.create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&layouts)
+                        .push_constant_ranges(&push_constant_ranges),
+                    None,
+                )?
+            };
+            Ok(layout)
+        })();
+
+        match result {
+            Ok(layout) => Ok(VulkanPipelineLayout {
+                shared: self.shared.clone(),
+                layout,
+                set_layouts: set_layouts.into_iter().map(|(_, layout)| layout).collect(),
+                parameters: parameters.into(),
+            }),
+            Err(err) => {
+                for (_, set_layout) in set_layouts {
+                    unsafe { device.destroy_descriptor_set_layout(set_layout, None) };
+                }
+                Err(err)
+            }
+        }
+    }
+
+    pub fn create_compute_pipeline(
+        &self,
+        shader: &VulkanShader,
+        layout: &VulkanPipelineLayout,
+    ) -> Result<VulkanPipeline, Error> {
+        let device = &self.shared.device;
+        let code = shader.words();
+        let module = unsafe {
+            device.create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(&code), None)?
+        };
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(&shader.entry_point);
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage.build())
+            .layout(layout.layout);
+        let result = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[info.build()], None)
+        };
+        unsafe {
+            device.destroy_shader_module(module, None);
+        }
+
+        let pipeline = result.map_err(|(_, result)| result)?[0];
+        Ok(VulkanPipeline {
+            shared: self.shared.clone(),
+            pipeline,
+        })
+    }
+
+    /// Create a pool for allocating up to `max_tables` descriptor tables.
+    ///
+    /// `sizes` lists the total number of descriptors per type.
+    pub fn create_descriptor_pool(
+        &self,
+        max_tables: u32,
+        sizes: &[(DescriptorTy, u32)],
+    ) -> Result<VulkanDescriptorPool, Error> {
+        let pool_sizes = sizes
+            .iter()
+            .map(|&(ty, descriptor_count)| vk::DescriptorPoolSize {
+                ty: ty.as_vk(),
+                descriptor_count,
+            })
+            .collect::<Vec<_>>();
+        let pool = unsafe {
+            self.shared.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::builder()
+                    .max_sets(max_tables)
+                    .pool_sizes(&pool_sizes),
+                None,
+            )?
+        };
+
+        Ok(VulkanDescriptorPool {
+            shared: self.shared.clone(),
+            pool,
+        })
+    }
+
+    /// Create a typed view of a buffer, required for `Buffer<T>` and `RWBuffer<T>` bindings.
+    pub fn create_buffer_view(
+        &self,
+        buffer: &VulkanBuffer,
+        format: TexelFormat,
+    ) -> Result<VulkanBufferView, Error> {
+        let view = unsafe {
+            self.shared.device.create_buffer_view(
+                &vk::BufferViewCreateInfo::builder()
+                    .buffer(buffer.buffer)
+                    .format(format.as_vk())
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE),
+                None,
+            )?
+        };
+
+        Ok(VulkanBufferView {
+            shared: self.shared.clone(),
+            view,
+        })
+    }
+
+    /// Write a `StructuredBuffer` or `ConstantBuffer` descriptor.
+    pub fn write_buffer(
+        &self,
+        table: &VulkanDescriptorTable,
+        binding: u32,
+        ty: DescriptorTy,
+        buffer: &VulkanBuffer,
+    ) {
+        let info = [vk::DescriptorBufferInfo {
+            buffer: buffer.buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(table.set)
+            .dst_binding(binding)
+            .descriptor_type(ty.as_vk())
+            .buffer_info(&info);
+        unsafe {
+            self.shared
+                .device
+                .update_descriptor_sets(&[write.build()], &[]);
+        }
+    }
+
+    /// Write a `Buffer<T>` or `RWBuffer<T>` descriptor.
+    pub fn write_texel_buffer(
+        &self,
+        table: &VulkanDescriptorTable,
+        binding: u32,
+        ty: DescriptorTy,
+        view: &VulkanBufferView,
+    ) {
+        let views = [view.view];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(table.set)
+            .dst_binding(binding)
+            .descriptor_type(ty.as_vk())
+            .texel_buffer_view(&views);
+        unsafe {
+            self.shared
+                .device
+                .update_descriptor_sets(&[write.build()], &[]);
+        }
+    }
+
+    /// Write a `Texture2D` or `RWTexture2D` descriptor.
+    pub fn write_image(
+        &self,
+        table: &VulkanDescriptorTable,
+        binding: u32,
+        ty: DescriptorTy,
+        image: &VulkanImage,
+    ) {
+        let info = [vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: image.view,
+            image_layout: vk::ImageLayout::GENERAL,
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(table.set)
+            .dst_binding(binding)
+            .descriptor_type(ty.as_vk())
+            .image_info(&info);
+        unsafe {
+            self.shared
+                .device
+                .update_descriptor_sets(&[write.build()], &[]);
+        }
+    }
+
+    /// Wait until all submitted work has finished.
+    pub fn wait_idle(&self) -> Result<(), Error> {
+        unsafe { Ok(self.shared.device.device_wait_idle()?) }
+    }
+}
+
+impl BackendDevice<Vulkan> for VulkanDevice {
+    fn create_queue(&self, ty: QueueTy) -> Result<VulkanQueue, Error> {
+        Ok(VulkanQueue {
+            shared: self.shared.clone(),
+            ty,
+            timestamp_period: self.timestamp_period,
+        })
+    }
+
+    fn create_command_buffer(&self, ty: QueueTy) -> Result<VulkanCommandBuffer, Error> {
+        let device = &self.shared.device;
+        unsafe {
+            let pool = device.create_command_pool(
+                &vk::CommandPoolCreateInfo::builder()
+                    .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                    .queue_family_index(self.shared.queue_family),
+                None,
+            )?;
+            let cmd_buffer = match device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1),
+            ) {
+                Ok(cmd_buffers) => cmd_buffers[0],
+                Err(err) => {
+                    device.destroy_command_pool(pool, None);
+                    return Err(err.into());
+                }
+            };
+
+            Ok(VulkanCommandBuffer {
+                shared: self.shared.clone(),
+                ty,
+                pool,
+                cmd_buffer,
+                compute_layout: RefCell::new(None),
+            })
+        }
+    }
+
+    fn create_semaphore(&self) -> Result<VulkanSemaphore, Error> {
+        let mut ty_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let semaphore = unsafe {
+            self.shared.device.create_semaphore(
+                &vk::SemaphoreCreateInfo::builder().push_next(&mut ty_info),
+                None,
+            )?
+        };
+
+        Ok(VulkanSemaphore {
+            shared: self.shared.clone(),
+            semaphore,
+        })
+    }
+
+    fn create_buffer(&self, size: u64, heap: HeapType) -> Result<VulkanBuffer, Error> {
+        let device = &self.shared.device;
+        let usage = match heap {
+            HeapType::Device => {
+                vk::BufferUsageFlags::TRANSFER_SRC
+                    | vk::BufferUsageFlags::TRANSFER_DST
+                    | vk::BufferUsageFlags::UNIFORM_BUFFER
+                    | vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER
+                    | vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER
+            }
+            HeapType::Upload => vk::BufferUsageFlags::TRANSFER_SRC,
+            HeapType::Readback => vk::BufferUsageFlags::TRANSFER_DST,
+        };
+
+        unsafe {
+            let buffer = device.create_buffer(
+                &vk::BufferCreateInfo::builder()
+                    .size(size)
+                    .usage(usage)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                None,
+            )?;
+            let requirements = device.get_buffer_memory_requirements(buffer);
+            let memory = match self.shared.allocate(requirements, heap) {
+                Ok(memory) => memory,
+                Err(err) => {
+                    device.destroy_buffer(buffer, None);
+                    return Err(err);
+                }
+            };
+            let mapped = (|| {
+                device.bind_buffer_memory(buffer, memory, 0)?;
+                match heap {
+                    HeapType::Device => Ok(ptr::null_mut()),
+                    HeapType::Upload | HeapType::Readback => {
+                        device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                    }
+                }
+            })();
+            let mapped = match mapped {
+                Ok(mapped) => mapped as *mut u8,
+                Err(err) => {
+                    device.destroy_buffer(buffer, None);
+                    device.free_memory(memory, None);
+                    return Err(err.into());
+                }
+            };
+
+            Ok(VulkanBuffer {
+                shared: self.shared.clone(),
+                buffer,
+                memory,
+                mapped,
+                size,
+            })
+        }
+    }
+
+    fn create_image_2d(&self, extent: Extent, format: TexelFormat) -> Result<VulkanImage, Error> {
+        let device = &self.shared.device;
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        unsafe {
+            let image = device.create_image(
+                &vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(format.as_vk())
+                    .extent(vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(
+                        vk::ImageUsageFlags::TRANSFER_SRC
+                            | vk::ImageUsageFlags::TRANSFER_DST
+                            | vk::ImageUsageFlags::SAMPLED
+                            | vk::ImageUsageFlags::STORAGE,
+                    )
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED),
+                None,
+            )?;
+            let requirements = device.get_image_memory_requirements(image);
+            let memory = match self.shared.allocate(requirements, HeapType::Device) {
+                Ok(memory) => memory,
+                Err(err) => {
+                    device.destroy_image(image, None);
+                    return Err(err);
+                }
+            };
+            let view = (|| {
+                device.bind_image_memory(image, memory, 0)?;
+                device.create_image_view(
+                    &vk::ImageViewCreateInfo::builder()
+                        .image(image)
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(format.as_vk())
+                        .subresource_range(subresource_range),
+                    None,
+                )
+            })();
+            let view = match view {
+                Ok(view) => view,
+                Err(err) => {
+                    device.destroy_image(image, None);
+                    device.free_memory(memory, None);
+                    return Err(err.into());
+                }
+            };
+
+            let image = VulkanImage {
+                shared: self.shared.clone(),
+                image,
+                view,
+                memory,
+                extent,
+                format,
+            };
+
+            // Images stay in the general layout for their whole lifetime.
+            self.shared.immediate(|cmd_buffer| {
+                let barrier = vk::ImageMemoryBarrier::builder()
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::GENERAL)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(image.image)
+                    .subresource_range(subresource_range);
+                device.cmd_pipeline_barrier(
+                    cmd_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier.build()],
+                );
+            })?;
+
+            Ok(image)
+        }
+    }
+
+    fn create_timer_queries(&self, num: usize) -> Result<VulkanTimerQueries, Error> {
+        let pool = unsafe {
+            self.shared.device.create_query_pool(
+                &vk::QueryPoolCreateInfo::builder()
+                    .query_type(vk::QueryType::TIMESTAMP)
+                    .query_count(num as _),
+                None,
+            )?
+        };
+
+        Ok(VulkanTimerQueries {
+            shared: self.shared.clone(),
+            pool,
+            num,
+        })
+    }
+}
+
+/// Compiled SPIR-V shader module.
+pub struct VulkanShader {
+    data: Vec<u8>,
+    entry_point: CString,
+}
+
+impl VulkanShader {
+    /// Compile HLSL source to SPIR-V.
+    ///
+    /// Structured buffers use the D3D memory layout, allowing to share the
+    /// data structures with the D3D12 backend.
+    pub fn new(name: &str, source: &str, entry_point: &str, target: &str) -> Result<Self, Error> {
+        let args = ["-spirv", "-fspv-target-env=vulkan1.1", "-fvk-use-dx-layout"];
+        match hassle_rs::compile_hlsl(name, source, entry_point, target, &args, &[]) {
+            Ok(data) => Ok(VulkanShader {
+                data,
+                entry_point: CString::new(entry_point).unwrap(),
+            }),
+            Err(err) => Err(Error::Shader { cause: err }),
+        }
+    }
+
+    pub fn new_from_path<P: AsRef<Path>>(
+        name: &str,
+        path: P,
+        entry_point: &str,
+        target: &str,
+    ) -> Result<Self, Error> {
+        let mut shader_file = File::open(path)?;
+        let mut shader_source = String::new();
+        shader_file.read_to_string(&mut shader_source)?;
+
+        Self::new(name, &shader_source, entry_point, target)
+    }
+
+    fn words(&self) -> Vec<u32> {
+        self.data
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect()
+    }
+}
+
+impl DescriptorTy {
+    fn as_vk(&self) -> vk::DescriptorType {
+        match self {
+            DescriptorTy::SRV(ViewTy::Texture) => vk::DescriptorType::SAMPLED_IMAGE,
+            DescriptorTy::SRV(ViewTy::TypedBuffer) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            DescriptorTy::SRV(ViewTy::StructuredBuffer) => vk::DescriptorType::STORAGE_BUFFER,
+            DescriptorTy::UAV(ViewTy::Texture) => vk::DescriptorType::STORAGE_IMAGE,
+            DescriptorTy::UAV(ViewTy::TypedBuffer) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            DescriptorTy::UAV(ViewTy::StructuredBuffer) => vk::DescriptorType::STORAGE_BUFFER,
+            DescriptorTy::CBV => vk::DescriptorType::UNIFORM_BUFFER,
+            DescriptorTy::Sampler => vk::DescriptorType::SAMPLER,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Parameter {
+    Table {
+        set: u32,
+        layout: vk::DescriptorSetLayout,
+    },
+    Constants {
+        offset: u32,
+        size: u32,
+    },
+}
+
+pub struct VulkanPipelineLayout {
+    shared: Arc<Shared>,
+    layout: vk::PipelineLayout,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    parameters: Arc<[Parameter]>,
+}
+
+impl Drop for VulkanPipelineLayout {
+    fn drop(&mut self) {
+        unsafe {
+            self.shared
+                .device
+                .destroy_pipeline_layout(self.layout, None);
+            for set_layout in &self.set_layouts {
+                self.shared
+                    .device
+                    .destroy_descriptor_set_layout(*set_layout, None);
+            }
+        }
+    }
+}
+
+pub struct VulkanPipeline {
+    shared: Arc<Shared>,
+    pipeline: vk::Pipeline,
+}
+
+impl Drop for VulkanPipeline {
+    fn drop(&mut self) {
+        unsafe { self.shared.device.destroy_pipeline(self.pipeline, None) }
+    }
+}
+
+pub struct VulkanDescriptorPool {
+    shared: Arc<Shared>,
+    pool: vk::DescriptorPool,
+}
+
+impl VulkanDescriptorPool {
+    /// Allocate a descriptor table for the descriptor parameter `slot` of `layout`.
+    pub fn allocate_table(
+        &self,
+        layout: &VulkanPipelineLayout,
+        slot: u32,
+    ) -> Result<VulkanDescriptorTable, Error> {
+        let set_layout = match layout.parameters[slot as usize] {
+            Parameter::Table { layout, .. } => layout,
+            Parameter::Constants { .. } => panic!("parameter {} isn't a descriptor table", slot),
+        };
+        let set_layouts = [set_layout];
+        let set = unsafe {
+            self.shared.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(self.pool)
+                    .set_layouts(&set_layouts),
+            )?[0]
+        };
+
+        Ok(VulkanDescriptorTable { set })
+    }
+}
+
+impl Drop for VulkanDescriptorPool {
+    fn drop(&mut self) {
+        unsafe { self.shared.device.destroy_descriptor_pool(self.pool, None) }
+    }
+}
+
+/// Descriptor set allocated from a `VulkanDescriptorPool`, freed with the pool.
+#[derive(Debug, Copy, Clone)]
+pub struct VulkanDescriptorTable {
+    set: vk::DescriptorSet,
+}
+
+pub struct VulkanBufferView {
+    shared: Arc<Shared>,
+    view: vk::BufferView,
+}
+
+impl Drop for VulkanBufferView {
+    fn drop(&mut self) {
+        unsafe { self.shared.device.destroy_buffer_view(self.view, None) }
+    }
+}
+
+pub struct VulkanBuffer {
+    shared: Arc<Shared>,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+    size: u64,
+}
+
+unsafe impl Send for VulkanBuffer {}
+unsafe impl Sync for VulkanBuffer {}
+
+impl VulkanBuffer {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn mapped(&self, offset: usize, len: usize) -> *mut u8 {
+        assert!(
+            !self.mapped.is_null(),
+            "device local buffers can't be mapped"
+        );
+        assert!((offset + len) as u64 <= self.size);
+        unsafe { self.mapped.add(offset) }
+    }
+}
+
+impl BackendBuffer for VulkanBuffer {
+    fn copy_from_host(&self, offset: usize, data: &[u8]) {
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.mapped(offset, data.len()), data.len());
+        }
+    }
+
+    fn copy_to_host(&self, offset: usize, data: &mut [u8]) {
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.mapped(offset, data.len()),
+                data.as_mut_ptr(),
+                data.len(),
+            );
+        }
+    }
+}
+
+impl Drop for VulkanBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.shared.device.destroy_buffer(self.buffer, None);
+            self.shared.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+pub struct VulkanImage {
+    shared: Arc<Shared>,
+    image: vk::Image,
+    view: vk::ImageView,
+    memory: vk::DeviceMemory,
+    extent: Extent,
+    format: TexelFormat,
+}
+
+impl VulkanImage {
+    pub fn extent(&self) -> Extent {
+        self.extent
+    }
+
+    pub fn format(&self) -> TexelFormat {
+        self.format
+    }
+}
+
+impl Drop for VulkanImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.shared.device.destroy_image_view(self.view, None);
+            self.shared.device.destroy_image(self.image, None);
+            self.shared.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+pub struct VulkanTimerQueries {
+    shared: Arc<Shared>,
+    pool: vk::QueryPool,
+    num: usize,
+}
+
+impl Drop for VulkanTimerQueries {
+    fn drop(&mut self) {
+        unsafe { self.shared.device.destroy_query_pool(self.pool, None) }
+    }
+}
+
+/// Timeline semaphore.
+pub struct VulkanSemaphore {
+    shared: Arc<Shared>,
+    semaphore: vk::Semaphore,
+}
+
+impl BackendSemaphore for VulkanSemaphore {
+    fn wait(&self, value: u64) -> Result<(), Error> {
+        let semaphores = [self.semaphore];
+        let values = [value];
+        unsafe {
+            self.shared.device.wait_semaphores(
+                &vk::SemaphoreWaitInfo::builder()
+                    .semaphores(&semaphores)
+                    .values(&values),
+                !0,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn completed_value(&self) -> u64 {
+        unsafe {
+            self.shared
+                .device
+                .get_semaphore_counter_value(self.semaphore)
+                .unwrap_or(!0)
+        }
+    }
+}
+
+impl Drop for VulkanSemaphore {
+    fn drop(&mut self) {
+        unsafe { self.shared.device.destroy_semaphore(self.semaphore, None) }
+    }
+}
+
+pub struct VulkanCommandBuffer {
+    shared: Arc<Shared>,
+    ty: QueueTy,
+    pool: vk::CommandPool,
+    cmd_buffer: vk::CommandBuffer,
+    // Layout of the currently bound compute pipeline.
+    compute_layout: RefCell<Option<(vk::PipelineLayout, Arc<[Parameter]>)>>,
+}
+
+impl VulkanCommandBuffer {
+    // Full execution and memory dependency between all previous and following commands.
+    fn barrier(&self, dst_stage: vk::PipelineStageFlags, dst_access: vk::AccessFlags) {
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(dst_access);
+        unsafe {
+            self.shared.device.cmd_pipeline_barrier(
+                self.cmd_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[barrier.build()],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    fn device_barrier(&self) {
+        self.barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+        );
+    }
+
+    /// Bind a compute pipeline together with the layout it has been created with.
+    pub fn bind_compute_pipeline(&self, pipeline: &VulkanPipeline, layout: &VulkanPipelineLayout) {
+        unsafe {
+            self.shared.device.cmd_bind_pipeline(
+                self.cmd_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.pipeline,
+            );
+        }
+        *self.compute_layout.borrow_mut() = Some((layout.layout, layout.parameters.clone()));
+    }
+
+    // Raw layout and parameter `slot` of the bound compute layout.
+    fn compute_parameter(&self, slot: u32) -> (vk::PipelineLayout, Parameter) {
+        let layout = self.compute_layout.borrow();
+        let (layout, parameters) = layout
+            .as_ref()
+            .expect("no compute pipeline bound, call `bind_compute_pipeline` first");
+        match parameters.get(slot as usize) {
+            Some(parameter) => (*layout, *parameter),
+            None => panic!(
+                "parameter {} out of range, layout has {} parameters",
+                slot,
+                parameters.len()
+            ),
+        }
+    }
+
+    /// Bind `table` to the descriptor parameter `slot` of the bound compute layout.
+    pub fn bind_descriptor_table(&self, slot: u32, table: &VulkanDescriptorTable) {
+        let (layout, set) = match self.compute_parameter(slot) {
+            (layout, Parameter::Table { set, .. }) => (layout, set),
+            (_, Parameter::Constants { .. }) => {
+                panic!("parameter {} isn't a descriptor table", slot)
+            }
+        };
+        unsafe {
+            self.shared.device.cmd_bind_descriptor_sets(
+                self.cmd_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                layout,
+                set,
+                &[table.set],
+                &[],
+            );
+        }
+    }
+
+    /// Set the push constants at `slot` of the bound compute layout.
+    ///
+    /// `T` must not exceed the number of 32-bit constants declared in the layout.
+    pub fn push_constants<T: Pod>(&self, slot: u32, constants: &T) {
+        let data = bytemuck::bytes_of(constants);
+        assert_eq!(data.len() % 4, 0, "constants must consist of 32-bit values");
+        let (layout, offset) = match self.compute_parameter(slot) {
+            (layout, Parameter::Constants { offset, size }) => {
+                assert!(
+                    data.len() as u32 <= size,
+                    "{} constants exceed the {} constants of parameter {}",
+                    data.len() / 4,
+                    size / 4,
+                    slot
+                );
+                (layout, offset)
+            }
+            (_, Parameter::Table { .. }) => panic!("parameter {} isn't a constant", slot),
+        };
+        unsafe {
+            self.shared.device.cmd_push_constants(
+                self.cmd_buffer,
+                layout,
+                vk::ShaderStageFlags::COMPUTE,
+                offset,
+                data,
+            );
+        }
+    }
+
+    pub fn dispatch(&self, x: u32, y: u32, z: u32) {
+        assert!(
+            self.compute_layout.borrow().is_some(),
+            "no compute pipeline bound, call `bind_compute_pipeline` first"
+        );
+        self.device_barrier();
+        unsafe {
+            self.shared.device.cmd_dispatch(self.cmd_buffer, x, y, z);
+        }
+    }
+
+    /// Copy a tightly packed buffer into the image.
+    pub fn copy_buffer_to_image(&self, src: &VulkanBuffer, dst: &VulkanImage) {
+        self.device_barrier();
+        unsafe {
+            self.shared.device.cmd_copy_buffer_to_image(
+                self.cmd_buffer,
+                src.buffer,
+                dst.image,
+                vk::ImageLayout::GENERAL,
+                &[image_copy_region(dst.extent)],
+            );
+        }
+    }
+
+    /// Copy the image into a buffer, tightly packed.
+    pub fn copy_image_to_buffer(&self, src: &VulkanImage, dst: &VulkanBuffer) {
+        self.device_barrier();
+        unsafe {
+            self.shared.device.cmd_copy_image_to_buffer(
+                self.cmd_buffer,
+                src.image,
+                vk::ImageLayout::GENERAL,
+                dst.buffer,
+                &[image_copy_region(src.extent)],
+            );
+        }
+    }
+}
+
+fn image_copy_region(extent: Extent) -> vk::BufferImageCopy {
+    vk::BufferImageCopy {
+        buffer_offset: 0,
+        buffer_row_length: 0,
+        buffer_image_height: 0,
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+        image_extent: vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        },
+    }
+}
+
+impl BackendCommandBuffer<Vulkan> for VulkanCommandBuffer {
+    fn begin(&self) -> Result<(), Error> {
+        unsafe {
+            let device = &self.shared.device;
+            device.reset_command_buffer(self.cmd_buffer, vk::CommandBufferResetFlags::empty())?;
+            device.begin_command_buffer(
+                self.cmd_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+        }
+        *self.compute_layout.borrow_mut() = None;
+        Ok(())
+    }
+
+    fn end(&self) -> Result<(), Error> {
+        // Make all writes available for host reads after synchronization.
+        self.barrier(vk::PipelineStageFlags::HOST, vk::AccessFlags::HOST_READ);
+        unsafe {
+            self.shared.device.end_command_buffer(self.cmd_buffer)?;
+        }
+        Ok(())
+    }
+
+    fn copy_buffer(&self, src: &VulkanBuffer, dst: &VulkanBuffer) {
+        assert_eq!(src.size, dst.size);
+        self.device_barrier();
+        unsafe {
+            self.shared.device.cmd_copy_buffer(
+                self.cmd_buffer,
+                src.buffer,
+                dst.buffer,
+                &[vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: 0,
+                    size: src.size,
+                }],
+            );
+        }
+    }
+
+    fn copy_image(&self, src: &VulkanImage, dst: &VulkanImage) {
+        assert_eq!(src.extent, dst.extent);
+        assert_eq!(src.format, dst.format);
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        self.device_barrier();
+        unsafe {
+            self.shared.device.cmd_copy_image(
+                self.cmd_buffer,
+                src.image,
+                vk::ImageLayout::GENERAL,
+                dst.image,
+                vk::ImageLayout::GENERAL,
+                &[vk::ImageCopy {
+                    src_subresource: subresource,
+                    src_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                    dst_subresource: subresource,
+                    dst_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                    extent: vk::Extent3D {
+                        width: src.extent.width,
+                        height: src.extent.height,
+                        depth: 1,
+                    },
+                }],
+            );
+        }
+    }
+
+    fn timestamp(&self, queries: &VulkanTimerQueries, query: usize) {
+        assert!(query < queries.num);
+        unsafe {
+            let device = &self.shared.device;
+            device.cmd_reset_query_pool(self.cmd_buffer, queries.pool, query as _, 1);
+            device.cmd_write_timestamp(
+                self.cmd_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                queries.pool,
+                query as _,
+            );
+        }
+    }
+
+    fn copy_timestamps(
+        &self,
+        queries: &VulkanTimerQueries,
+        range: Range<usize>,
+        buffer: &VulkanBuffer,
+        buffer_offset: u64,
+    ) {
+        self.device_barrier();
+        unsafe {
+            self.shared.device.cmd_copy_query_pool_results(
+                self.cmd_buffer,
+                queries.pool,
+                range.start as _,
+                (range.end - range.start) as _,
+                buffer.buffer,
+                buffer_offset,
+                8,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            );
+        }
+    }
+}
+
+impl Drop for VulkanCommandBuffer {
+    fn drop(&mut self) {
+        unsafe { self.shared.device.destroy_command_pool(self.pool, None) }
+    }
+}
+
+pub struct VulkanQueue {
+    shared: Arc<Shared>,
+    ty: QueueTy,
+    timestamp_period: f32,
+}
+
+impl BackendQueue<Vulkan> for VulkanQueue {
+    fn submit(&self, cmd_buffers: &[&VulkanCommandBuffer]) -> Result<(), Error> {
+        let cmd_buffers = cmd_buffers
+            .iter()
+            .map(|cmd_buffer| {
+                assert_eq!(cmd_buffer.ty, self.ty);
+                cmd_buffer.cmd_buffer
+            })
+            .collect::<Vec<_>>();
+        let submit = vk::SubmitInfo::builder().command_buffers(&cmd_buffers);
+        let queue = self.shared.queue.lock().unwrap();
+        unsafe {
+            self.shared
+                .device
+                .queue_submit(*queue, &[submit.build()], vk::Fence::null())?;
+        }
+        Ok(())
+    }
+
+    fn signal(&self, semaphore: &VulkanSemaphore, value: u64) -> Result<(), Error> {
+        let semaphores = [semaphore.semaphore];
+        let values = [value];
+        let mut timeline =
+            vk::TimelineSemaphoreSubmitInfo::builder().signal_semaphore_values(&values);
+        let submit = vk::SubmitInfo::builder()
+            .signal_semaphores(&semaphores)
+            .push_next(&mut timeline);
+        let queue = self.shared.queue.lock().unwrap();
+        unsafe {
+            self.shared
+                .device
+                .queue_submit(*queue, &[submit.build()], vk::Fence::null())?;
+        }
+        Ok(())
+    }
+
+    fn timing_frequency(&self) -> u64 {
+        (1.0e9 / self.timestamp_period as f64) as u64
+    }
+}
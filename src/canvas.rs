@@ -0,0 +1,418 @@
+//! Immediate mode 2D drawing on top of gpu data.
+
+use crate::{clip_path, generate_gpu_data, is_visible, kurbo, DEFAULT_SCREEN_TOLERANCE};
+use crate::{FillRule, GpuData, Style};
+use kurbo::Shape;
+use std::f64::consts::PI;
+
+// Cubic bezier control point distance for approximating quarter circles.
+const KAPPA: f64 = 0.552_284_749_831;
+
+/// Records drawing commands into flattened, clipped paths.
+///
+/// Transforms and clip rectangles are managed as stacks. Clip rectangles
+/// are axis aligned in target space, rotated clips use their bounding box.
+pub struct Canvas {
+    tolerance: f64,
+    transforms: Vec<kurbo::Affine>,
+    clips: Vec<kurbo::Rect>,
+    paths: Vec<kurbo::BezPath>,
+    styles: Vec<Style>,
+}
+
+impl Canvas {
+    pub fn new() -> Self {
+        Self::with_tolerance(DEFAULT_SCREEN_TOLERANCE)
+    }
+
+    /// Create a canvas flattening curves with the given tolerance in target space.
+    pub fn with_tolerance(tolerance: f64) -> Self {
+        Canvas {
+            tolerance,
+            transforms: vec![kurbo::Affine::default()],
+            clips: Vec::new(),
+            paths: Vec::new(),
+            styles: Vec::new(),
+        }
+    }
+
+    pub fn transform(&self) -> kurbo::Affine {
+        *self.transforms.last().unwrap()
+    }
+
+    /// Push a transform, concatenated with the current one.
+    pub fn push_transform(&mut self, transform: kurbo::Affine) {
+        let transform = self.transform() * transform;
+        self.transforms.push(transform);
+    }
+
+    pub fn pop_transform(&mut self) {
+        assert!(self.transforms.len() > 1, "unbalanced transform stack");
+        self.transforms.pop();
+    }
+
+    /// Push a clip rectangle, intersected with the current clip.
+    pub fn push_clip(&mut self, rect: kurbo::Rect) {
+        let mut clip = (self.transform() * rect.to_path(self.tolerance)).bounding_box();
+        if let Some(parent) = self.clips.last() {
+            clip = clip.intersect(*parent);
+        }
+        self.clips.push(clip);
+    }
+
+    pub fn pop_clip(&mut self) {
+        assert!(!self.clips.is_empty(), "unbalanced clip stack");
+        self.clips.pop();
+    }
+
+    pub fn fill_rect(&mut self, rect: kurbo::Rect, style: &Style) {
+        self.fill_path(&rect.to_path(self.tolerance), style);
+    }
+
+    pub fn fill_rounded_rect(&mut self, rect: kurbo::Rect, radius: f64, style: &Style) {
+        let rect = rect.abs();
+        let r = radius.max(0.0).min(0.5 * rect.width().min(rect.height()));
+        let k = r * KAPPA;
+
+        let mut path = kurbo::BezPath::new();
+        path.move_to((rect.x0 + r, rect.y0));
+        path.line_to((rect.x1 - r, rect.y0));
+        path.curve_to(
+            (rect.x1 - r + k, rect.y0),
+            (rect.x1, rect.y0 + r - k),
+            (rect.x1, rect.y0 + r),
+        );
+        path.line_to((rect.x1, rect.y1 - r));
+        path.curve_to(
+            (rect.x1, rect.y1 - r + k),
+            (rect.x1 - r + k, rect.y1),
+            (rect.x1 - r, rect.y1),
+        );
+        path.line_to((rect.x0 + r, rect.y1));
+        path.curve_to(
+            (rect.x0 + r - k, rect.y1),
+            (rect.x0, rect.y1 - r + k),
+            (rect.x0, rect.y1 - r),
+        );
+        path.line_to((rect.x0, rect.y0 + r));
+        path.curve_to(
+            (rect.x0, rect.y0 + r - k),
+            (rect.x0 + r - k, rect.y0),
+            (rect.x0 + r, rect.y0),
+        );
+        path.close_path();
+
+        self.fill_path(&path, style);
+    }
+
+    pub fn fill_circle(&mut self, center: kurbo::Point, radius: f64, style: &Style) {
+        let circle = kurbo::Circle::new(center, radius);
+        self.fill_path(&circle.to_path(self.tolerance), style);
+    }
+
+    /// Fill a path in the current coordinate space.
+    pub fn fill_path(&mut self, path: &kurbo::BezPath, style: &Style) {
+        let path = self.transform() * path.clone();
+        let path =
+            pathbreaker::break_path(&path, pathbreaker::CubicApprox::Flatten(self.tolerance));
+        self.push_path(path, *style);
+    }
+
+    /// Stroke a path with round joins and caps.
+    ///
+    /// The stroke outline is emitted as union of segment quads and join discs,
+    /// which requires the non-zero fill rule.
+    pub fn stroke_path(&mut self, path: &kurbo::BezPath, width: f64, style: &Style) {
+        let transform = self.transform();
+
+        // Flatten in local space with the tolerance mapped from target space.
+        let [a, b, c, d, _, _] = transform.as_coeffs();
+        let scale = (a * d - b * c).abs().sqrt().max(std::f64::EPSILON);
+        let tolerance = self.tolerance / scale;
+        let path = pathbreaker::break_path(path, pathbreaker::CubicApprox::Flatten(tolerance));
+
+        let half_width = 0.5 * width;
+        let mut outline = kurbo::BezPath::new();
+
+        let mut first = kurbo::Point::ZERO;
+        let mut last = kurbo::Point::ZERO;
+        for elem in &path {
+            match elem {
+                kurbo::PathEl::MoveTo(p) => {
+                    stroke_join(&mut outline, p, half_width, tolerance);
+                    first = p;
+                    last = p;
+                }
+                kurbo::PathEl::LineTo(p) => {
+                    stroke_segment(&mut outline, last, p, half_width);
+                    stroke_join(&mut outline, p, half_width, tolerance);
+                    last = p;
+                }
+                kurbo::PathEl::ClosePath => {
+                    stroke_segment(&mut outline, last, first, half_width);
+                    last = first;
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        let style = Style {
+            fill_rule: FillRule::NonZero,
+            ..*style
+        };
+        self.push_path(transform * outline, style);
+    }
+
+    fn push_path(&mut self, path: kurbo::BezPath, style: Style) {
+        let path = match self.clips.last() {
+            Some(&clip) => {
+                let bbox = path.bounding_box();
+                if !is_visible(bbox, clip) {
+                    return;
+                }
+                if bbox.union(clip) == clip {
+                    path
+                } else {
+                    clip_path(&path, clip, self.tolerance)
+                }
+            }
+            None => path,
+        };
+
+        self.paths.push(path);
+        self.styles.push(style);
+    }
+
+    /// Recorded paths in target space, flattened and clipped.
+    pub fn paths(&self) -> &[kurbo::BezPath] {
+        &self.paths
+    }
+
+    /// Styles of the recorded paths, matched by index.
+    pub fn styles(&self) -> &[Style] {
+        &self.styles
+    }
+
+    /// Generate gpu data for all recorded paths together with their styles.
+    ///
+    /// The style indices of the gpu data refer to the returned styles.
+    pub fn finish(&self) -> (GpuData, Vec<Style>) {
+        (generate_gpu_data(&self.paths), self.styles.clone())
+    }
+
+    /// Clear all recorded paths, keeping the transform and clip stacks.
+    pub fn reset(&mut self) {
+        self.paths.clear();
+        self.styles.clear();
+    }
+}
+
+// Quad covering a single line segment, wound consistently with `stroke_join`.
+fn stroke_segment(
+    outline: &mut kurbo::BezPath,
+    p0: kurbo::Point,
+    p1: kurbo::Point,
+    half_width: f64,
+) {
+    let dir = p1 - p0;
+    let len = dir.hypot();
+    if len == 0.0 {
+        return;
+    }
+    let normal = kurbo::Vec2::new(-dir.y, dir.x) * (half_width / len);
+
+    outline.move_to(p0 - normal);
+    outline.line_to(p1 - normal);
+    outline.line_to(p1 + normal);
+    outline.line_to(p0 + normal);
+    outline.close_path();
+}
+
+// Polygonal disc used for round joins and caps.
+fn stroke_join(outline: &mut kurbo::BezPath, center: kurbo::Point, radius: f64, tolerance: f64) {
+    if radius <= tolerance {
+        return;
+    }
+    let angle = 2.0 * (1.0 - tolerance / radius).acos();
+    let num_segments = ((2.0 * PI / angle).ceil() as usize).max(4);
+
+    outline.move_to(center + kurbo::Vec2::new(radius, 0.0));
+    for i in 1..num_segments {
+        let theta = 2.0 * PI * i as f64 / num_segments as f64;
+        outline.line_to(center + kurbo::Vec2::new(radius * theta.cos(), radius * theta.sin()));
+    }
+    outline.close_path();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(red: f32) -> Style {
+        Style {
+            color: [red, 0.0, 0.0, 1.0],
+            fill_rule: FillRule::EvenOdd,
+        }
+    }
+
+    // Points of each contour, asserting all contours are closed.
+    fn contours(path: &kurbo::BezPath) -> Vec<Vec<kurbo::Point>> {
+        let mut contours = Vec::new();
+        let mut contour = Vec::new();
+        for elem in path {
+            match elem {
+                kurbo::PathEl::MoveTo(p) => {
+                    assert!(contour.is_empty(), "unclosed contour");
+                    contour.push(p);
+                }
+                kurbo::PathEl::LineTo(p) => contour.push(p),
+                kurbo::PathEl::ClosePath => contours.push(std::mem::take(&mut contour)),
+                _ => panic!("unflattened path element {:?}", elem),
+            }
+        }
+        assert!(contour.is_empty(), "unclosed contour");
+        contours
+    }
+
+    fn signed_area(contour: &[kurbo::Point]) -> f64 {
+        let n = contour.len();
+        (0..n)
+            .map(|i| contour[i].to_vec2().cross(contour[(i + 1) % n].to_vec2()))
+            .sum::<f64>()
+            * 0.5
+    }
+
+    fn assert_rect_eq(a: kurbo::Rect, b: kurbo::Rect) {
+        let eps = 1e-6;
+        assert!(
+            (a.x0 - b.x0).abs() < eps
+                && (a.y0 - b.y0).abs() < eps
+                && (a.x1 - b.x1).abs() < eps
+                && (a.y1 - b.y1).abs() < eps,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn transform_stack() {
+        let mut canvas = Canvas::new();
+        let translate = kurbo::Affine::translate((10.0, 0.0));
+        canvas.push_transform(translate);
+        canvas.push_transform(kurbo::Affine::scale(2.0));
+        assert_eq!(canvas.transform(), translate * kurbo::Affine::scale(2.0));
+
+        canvas.pop_transform();
+        assert_eq!(canvas.transform(), translate);
+        canvas.pop_transform();
+        assert_eq!(canvas.transform(), kurbo::Affine::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "unbalanced transform stack")]
+    fn pop_empty_transform() {
+        Canvas::new().pop_transform();
+    }
+
+    #[test]
+    #[should_panic(expected = "unbalanced clip stack")]
+    fn pop_empty_clip() {
+        Canvas::new().pop_clip();
+    }
+
+    #[test]
+    fn clip_intersection() {
+        let mut canvas = Canvas::new();
+        canvas.push_clip(kurbo::Rect::new(0.0, 0.0, 100.0, 100.0));
+        canvas.push_transform(kurbo::Affine::translate((50.0, 50.0)));
+        canvas.push_clip(kurbo::Rect::new(0.0, 0.0, 100.0, 100.0));
+        canvas.pop_transform();
+
+        canvas.fill_rect(kurbo::Rect::new(-200.0, -200.0, 200.0, 200.0), &style(1.0));
+        // Outside of the intersection.
+        canvas.fill_rect(kurbo::Rect::new(10.0, 10.0, 40.0, 40.0), &style(0.5));
+        assert_eq!(canvas.paths().len(), 1);
+        assert_rect_eq(
+            canvas.paths()[0].bounding_box(),
+            kurbo::Rect::new(50.0, 50.0, 100.0, 100.0),
+        );
+
+        // Popping restores the outer clip.
+        canvas.pop_clip();
+        canvas.fill_rect(kurbo::Rect::new(10.0, 10.0, 40.0, 40.0), &style(0.5));
+        assert_eq!(canvas.paths().len(), 2);
+    }
+
+    #[test]
+    fn fill_shapes() {
+        let mut canvas = Canvas::new();
+        canvas.push_transform(kurbo::Affine::translate((10.0, 20.0)));
+        canvas.fill_rect(kurbo::Rect::new(0.0, 0.0, 10.0, 10.0), &style(1.0));
+        canvas.push_transform(kurbo::Affine::scale(2.0));
+        canvas.fill_rounded_rect(kurbo::Rect::new(0.0, 0.0, 10.0, 10.0), 2.0, &style(1.0));
+        canvas.pop_transform();
+        canvas.fill_circle(kurbo::Point::new(5.0, 5.0), 3.0, &style(1.0));
+
+        for path in canvas.paths() {
+            assert_eq!(contours(path).len(), 1);
+        }
+        let bboxes = canvas
+            .paths()
+            .iter()
+            .map(|path| path.bounding_box())
+            .collect::<Vec<_>>();
+        assert_rect_eq(bboxes[0], kurbo::Rect::new(10.0, 20.0, 20.0, 30.0));
+        assert_rect_eq(bboxes[1], kurbo::Rect::new(10.0, 20.0, 30.0, 40.0));
+
+        let center = kurbo::Point::new(15.0, 25.0);
+        for p in &contours(&canvas.paths()[2])[0] {
+            assert!((p.distance(center) - 3.0).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn stroke_winding() {
+        let mut canvas = Canvas::new();
+        canvas.push_transform(kurbo::Affine::translate((5.0, 5.0)));
+        let mut path = kurbo::BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((10.0, 0.0));
+        path.line_to((10.0, 10.0));
+        canvas.stroke_path(&path, 2.0, &style(1.0));
+
+        assert_eq!(canvas.styles()[0].fill_rule, FillRule::NonZero);
+        let contours = contours(&canvas.paths()[0]);
+        // Two segment quads and a join disc at each of the three points.
+        let quads = contours
+            .iter()
+            .filter(|contour| contour.len() == 4)
+            .collect::<Vec<_>>();
+        assert_eq!(quads.len(), 2);
+        assert_eq!(contours.len(), 5);
+        assert_eq!(quads[0][0], kurbo::Point::new(5.0, 4.0));
+
+        let winding = signed_area(&contours[0]).signum();
+        assert!(contours
+            .iter()
+            .all(|contour| signed_area(contour).signum() == winding));
+    }
+
+    #[test]
+    fn finish_styles() {
+        let mut canvas = Canvas::new();
+        canvas.push_clip(kurbo::Rect::new(0.0, 0.0, 100.0, 100.0));
+        canvas.fill_rect(kurbo::Rect::new(0.0, 0.0, 10.0, 10.0), &style(0.25));
+        canvas.fill_rect(kurbo::Rect::new(200.0, 0.0, 210.0, 10.0), &style(0.5));
+        canvas.fill_circle(kurbo::Point::new(50.0, 50.0), 5.0, &style(0.75));
+
+        let (gpu_data, styles) = canvas.finish();
+        assert_eq!(gpu_data.objects.len(), 2);
+        assert_eq!(gpu_data.styles, [0, 1]);
+        assert_eq!(styles, [style(0.25), style(0.75)]);
+
+        canvas.reset();
+        let (gpu_data, styles) = canvas.finish();
+        assert!(gpu_data.objects.is_empty() && styles.is_empty());
+    }
+}
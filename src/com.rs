@@ -0,0 +1,61 @@
+//! Owning COM pointers.
+
+use std::mem;
+use std::ops::Deref;
+use winapi::um::unknwnbase::IUnknown;
+use winapi::Interface;
+
+/// Reference counted COM object, released on drop.
+///
+/// `d3d12::WeakPtr` is `Copy` and doesn't own a reference, copies of it
+/// dangle once the object has been released. Cloning an `Owned` adds a
+/// reference instead.
+pub(crate) struct Owned<T: Interface>(d3d12::WeakPtr<T>);
+
+impl<T: Interface> Owned<T> {
+    /// Take over the reference held by `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid object and the caller must not release
+    /// the taken reference anymore.
+    pub(crate) unsafe fn new(ptr: d3d12::WeakPtr<T>) -> Self {
+        debug_assert!(!ptr.is_null());
+        Owned(ptr)
+    }
+
+    /// Erase the interface type, keeping the reference.
+    pub(crate) fn into_unknown(self) -> Owned<IUnknown> {
+        let ptr = unsafe { d3d12::WeakPtr::from_raw(self.0.as_mut_ptr() as *mut IUnknown) };
+        mem::forget(self);
+        Owned(ptr)
+    }
+}
+
+impl<T: Interface> Clone for Owned<T> {
+    fn clone(&self) -> Self {
+        unsafe {
+            self.0.as_unknown().AddRef();
+        }
+        Owned(self.0)
+    }
+}
+
+impl<T: Interface> Drop for Owned<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.0.destroy();
+        }
+    }
+}
+
+impl<T: Interface> Deref for Owned<T> {
+    type Target = d3d12::WeakPtr<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// Only used for free-threaded D3D12 objects, e.g fences and resources.
+unsafe impl<T: Interface> Send for Owned<T> {}
+unsafe impl<T: Interface> Sync for Owned<T> {}
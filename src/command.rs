@@ -1,35 +1,323 @@
-use crate::{Buffer, DescriptorHeap, Device, Error, Image, TimerQueries};
-use std::ops::Range;
-use winapi::um::d3d12::D3D12_QUERY_TYPE_TIMESTAMP;
+use crate::pipeline::LayoutParameter;
+use crate::resource::Tracking;
+use crate::{
+    check_hresult, reconcile, Barrier, Buffer, CommandTracker, DescriptorHeap, Device, Error,
+    Extent, Footprint, Format, GpuDescriptor, HResult, Image, MarkerColor, MarkerStream, Pipeline,
+    PipelineLayout, ResourceId, ResourceStates, TimerQueries, ALL_SUBRESOURCES,
+    PLACEMENT_ALIGNMENT, ROW_PITCH_ALIGNMENT,
+};
+use bytemuck::Pod;
+use std::{
+    cell::{Ref, RefCell},
+    collections::HashMap,
+    mem,
+    ops::Range,
+    ptr,
+    sync::Arc,
+};
+use winapi::um::d3d12::*;
 
 pub use d3d12::CmdListType as CmdBufferTy;
 
 pub struct CommandBuffer {
+    ty: CmdBufferTy,
     allocator: d3d12::CommandAllocator,
+    pub(crate) cmd_buffer: d3d12::GraphicsCommandList,
+    // Shared command signature for `dispatch_indirect`.
+    pub(crate) dispatch_signature: d3d12::CommandSignature,
+    // Parameters of the currently bound compute layout.
+    compute_layout: RefCell<Option<Arc<[LayoutParameter]>>>,
+    // Unordered access views bound together with the compute pipeline.
+    unordered: RefCell<Vec<(Arc<Tracking>, u32)>>,
+    tracker: RefCell<CommandTracker<ResourceStates>>,
+    resources: RefCell<HashMap<ResourceId, Arc<Tracking>>>,
+    markers: RefCell<MarkerStream>,
+}
+
+/// Queue level resource states after executing a batch of command buffers.
+///
+/// Reconciliation only computes the new states, they are applied with
+/// `commit` once the batch is about to be executed.
+#[derive(Default)]
+pub(crate) struct PendingStates(HashMap<ResourceId, (Arc<Tracking>, Vec<ResourceStates>)>);
+
+impl PendingStates {
+    pub(crate) fn commit(self) {
+        for (_, (tracking, states)) in self.0 {
+            *tracking.states.lock().unwrap() = states;
+        }
+    }
+}
+
+/// Record state tracking barriers into a command list as a single batch.
+pub(crate) fn record_barriers(
     cmd_buffer: d3d12::GraphicsCommandList,
+    barriers: &[Barrier<ResourceStates>],
+    resources: &HashMap<ResourceId, Arc<Tracking>>,
+) {
+    if barriers.is_empty() {
+        return;
+    }
+
+    let raw = barriers
+        .iter()
+        .map(|barrier| unsafe {
+            let mut raw: D3D12_RESOURCE_BARRIER = mem::zeroed();
+            match *barrier {
+                Barrier::Transition {
+                    resource,
+                    subresource,
+                    before,
+                    after,
+                } => {
+                    raw.Type = D3D12_RESOURCE_BARRIER_TYPE_TRANSITION;
+                    *raw.u.Transition_mut() = D3D12_RESOURCE_TRANSITION_BARRIER {
+                        pResource: resources[&resource].resource.as_mut_ptr(),
+                        Subresource: subresource,
+                        StateBefore: before,
+                        StateAfter: after,
+                    };
+                }
+                Barrier::Unordered { resource } => {
+                    raw.Type = D3D12_RESOURCE_BARRIER_TYPE_UAV;
+                    *raw.u.UAV_mut() = D3D12_RESOURCE_UAV_BARRIER {
+                        pResource: resources[&resource].resource.as_mut_ptr(),
+                    };
+                }
+            }
+            raw
+        })
+        .collect::<Vec<_>>();
+    unsafe {
+        cmd_buffer.ResourceBarrier(raw.len() as _, raw.as_ptr());
+    }
+}
+
+// Buffer side of a buffer-image copy.
+fn footprint_location(
+    buffer: &Buffer,
+    footprint: &Footprint,
+    format: Format,
+) -> D3D12_TEXTURE_COPY_LOCATION {
+    assert_eq!(footprint.offset % PLACEMENT_ALIGNMENT, 0);
+    assert_eq!(footprint.row_pitch as u64 % ROW_PITCH_ALIGNMENT, 0);
+    assert!(
+        footprint.end() <= buffer.size(),
+        "footprint range {}..{} exceeds buffer size {}",
+        footprint.offset,
+        footprint.end(),
+        buffer.size()
+    );
+
+    let mut location = D3D12_TEXTURE_COPY_LOCATION {
+        pResource: buffer.0.as_mut_ptr(),
+        Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+        u: unsafe { mem::zeroed() },
+    };
+    unsafe {
+        *location.u.PlacedFootprint_mut() = D3D12_PLACED_SUBRESOURCE_FOOTPRINT {
+            Offset: footprint.offset,
+            Footprint: D3D12_SUBRESOURCE_FOOTPRINT {
+                Format: format,
+                Width: footprint.extent.width,
+                Height: footprint.extent.height,
+                Depth: footprint.extent.depth,
+                RowPitch: footprint.row_pitch,
+            },
+        };
+    }
+    location
+}
+
+// Image side of a buffer-image copy, `offset` and `extent` select the copied box.
+fn subresource_location(
+    image: &Image,
+    subresource: u32,
+    offset: [u32; 3],
+    extent: Extent,
+) -> D3D12_TEXTURE_COPY_LOCATION {
+    let subresource_extent = image.subresource_extent(subresource);
+    assert!(
+        offset[0] + extent.width <= subresource_extent.width
+            && offset[1] + extent.height <= subresource_extent.height
+            && offset[2] + extent.depth <= subresource_extent.depth,
+        "box at {:?} with {:?} exceeds subresource {} of {:?}",
+        offset,
+        extent,
+        subresource,
+        subresource_extent
+    );
+
+    let mut location = D3D12_TEXTURE_COPY_LOCATION {
+        pResource: image.0.as_mut_ptr(),
+        Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+        u: unsafe { mem::zeroed() },
+    };
+    unsafe {
+        *location.u.SubresourceIndex_mut() = subresource;
+    }
+    location
+}
+
+// `WINPIX_EVENT_UNICODE_VERSION`, event data is a null terminated UTF-16 string.
+const PIX_EVENT_UNICODE_VERSION: u32 = 0;
+
+fn pix_string(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain(Some(0)).collect()
+}
+
+/// Ends the region on drop, see `CommandBuffer::scoped_region`.
+pub struct RegionGuard<'a> {
+    cmd_buffer: &'a CommandBuffer,
+}
+
+impl Drop for RegionGuard<'_> {
+    fn drop(&mut self) {
+        self.cmd_buffer.end_region();
+    }
+}
+
+impl std::ops::Deref for RegionGuard<'_> {
+    type Target = CommandBuffer;
+    fn deref(&self) -> &Self::Target {
+        self.cmd_buffer
+    }
 }
 
 impl Device {
+    /// Create a command buffer with its own allocator.
+    ///
+    /// `begin` resets the allocator, the caller has to ensure the GPU finished
+    /// executing the previous recording. `CommandPool` tracks this automatically.
     pub fn create_command_buffer(&self, ty: CmdBufferTy) -> Result<CommandBuffer, Error> {
-        let (allocator, _) = self.create_command_allocator(ty);
-        let (cmd_buffer, _) =
-            self.create_graphics_command_list(ty, allocator, d3d12::PipelineState::null(), 0);
+        CommandBuffer::new(**self, self.dispatch_signature, ty, |hr| self.check(hr))
+    }
+}
+
+// Command lists and allocators can be recorded on any thread. `CommandBuffer`
+// isn't `Sync`, so it's never accessed concurrently.
+unsafe impl Send for CommandBuffer {}
+
+impl CommandBuffer {
+    pub(crate) fn new(
+        device: d3d12::Device,
+        dispatch_signature: d3d12::CommandSignature,
+        ty: CmdBufferTy,
+        check: impl Fn(HResult) -> Result<(), Error>,
+    ) -> Result<Self, Error> {
+        let (allocator, hr) = device.create_command_allocator(ty);
+        check(hr)?;
+        let (cmd_buffer, hr) =
+            device.create_graphics_command_list(ty, allocator, d3d12::PipelineState::null(), 0);
+        if let Err(err) = check(hr) {
+            unsafe {
+                allocator.destroy();
+            }
+            return Err(err);
+        }
         cmd_buffer.close();
         Ok(CommandBuffer {
+            ty,
             allocator,
             cmd_buffer,
+            dispatch_signature,
+            compute_layout: RefCell::new(None),
+            unordered: RefCell::new(Vec::new()),
+            tracker: RefCell::new(CommandTracker::new()),
+            resources: RefCell::new(HashMap::new()),
+            markers: RefCell::new(MarkerStream::new()),
         })
     }
 }
 
 impl CommandBuffer {
-    pub fn begin(&self) {
+    pub fn ty(&self) -> CmdBufferTy {
+        self.ty
+    }
+
+    pub fn begin(&self) -> Result<(), Error> {
         self.allocator.reset();
-        self.cmd_buffer
-            .reset(self.allocator, d3d12::PipelineState::null());
+        check_hresult(
+            self.cmd_buffer
+                .reset(self.allocator, d3d12::PipelineState::null()),
+        )?;
+        *self.compute_layout.borrow_mut() = None;
+        self.unordered.borrow_mut().clear();
+        self.tracker.borrow_mut().reset();
+        self.resources.borrow_mut().clear();
+        self.markers.borrow_mut().clear();
+        Ok(())
+    }
+
+    pub(crate) fn track(&self, tracking: &Arc<Tracking>, subresource: u32, state: ResourceStates) {
+        self.resources
+            .borrow_mut()
+            .entry(tracking.desc.id)
+            .or_insert_with(|| tracking.clone());
+        self.tracker
+            .borrow_mut()
+            .require(&tracking.desc, subresource, state);
+    }
+
+    // Order unordered accesses of a resource tracked before.
+    pub(crate) fn order_unordered(&self, tracking: &Arc<Tracking>) {
+        self.tracker.borrow_mut().order_unordered(tracking.desc.id);
+    }
+
+    // Record all queued transitions as a single barrier batch.
+    pub(crate) fn flush_barriers(&self) {
+        let barriers = self.tracker.borrow_mut().flush();
+        record_barriers(self.cmd_buffer, &barriers, &self.resources.borrow());
+    }
+
+    /// Transition the buffer into `state` for the following commands.
+    ///
+    /// Transitions are batched and recorded before the next copy, dispatch or on `end`.
+    pub fn transition_buffer(&self, buffer: &Buffer, state: ResourceStates) {
+        self.track(&buffer.1, 0, state);
+    }
+
+    /// Transition a subresource (or `ALL_SUBRESOURCES`) of the image into `state`.
+    ///
+    /// Transitions are batched and recorded before the next copy, dispatch or on `end`.
+    pub fn transition_image(&self, image: &Image, subresource: u32, state: ResourceStates) {
+        self.track(&image.1, subresource, state);
+    }
+
+    /// Transitions required by the first resource uses before executing the command buffer.
+    ///
+    /// Reconciles against and updates `pending`, falling back to the queue
+    /// level state for resources not used earlier in the batch.
+    pub(crate) fn reconcile(&self, pending: &mut PendingStates) -> Vec<Barrier<ResourceStates>> {
+        let resources = self.resources.borrow();
+        self.tracker
+            .borrow()
+            .usages()
+            .iter()
+            .flat_map(|usage| {
+                let tracking = &resources[&usage.resource.id];
+                let (_, states) = pending.0.entry(usage.resource.id).or_insert_with(|| {
+                    let states = tracking.states.lock().unwrap().clone();
+                    (tracking.clone(), states)
+                });
+                reconcile(usage, states)
+            })
+            .collect()
+    }
+
+    /// Record reconciliation barriers of this command buffer into another command list.
+    pub(crate) fn record_reconcile(
+        &self,
+        cmd_buffer: d3d12::GraphicsCommandList,
+        barriers: &[Barrier<ResourceStates>],
+    ) {
+        record_barriers(cmd_buffer, barriers, &self.resources.borrow());
     }
 
     pub fn copy_buffer(&self, src: &Buffer, dst: &Buffer) {
+        self.track(&src.1, 0, D3D12_RESOURCE_STATE_COPY_SOURCE);
+        self.track(&dst.1, 0, D3D12_RESOURCE_STATE_COPY_DEST);
+        self.flush_barriers();
         unsafe {
             self.cmd_buffer
                 .CopyResource(dst.0.as_mut_ptr(), src.0.as_mut_ptr());
@@ -37,17 +325,221 @@ impl CommandBuffer {
     }
 
     pub fn copy_image(&self, src: &Image, dst: &Image) {
+        self.track(&src.1, ALL_SUBRESOURCES, D3D12_RESOURCE_STATE_COPY_SOURCE);
+        self.track(&dst.1, ALL_SUBRESOURCES, D3D12_RESOURCE_STATE_COPY_DEST);
+        self.flush_barriers();
         unsafe {
             self.cmd_buffer
                 .CopyResource(dst.0.as_mut_ptr(), src.0.as_mut_ptr());
         }
     }
 
+    /// Copy `size` bytes from `src` at `src_offset` to `dst` at `dst_offset`.
+    pub fn copy_buffer_region(
+        &self,
+        src: &Buffer,
+        src_offset: u64,
+        dst: &Buffer,
+        dst_offset: u64,
+        size: u64,
+    ) {
+        assert!(
+            src_offset + size <= src.size(),
+            "source range {}..{} exceeds buffer size {}",
+            src_offset,
+            src_offset + size,
+            src.size()
+        );
+        assert!(
+            dst_offset + size <= dst.size(),
+            "destination range {}..{} exceeds buffer size {}",
+            dst_offset,
+            dst_offset + size,
+            dst.size()
+        );
+
+        self.track(&src.1, 0, D3D12_RESOURCE_STATE_COPY_SOURCE);
+        self.track(&dst.1, 0, D3D12_RESOURCE_STATE_COPY_DEST);
+        self.flush_barriers();
+        unsafe {
+            self.cmd_buffer.CopyBufferRegion(
+                dst.0.as_mut_ptr(),
+                dst_offset,
+                src.0.as_mut_ptr(),
+                src_offset,
+                size,
+            );
+        }
+    }
+
+    /// Copy texels laid out as `footprint` in `src` into a subresource of `dst` at `offset`.
+    pub fn copy_buffer_to_image(
+        &self,
+        src: &Buffer,
+        footprint: &Footprint,
+        dst: &Image,
+        subresource: u32,
+        offset: [u32; 3],
+    ) {
+        let src_location = footprint_location(src, footprint, dst.format());
+        let dst_location = subresource_location(dst, subresource, offset, footprint.extent);
+
+        self.track(&src.1, 0, D3D12_RESOURCE_STATE_COPY_SOURCE);
+        self.track(&dst.1, subresource, D3D12_RESOURCE_STATE_COPY_DEST);
+        self.flush_barriers();
+        unsafe {
+            self.cmd_buffer.CopyTextureRegion(
+                &dst_location,
+                offset[0],
+                offset[1],
+                offset[2],
+                &src_location,
+                ptr::null(),
+            );
+        }
+    }
+
+    /// Copy the box at `offset` with the extent of `footprint` from a subresource of `src` into `dst`.
+    pub fn copy_image_to_buffer(
+        &self,
+        src: &Image,
+        subresource: u32,
+        offset: [u32; 3],
+        dst: &Buffer,
+        footprint: &Footprint,
+    ) {
+        let src_location = subresource_location(src, subresource, offset, footprint.extent);
+        let dst_location = footprint_location(dst, footprint, src.format());
+        let src_box = D3D12_BOX {
+            left: offset[0],
+            top: offset[1],
+            front: offset[2],
+            right: offset[0] + footprint.extent.width,
+            bottom: offset[1] + footprint.extent.height,
+            back: offset[2] + footprint.extent.depth,
+        };
+
+        self.track(&src.1, subresource, D3D12_RESOURCE_STATE_COPY_SOURCE);
+        self.track(&dst.1, 0, D3D12_RESOURCE_STATE_COPY_DEST);
+        self.flush_barriers();
+        unsafe {
+            self.cmd_buffer
+                .CopyTextureRegion(&dst_location, 0, 0, 0, &src_location, &src_box);
+        }
+    }
+
     pub fn bind_descriptor_heap(&self, heap: &DescriptorHeap) {
         self.cmd_buffer
             .set_descriptor_heaps(&[heap.heap_view, heap.heap_sampler]);
     }
 
+    /// Bind a compute pipeline together with the layout it has been created with.
+    ///
+    /// Unbinds all unordered access resources.
+    pub fn bind_compute_pipeline(&self, pipeline: &Pipeline, layout: &PipelineLayout) {
+        self.cmd_buffer.set_compute_root_signature(layout.signature);
+        self.cmd_buffer.set_pipeline_state(pipeline.0);
+        *self.compute_layout.borrow_mut() = Some(layout.parameters.clone());
+        self.unordered.borrow_mut().clear();
+    }
+
+    /// Declare a buffer accessed through an unordered access view of the bound descriptor tables.
+    ///
+    /// Following dispatches transition the buffer into unordered access state
+    /// and are ordered by UAV barriers, until the next `bind_compute_pipeline`.
+    pub fn bind_unordered_buffer(&self, buffer: &Buffer) {
+        self.unordered.borrow_mut().push((buffer.1.clone(), 0));
+    }
+
+    /// Declare a subresource (or `ALL_SUBRESOURCES`) accessed through an unordered
+    /// access view of the bound descriptor tables, see `bind_unordered_buffer`.
+    pub fn bind_unordered_image(&self, image: &Image, subresource: u32) {
+        self.unordered
+            .borrow_mut()
+            .push((image.1.clone(), subresource));
+    }
+
+    // Root parameter `slot` of the bound compute layout.
+    fn compute_parameter(&self, slot: u32) -> LayoutParameter {
+        let layout = self.compute_layout.borrow();
+        let parameters = layout
+            .as_ref()
+            .expect("no compute pipeline bound, call `bind_compute_pipeline` first");
+        match parameters.get(slot as usize) {
+            Some(parameter) => *parameter,
+            None => panic!(
+                "root parameter {} out of range, layout has {} parameters",
+                slot,
+                parameters.len()
+            ),
+        }
+    }
+
+    /// Set the root constants at `slot` of the bound compute layout.
+    ///
+    /// `T` must not exceed the number of 32-bit constants declared in the layout.
+    pub fn push_constants<T: Pod>(&self, slot: u32, constants: &T) {
+        let size = mem::size_of::<T>();
+        assert_eq!(size % 4, 0, "constants must consist of 32-bit values");
+        match self.compute_parameter(slot) {
+            LayoutParameter::Constants { num } => assert!(
+                size / 4 <= num as usize,
+                "{} constants exceed the {} constants of root parameter {}",
+                size / 4,
+                num,
+                slot
+            ),
+            LayoutParameter::Table => panic!("root parameter {} isn't a constant", slot),
+        }
+
+        unsafe {
+            self.cmd_buffer.SetComputeRoot32BitConstants(
+                slot,
+                (size / 4) as _,
+                bytemuck::bytes_of(constants).as_ptr() as *const _,
+                0,
+            );
+        }
+    }
+
+    /// Bind a descriptor table at `slot` of the bound compute layout.
+    pub fn bind_descriptor_table(&self, slot: u32, table: GpuDescriptor) {
+        match self.compute_parameter(slot) {
+            LayoutParameter::Table => {}
+            LayoutParameter::Constants { .. } => {
+                panic!("root parameter {} isn't a descriptor table", slot)
+            }
+        }
+        self.cmd_buffer
+            .set_compute_root_descriptor_table(slot, table);
+    }
+
+    pub(crate) fn assert_compute_bound(&self) {
+        assert!(
+            self.compute_layout.borrow().is_some(),
+            "no compute pipeline bound, call `bind_compute_pipeline` first"
+        );
+    }
+
+    // Require unordered access for the bound UAVs, consecutive dispatches
+    // accessing the same resource are separated by UAV barriers.
+    pub(crate) fn track_unordered(&self) {
+        for (tracking, subresource) in self.unordered.borrow().iter() {
+            self.track(
+                tracking,
+                *subresource,
+                D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+            );
+        }
+    }
+
+    pub fn dispatch(&self, x: u32, y: u32, z: u32) {
+        self.assert_compute_bound();
+        self.track_unordered();
+        self.flush_barriers();
+        self.cmd_buffer.dispatch([x, y, z]);
+    }
+
     pub fn timestamp(&self, heap: &TimerQueries, query: usize) {
         unsafe {
             self.cmd_buffer
@@ -56,6 +548,8 @@ impl CommandBuffer {
     }
 
     pub fn copy_timestamps(&self, heap: &TimerQueries, queries: Range<usize>, buffer: &Buffer, buffer_offset: u32) {
+        self.track(&buffer.1, 0, D3D12_RESOURCE_STATE_COPY_DEST);
+        self.flush_barriers();
         unsafe {
             self.cmd_buffer.ResolveQueryData(
                 heap.0.as_mut_ptr(),
@@ -68,18 +562,70 @@ impl CommandBuffer {
         }
     }
 
-    pub fn end(&self) {
-        self.cmd_buffer.close();
+    /// Begin a named region, ended by `end_region`.
+    ///
+    /// PIX only receives the name, the color is kept in the marker stream.
+    pub fn begin_region(&self, name: &str, color: MarkerColor) {
+        let data = pix_string(name);
+        unsafe {
+            self.cmd_buffer.BeginEvent(
+                PIX_EVENT_UNICODE_VERSION,
+                data.as_ptr() as *const _,
+                (data.len() * 2) as _,
+            );
+        }
+        self.markers.borrow_mut().begin_region(name, color);
+    }
+
+    pub fn end_region(&self) {
+        self.markers.borrow_mut().end_region();
+        unsafe {
+            self.cmd_buffer.EndEvent();
+        }
+    }
+
+    /// Region ended when the returned guard is dropped.
+    pub fn scoped_region(&self, name: &str, color: MarkerColor) -> RegionGuard<'_> {
+        self.begin_region(name, color);
+        RegionGuard { cmd_buffer: self }
+    }
+
+    pub fn insert_marker(&self, name: &str) {
+        let data = pix_string(name);
+        unsafe {
+            self.cmd_buffer.SetMarker(
+                PIX_EVENT_UNICODE_VERSION,
+                data.as_ptr() as *const _,
+                (data.len() * 2) as _,
+            );
+        }
+        self.markers.borrow_mut().insert_marker(name);
+    }
+
+    /// Markers recorded since `begin`.
+    pub fn markers(&self) -> Ref<'_, MarkerStream> {
+        self.markers.borrow()
+    }
+
+    pub fn end(&self) -> Result<(), Error> {
+        let depth = self.markers.borrow().depth();
+        assert_eq!(depth, 0, "{} regions haven't been ended", depth);
+        self.flush_barriers();
+        check_hresult(self.cmd_buffer.close())
     }
 
     pub(crate) fn command_list(&self) -> d3d12::CommandList {
         self.cmd_buffer.as_list()
     }
-}
 
-impl std::ops::Deref for CommandBuffer {
-    type Target = d3d12::GraphicsCommandList;
-    fn deref(&self) -> &Self::Target {
-        &self.cmd_buffer
+    /// Underlying command list for recording commands not exposed by `CommandBuffer`.
+    ///
+    /// # Safety
+    ///
+    /// Commands recorded on the raw list bypass resource state tracking and
+    /// layout validation. The caller has to keep referenced resources alive
+    /// until execution finished and leave them in the tracked states.
+    pub unsafe fn raw(&self) -> d3d12::GraphicsCommandList {
+        self.cmd_buffer
     }
 }
@@ -0,0 +1,46 @@
+//! D3D12 fences as deletion queue timelines.
+
+use super::*;
+use crate::check_hresult;
+use crate::com::Owned;
+use std::ptr;
+use winapi::um::d3d12::ID3D12Fence;
+use winapi::um::unknwnbase::IUnknown;
+use winapi::Interface;
+
+/// Submission timeline of a D3D12 queue.
+pub(crate) type Dx12Timeline = Timeline<Owned<ID3D12Fence>>;
+
+/// Deletion queue releasing references to D3D12 objects.
+pub(crate) type Dx12DeletionQueue = DeletionQueue<Owned<ID3D12Fence>, Owned<IUnknown>>;
+
+impl TimelineFence for Owned<ID3D12Fence> {
+    fn completed_value(&self) -> u64 {
+        self.get_value()
+    }
+
+    fn wait(&self, value: u64) -> Result<(), Error> {
+        // Without an event `SetEventOnCompletion` blocks until the fence reached the value.
+        check_hresult(self.set_event_on_completion(d3d12::Event(ptr::null_mut()), value))
+    }
+}
+
+impl Dx12Timeline {
+    /// Signal the value of a new submission on `queue`.
+    ///
+    /// Submissions of a queue need to be serialized by the caller.
+    pub(crate) fn signal(&self, queue: d3d12::CommandQueue) -> Result<(), Error> {
+        self.submit(|value| check_hresult(queue.signal(**self.fence(), value)))
+    }
+}
+
+impl Dx12DeletionQueue {
+    /// Release the reference held by `object` once all work submitted so far has been finished.
+    ///
+    /// # Safety
+    ///
+    /// The caller must not use or release `object` anymore.
+    pub(crate) unsafe fn defer_release<T: Interface>(&self, object: d3d12::WeakPtr<T>) {
+        self.defer(Owned::new(object).into_unknown());
+    }
+}
@@ -0,0 +1,359 @@
+//! Deferred destruction of GPU objects.
+//!
+//! Dropping a resource or pipeline hands its reference to the device's
+//! `DeletionQueue` together with the latest submission value of each queue.
+//! The reference is released once all queues passed these values, allowing
+//! objects to be dropped right after submitting the work using them.
+//!
+//! Resources are kept alive by the command buffers using them, other objects
+//! recorded into command buffers which haven't been submitted yet must be
+//! kept alive until submission.
+
+#[cfg(feature = "d3d12")]
+mod dx12;
+
+#[cfg(feature = "d3d12")]
+pub(crate) use self::dx12::*;
+
+use crate::Error;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Fence signaled with increasing values by a queue.
+pub(crate) trait TimelineFence {
+    /// Latest value the GPU reached.
+    fn completed_value(&self) -> u64;
+
+    /// Block until the GPU reached `value`.
+    fn wait(&self, value: u64) -> Result<(), Error>;
+}
+
+/// Submission timeline of a queue, signaled after each submission.
+pub(crate) struct Timeline<F> {
+    fence: F,
+    submitted: AtomicU64,
+}
+
+impl<F: TimelineFence> Timeline<F> {
+    pub(crate) fn new(fence: F) -> Arc<Self> {
+        Arc::new(Timeline {
+            fence,
+            submitted: AtomicU64::new(0),
+        })
+    }
+
+    /// Signal the value of a new submission with `signal`.
+    ///
+    /// The value is only reserved if `signal` succeeded, a failed signal
+    /// would never be reached by the fence. Submissions of a queue need to be
+    /// serialized by the caller.
+    pub(crate) fn submit<S>(&self, signal: S) -> Result<(), Error>
+    where
+        S: FnOnce(u64) -> Result<(), Error>,
+    {
+        let value = self.submitted() + 1;
+        signal(value)?;
+        self.submitted.store(value, Ordering::Release);
+        Ok(())
+    }
+
+    pub(crate) fn fence(&self) -> &F {
+        &self.fence
+    }
+
+    fn submitted(&self) -> u64 {
+        self.submitted.load(Ordering::Acquire)
+    }
+
+    fn is_completed(&self, value: u64) -> bool {
+        // Fences are signaled with `u64::MAX` on device removal.
+        self.fence.completed_value() >= value
+    }
+
+    fn wait(&self, value: u64) -> Result<(), Error> {
+        if self.is_completed(value) {
+            return Ok(());
+        }
+        self.fence.wait(value)
+    }
+}
+
+// Object released on drop once all timelines reached their values.
+struct Deferred<F, T> {
+    object: T,
+    waits: Vec<(Arc<Timeline<F>>, u64)>,
+}
+
+impl<F: TimelineFence, T> Deferred<F, T> {
+    fn is_retired(&self) -> bool {
+        self.waits
+            .iter()
+            .all(|(timeline, value)| timeline.is_completed(*value))
+    }
+}
+
+struct DeletionState<F, T> {
+    timelines: Vec<Weak<Timeline<F>>>,
+    pending: Vec<Deferred<F, T>>,
+}
+
+/// Objects dropped while still in use by the GPU, owned by the device.
+///
+/// Objects of type `T` release their underlying GPU object when dropped.
+pub(crate) struct DeletionQueue<F: TimelineFence, T> {
+    state: Mutex<DeletionState<F, T>>,
+}
+
+impl<F: TimelineFence, T> DeletionQueue<F, T> {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(DeletionQueue {
+            state: Mutex::new(DeletionState {
+                timelines: Vec::new(),
+                pending: Vec::new(),
+            }),
+        })
+    }
+
+    /// Track submissions of a queue.
+    pub(crate) fn register(&self, timeline: &Arc<Timeline<F>>) {
+        self.state
+            .lock()
+            .unwrap()
+            .timelines
+            .push(Arc::downgrade(timeline));
+    }
+
+    /// Release `object` once all work submitted so far has been finished.
+    pub(crate) fn defer(&self, object: T) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .timelines
+            .retain(|timeline| timeline.strong_count() > 0);
+        let waits = state
+            .timelines
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|timeline| {
+                let value = timeline.submitted();
+                (timeline, value)
+            })
+            .filter(|(timeline, value)| !timeline.is_completed(*value))
+            .collect::<Vec<_>>();
+
+        if waits.is_empty() {
+            // Release outside of the lock.
+            drop(state);
+            drop(object);
+        } else {
+            state.pending.push(Deferred { object, waits });
+        }
+    }
+
+    /// Release all objects the GPU finished with, returns the number of objects still pending.
+    pub(crate) fn flush(&self) -> usize {
+        let retired = {
+            let mut state = self.state.lock().unwrap();
+            let (retired, pending): (Vec<_>, Vec<_>) = mem::take(&mut state.pending)
+                .into_iter()
+                .partition(Deferred::is_retired);
+            state.pending = pending;
+            retired
+        };
+        drop(retired);
+        self.state.lock().unwrap().pending.len()
+    }
+
+    /// Wait until all queues finished their submissions and release all pending objects.
+    pub(crate) fn wait_idle(&self) -> Result<(), Error> {
+        let waits = {
+            let state = self.state.lock().unwrap();
+            state
+                .timelines
+                .iter()
+                .filter_map(Weak::upgrade)
+                .map(|timeline| {
+                    let value = timeline.submitted();
+                    (timeline, value)
+                })
+                .chain(
+                    state
+                        .pending
+                        .iter()
+                        .flat_map(|deferred| deferred.waits.iter().cloned()),
+                )
+                .collect::<Vec<_>>()
+        };
+
+        for (timeline, value) in waits {
+            timeline.wait(value)?;
+        }
+        self.flush();
+        Ok(())
+    }
+}
+
+impl<F: TimelineFence, T> Drop for DeletionQueue<F, T> {
+    fn drop(&mut self) {
+        let pending = mem::take(&mut self.state.get_mut().unwrap().pending);
+        for deferred in pending {
+            let finished = deferred
+                .waits
+                .iter()
+                .all(|(timeline, value)| timeline.wait(*value).is_ok());
+            // Leak the remaining objects if we can't wait for the GPU.
+            if !finished {
+                mem::forget(deferred.object);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fence completed by the test, waiting completes it immediately.
+    #[derive(Default)]
+    struct TestFence(AtomicU64);
+
+    impl TimelineFence for TestFence {
+        fn completed_value(&self) -> u64 {
+            self.0.load(Ordering::Acquire)
+        }
+
+        fn wait(&self, value: u64) -> Result<(), Error> {
+            self.0.fetch_max(value, Ordering::AcqRel);
+            Ok(())
+        }
+    }
+
+    // Records its id into the released list on drop.
+    struct Object(u32, Arc<Mutex<Vec<u32>>>);
+
+    impl Drop for Object {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    fn timeline() -> Arc<Timeline<TestFence>> {
+        Timeline::new(TestFence::default())
+    }
+
+    fn submit(timeline: &Timeline<TestFence>) {
+        timeline.submit(|_| Ok(())).unwrap();
+    }
+
+    fn complete(timeline: &Timeline<TestFence>, value: u64) {
+        timeline.fence().0.store(value, Ordering::Release);
+    }
+
+    #[test]
+    fn capture_submitted_values() {
+        let released = Arc::new(Mutex::new(Vec::new()));
+        let queue = DeletionQueue::new();
+        let (a, b) = (timeline(), timeline());
+        queue.register(&a);
+        queue.register(&b);
+
+        submit(&a);
+        submit(&a);
+        submit(&b);
+        queue.defer(Object(0, released.clone()));
+
+        // Later submissions don't delay objects deferred before.
+        submit(&a);
+        complete(&a, 2);
+        assert_eq!(queue.flush(), 1);
+        complete(&b, 1);
+        assert_eq!(queue.flush(), 0);
+        assert_eq!(*released.lock().unwrap(), [0]);
+
+        queue.defer(Object(1, released.clone()));
+        assert_eq!(queue.flush(), 1);
+        complete(&a, 3);
+        assert_eq!(queue.flush(), 0);
+        assert_eq!(*released.lock().unwrap(), [0, 1]);
+    }
+
+    #[test]
+    fn release_idle() {
+        let released = Arc::new(Mutex::new(Vec::new()));
+        let queue = DeletionQueue::new();
+        let a = timeline();
+        queue.register(&a);
+
+        // Nothing in flight, released right away.
+        queue.defer(Object(0, released.clone()));
+        submit(&a);
+        complete(&a, 1);
+        queue.defer(Object(1, released.clone()));
+        assert_eq!(*released.lock().unwrap(), [0, 1]);
+    }
+
+    #[test]
+    fn dropped_timelines() {
+        let released = Arc::new(Mutex::new(Vec::new()));
+        let queue = DeletionQueue::new();
+        let (a, b) = (timeline(), timeline());
+        queue.register(&a);
+        queue.register(&b);
+
+        // Timelines of dropped queues are ignored for objects deferred afterwards.
+        submit(&b);
+        drop(b);
+        queue.defer(Object(0, released.clone()));
+        assert_eq!(*released.lock().unwrap(), [0]);
+
+        // Pending objects keep the timelines they wait on alive.
+        submit(&a);
+        queue.defer(Object(1, released.clone()));
+        drop(a);
+        assert_eq!(queue.flush(), 1);
+        assert_eq!(*released.lock().unwrap(), [0]);
+    }
+
+    #[test]
+    fn wait_idle() {
+        let released = Arc::new(Mutex::new(Vec::new()));
+        let queue = DeletionQueue::new();
+        let a = timeline();
+        queue.register(&a);
+
+        submit(&a);
+        queue.defer(Object(0, released.clone()));
+        submit(&a);
+        queue.wait_idle().unwrap();
+        assert_eq!(a.fence().completed_value(), 2);
+        assert_eq!(*released.lock().unwrap(), [0]);
+
+        // Pending objects are released when the queue is dropped.
+        submit(&a);
+        queue.defer(Object(1, released.clone()));
+        drop(queue);
+        assert_eq!(*released.lock().unwrap(), [0, 1]);
+    }
+
+    #[test]
+    fn failed_signal() {
+        let released = Arc::new(Mutex::new(Vec::new()));
+        let queue = DeletionQueue::new();
+        let a = timeline();
+        queue.register(&a);
+
+        // Values of failed signals are never waited on.
+        let err = a.submit(|_| Err(Error::InvalidArgument { hr: 0 }));
+        assert!(err.is_err());
+        queue.defer(Object(0, released.clone()));
+        assert_eq!(*released.lock().unwrap(), [0]);
+
+        let mut signaled = 0;
+        a.submit(|value| {
+            signaled = value;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(signaled, 1);
+    }
+}
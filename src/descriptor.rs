@@ -115,20 +115,27 @@ impl Device {
         &self,
         desc: &DescriptorHeapDesc,
     ) -> Result<DescriptorHeap, Error> {
-        let (heap_view, _) = d3d12::Device::create_descriptor_heap(
+        let (heap_view, hr) = d3d12::Device::create_descriptor_heap(
             self,
             desc.num_views as _,
             d3d12::DescriptorHeapType::CbvSrvUav,
             d3d12::DescriptorHeapFlags::SHADER_VISIBLE,
             0,
         );
-        let (heap_sampler, _) = d3d12::Device::create_descriptor_heap(
+        self.check(hr)?;
+        let (heap_sampler, hr) = d3d12::Device::create_descriptor_heap(
             self,
             desc.num_samplers as _,
             d3d12::DescriptorHeapType::Sampler,
             d3d12::DescriptorHeapFlags::SHADER_VISIBLE,
             0,
         );
+        if let Err(err) = self.check(hr) {
+            unsafe {
+                heap_view.destroy();
+            }
+            return Err(err);
+        }
         let increment_view =
             self.get_descriptor_increment_size(d3d12::DescriptorHeapType::CbvSrvUav);
         let increment_sampler =
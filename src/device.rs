@@ -1,5 +1,17 @@
-use crate::{CmdBufferTy, Error, Pipeline, PipelineLayout, Queue, Semaphore, Shader};
-use winapi::shared::winerror;
+use crate::com::Owned;
+use crate::deletion::{Dx12DeletionQueue, Dx12Timeline};
+use crate::indirect::create_signature;
+use crate::lost::DeviceState;
+use crate::queue::Fixups;
+use crate::{
+    check_hresult, AdapterDesc, AdapterSelection, CmdBufferTy, Error, HResult, IndirectTy,
+    Pipeline, PipelineLayout, Queue, Semaphore, Shader,
+};
+use std::{
+    io, mem,
+    sync::{Arc, Mutex},
+};
+use winapi::shared::{dxgi, winerror};
 
 pub use d3d12::FactoryCreationFlags as DeviceCreateFlags;
 
@@ -12,25 +24,103 @@ type D3DDevice = d3d12::Device;
 pub struct Device {
     pub(crate) factory: Factory,
     device: D3DDevice,
+    adapter: AdapterDesc,
+    pub(crate) dispatch_signature: d3d12::CommandSignature,
+    pub(crate) state: Arc<DeviceState>,
+    pub(crate) deletion: Arc<Dx12DeletionQueue>,
 }
 
 impl Device {
-    pub fn new(flags: DeviceCreateFlags) -> Result<Self, Error> {
+    pub fn new(flags: DeviceCreateFlags, selection: AdapterSelection) -> Result<Self, Error> {
         if flags.contains(DeviceCreateFlags::DEBUG) {
-            let (debug, _) = d3d12::Debug::get_interface();
+            let (debug, hr) = d3d12::Debug::get_interface();
+            check_hresult(hr)?;
             debug.enable_layer();
         }
 
-        let (factory, _) = d3d12::Factory4::create(flags);
+        let (factory, hr) = d3d12::Factory4::create(flags);
+        check_hresult(hr)?;
 
         // Find suitable adapter and open device.
-        let adapter = Self::select_adapter(&factory);
-        let (device, _) = D3DDevice::create(adapter, FEATURE_LEVEL);
+        let mut adapters = Self::collect_adapters(&factory);
+        let descs = adapters
+            .iter()
+            .map(|(_, desc)| desc.clone())
+            .collect::<Vec<_>>();
+        let selected = selection.select(&descs);
 
-        Ok(Device { factory, device })
+        for (i, (adapter, _)) in adapters.iter().enumerate() {
+            if Some(i) != selected {
+                unsafe {
+                    adapter.destroy();
+                }
+            }
+        }
+
+        let (adapter, adapter_desc) = match selected {
+            Some(i) => adapters.swap_remove(i),
+            None => {
+                unsafe {
+                    factory.destroy();
+                }
+                return Err(Error::AdapterNotFound);
+            }
+        };
+        let (device, hr) = D3DDevice::create(adapter, FEATURE_LEVEL);
+        if let Err(err) = check_hresult(hr) {
+            unsafe {
+                adapter.destroy();
+                factory.destroy();
+            }
+            return Err(err);
+        }
+        let (dispatch_signature, hr) = create_signature(device, IndirectTy::Dispatch);
+        if let Err(err) = check_hresult(hr) {
+            unsafe {
+                device.destroy();
+                adapter.destroy();
+                factory.destroy();
+            }
+            return Err(err);
+        }
+
+        Ok(Device {
+            factory,
+            device,
+            adapter: adapter_desc,
+            dispatch_signature,
+            state: Arc::new(DeviceState::new(device)),
+            deletion: Dx12DeletionQueue::new(),
+        })
+    }
+
+    /// List all adapters supporting D3D12.
+    pub fn enumerate_adapters() -> Result<Vec<AdapterDesc>, Error> {
+        let (factory, hr) = d3d12::Factory4::create(DeviceCreateFlags::empty());
+        check_hresult(hr)?;
+        let adapters = Self::collect_adapters(&factory);
+        let descs = adapters
+            .into_iter()
+            .map(|(adapter, desc)| {
+                unsafe {
+                    adapter.destroy();
+                }
+                desc
+            })
+            .collect();
+        unsafe {
+            factory.destroy();
+        }
+        Ok(descs)
     }
 
-    fn select_adapter(factory: &Factory) -> Adapter {
+    /// Description of the adapter the device has been created on.
+    pub fn adapter(&self) -> &AdapterDesc {
+        &self.adapter
+    }
+
+    fn collect_adapters(factory: &Factory) -> Vec<(Adapter, AdapterDesc)> {
+        let mut adapters = Vec::new();
         let mut adapter_id = 0;
         loop {
             let (adapter, hr) = factory.enumerate_adapters(adapter_id);
@@ -38,12 +128,16 @@ impl Device {
                 break;
             }
 
+            let index = adapter_id;
             adapter_id += 1;
 
             // Check for D3D12 support
             {
                 let (device, hr) = D3DDevice::create(adapter, d3d12::FeatureLevel::L12_0);
                 if !winerror::SUCCEEDED(hr) {
+                    unsafe {
+                        adapter.destroy();
+                    }
                     continue;
                 }
                 unsafe {
@@ -51,28 +145,115 @@ impl Device {
                 }
             };
 
-            return adapter;
+            let desc = unsafe {
+                let mut desc = mem::zeroed::<dxgi::DXGI_ADAPTER_DESC1>();
+                adapter.GetDesc1(&mut desc);
+                desc
+            };
+            let name_len = desc
+                .Description
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(desc.Description.len());
+
+            adapters.push((
+                adapter,
+                AdapterDesc {
+                    index,
+                    name: String::from_utf16_lossy(&desc.Description[..name_len]),
+                    vendor_id: desc.VendorId,
+                    device_id: desc.DeviceId,
+                    dedicated_memory: desc.DedicatedVideoMemory as _,
+                    software: desc.Flags & dxgi::DXGI_ADAPTER_FLAG_SOFTWARE != 0,
+                },
+            ));
         }
 
-        panic!("Couldn't find suitable D3D12 adapter");
+        adapters
+    }
+
+    /// Convert an `HRESULT` into a result, querying the removal reason on device loss.
+    ///
+    /// The lost callback is notified on the first detection of device loss.
+    pub fn check(&self, hr: HResult) -> Result<(), Error> {
+        self.state.check(hr)
+    }
+
+    /// Register a callback invoked once on detection of device removal.
+    ///
+    /// See the `lost` module for the recovery procedure.
+    pub fn set_lost_callback<F>(&self, callback: F)
+    where
+        F: FnMut(HResult) + Send + 'static,
+    {
+        self.state.set_callback(Some(Box::new(callback)));
+    }
+
+    /// Mark the device as removed for testing the recovery path.
+    ///
+    /// Subsequent submissions, presents and waits return `Error::DeviceLost`
+    /// with the given reason, defaulting to `DXGI_ERROR_DEVICE_REMOVED`.
+    /// The lost callback is notified immediately.
+    pub fn simulate_removal(&self, reason: Option<HResult>) {
+        self.state.simulate_removal(reason);
+    }
+
+    /// Check if the device has been removed, returns the removal reason.
+    pub fn removed_reason(&self) -> Option<HResult> {
+        self.state.removed_reason()
     }
 
     pub fn create_semaphore(&self) -> Result<Semaphore, Error> {
-        let (fence, _) = self.create_fence(0);
+        let (fence, hr) = self.create_fence(0);
+        self.check(hr)?;
         let event = d3d12::Event::create(false, false);
+        if event.0.is_null() {
+            unsafe {
+                fence.destroy();
+            }
+            return Err(io::Error::last_os_error().into());
+        }
 
-        Ok(Semaphore { fence, event })
+        Ok(Semaphore {
+            fence: unsafe { Owned::new(fence) },
+            event,
+            state: self.state.clone(),
+        })
     }
 
     pub fn create_queue(&self, ty: CmdBufferTy) -> Result<Queue, Error> {
-        let (queue, _) = self.device.create_command_queue(
+        let (queue, hr) = self.device.create_command_queue(
             ty,
             d3d12::Priority::Normal,
             d3d12::CommandQueueFlags::empty(),
             0,
         );
+        self.check(hr)?;
+        let (fence, hr) = self.device.create_fence(0);
+        if let Err(err) = self.check(hr) {
+            unsafe {
+                queue.destroy();
+            }
+            return Err(err);
+        }
+        let (timeline_fence, hr) = self.device.create_fence(0);
+        if let Err(err) = self.check(hr) {
+            unsafe {
+                fence.destroy();
+                queue.destroy();
+            }
+            return Err(err);
+        }
+        let timeline = Dx12Timeline::new(unsafe { Owned::new(timeline_fence) });
+        self.deletion.register(&timeline);
 
-        Ok(Queue { queue })
+        Ok(Queue {
+            ty,
+            queue,
+            timeline,
+            state: self.state.clone(),
+            fixups: Mutex::new(Fixups::new(ty, fence)),
+        })
     }
 
     pub fn create_compute_pipeline(
@@ -80,14 +261,29 @@ impl Device {
         shader: &Shader,
         layout: &PipelineLayout,
     ) -> Result<Pipeline, Error> {
-        let (pipeline, _) = self.device.create_compute_pipeline_state(
-            *layout,
+        let (pipeline, hr) = self.device.create_compute_pipeline_state(
+            layout.signature,
             shader.bytecode(),
             0,
             d3d12::CachedPSO::null(),
             d3d12::PipelineStateFlags::empty(),
         );
-        Ok(pipeline)
+        self.check(hr)?;
+        Ok(Pipeline(pipeline, self.deletion.clone()))
+    }
+
+    /// Release dropped resources and pipelines the GPU finished with.
+    ///
+    /// Returns the number of objects still in use.
+    pub fn flush(&self) -> usize {
+        self.deletion.flush()
+    }
+
+    /// Wait until all queues finished their submissions and release all dropped objects.
+    pub fn wait_idle(&self) -> Result<(), Error> {
+        self.state.check_lost()?;
+        self.deletion.wait_idle()?;
+        self.state.check_lost()
     }
 }
 
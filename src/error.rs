@@ -1,10 +1,63 @@
 use std::{fmt, io};
 
+/// Raw `HRESULT` error code as returned by D3D12 and DXGI.
+pub type HResult = i32;
+
+// `VK_ERROR_DEVICE_LOST`
+const VK_ERROR_DEVICE_LOST: i32 = -4;
+
 #[derive(Debug)]
 pub enum Error {
     Shader { cause: String },
     Io(io::Error),
-    DeviceLost,
+    AdapterNotFound,
+    OutOfMemory { hr: HResult },
+    InvalidArgument { hr: HResult },
+    /// Device removal reported by a failing call (`hr`) or detected during
+    /// submission, presentation or synchronization.
+    DeviceLost { hr: HResult, reason: HResult },
+    Unsupported { hr: HResult },
+    /// Any other failing `HRESULT`.
+    Api { hr: HResult },
+    /// Failing `VkResult` of the Vulkan backend.
+    Vulkan { result: i32 },
+}
+
+impl Error {
+    /// Map a failing `HRESULT` to an error.
+    ///
+    /// The removal reason of `DeviceLost` is set to the code itself,
+    /// use `Device::check` for querying the actual reason from the device.
+    pub fn from_hresult(hr: HResult) -> Self {
+        match hresult_kind(hr) {
+            HResultKind::OutOfMemory => Error::OutOfMemory { hr },
+            HResultKind::InvalidArgument => Error::InvalidArgument { hr },
+            HResultKind::DeviceRemoved => Error::DeviceLost { hr, reason: hr },
+            HResultKind::Unsupported => Error::Unsupported { hr },
+            HResultKind::Other => Error::Api { hr },
+        }
+    }
+
+    /// Check if the error originates from device removal.
+    pub fn is_device_lost(&self) -> bool {
+        match *self {
+            Error::DeviceLost { .. } => true,
+            Error::Vulkan { result } => result == VK_ERROR_DEVICE_LOST,
+            _ => false,
+        }
+    }
+
+    /// Raw `HRESULT` of API errors.
+    pub fn hresult(&self) -> Option<HResult> {
+        match *self {
+            Error::OutOfMemory { hr }
+            | Error::InvalidArgument { hr }
+            | Error::DeviceLost { hr, .. }
+            | Error::Unsupported { hr }
+            | Error::Api { hr } => Some(hr),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -12,7 +65,20 @@ impl fmt::Display for Error {
         match *self {
             Error::Shader { ref cause } => writeln!(fmt, "Shader: {}", cause),
             Error::Io(ref err) => writeln!(fmt, "I/O: {}", err),
-            Error::DeviceLost => writeln!(fmt, "Device Lost"),
+            Error::AdapterNotFound => writeln!(fmt, "No suitable adapter found"),
+            Error::OutOfMemory { hr } => writeln!(fmt, "Out of memory: {}", HResultDisplay(hr)),
+            Error::InvalidArgument { hr } => {
+                writeln!(fmt, "Invalid argument: {}", HResultDisplay(hr))
+            }
+            Error::DeviceLost { hr, reason } => writeln!(
+                fmt,
+                "Device lost: {} (reason: {})",
+                HResultDisplay(hr),
+                HResultDisplay(reason)
+            ),
+            Error::Unsupported { hr } => writeln!(fmt, "Unsupported: {}", HResultDisplay(hr)),
+            Error::Api { hr } => writeln!(fmt, "API: {}", HResultDisplay(hr)),
+            Error::Vulkan { result } => writeln!(fmt, "Vulkan: VkResult {}", result),
         }
     }
 }
@@ -24,3 +90,220 @@ impl From<io::Error> for Error {
         Error::Io(err)
     }
 }
+
+/// Error category of an `HRESULT`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HResultKind {
+    OutOfMemory,
+    InvalidArgument,
+    DeviceRemoved,
+    Unsupported,
+    Other,
+}
+
+// Decoding table of common D3D12 and DXGI error codes.
+//
+// Returns (name, category, description).
+fn hresult_entry(hr: HResult) -> Option<(&'static str, HResultKind, &'static str)> {
+    let entry = match hr as u32 {
+        0x8000_4001 => ("E_NOTIMPL", HResultKind::Unsupported, "not implemented"),
+        0x8000_4002 => (
+            "E_NOINTERFACE",
+            HResultKind::Unsupported,
+            "interface not supported",
+        ),
+        0x8000_4005 => ("E_FAIL", HResultKind::Other, "unspecified failure"),
+        0x8007_0005 => ("E_ACCESSDENIED", HResultKind::Other, "access denied"),
+        0x8007_000E => (
+            "E_OUTOFMEMORY",
+            HResultKind::OutOfMemory,
+            "failed to allocate memory",
+        ),
+        0x8007_0057 => (
+            "E_INVALIDARG",
+            HResultKind::InvalidArgument,
+            "one or more arguments are invalid",
+        ),
+        0x887A_0001 => (
+            "DXGI_ERROR_INVALID_CALL",
+            HResultKind::InvalidArgument,
+            "invalid call or parameters",
+        ),
+        0x887A_0002 => (
+            "DXGI_ERROR_NOT_FOUND",
+            HResultKind::Other,
+            "object not found",
+        ),
+        0x887A_0003 => (
+            "DXGI_ERROR_MORE_DATA",
+            HResultKind::InvalidArgument,
+            "buffer too small",
+        ),
+        0x887A_0004 => (
+            "DXGI_ERROR_UNSUPPORTED",
+            HResultKind::Unsupported,
+            "functionality not supported",
+        ),
+        0x887A_0005 => (
+            "DXGI_ERROR_DEVICE_REMOVED",
+            HResultKind::DeviceRemoved,
+            "device removed",
+        ),
+        0x887A_0006 => (
+            "DXGI_ERROR_DEVICE_HUNG",
+            HResultKind::DeviceRemoved,
+            "device hung due to badly formed commands",
+        ),
+        0x887A_0007 => (
+            "DXGI_ERROR_DEVICE_RESET",
+            HResultKind::DeviceRemoved,
+            "device reset due to badly formed commands",
+        ),
+        0x887A_000A => (
+            "DXGI_ERROR_WAS_STILL_DRAWING",
+            HResultKind::Other,
+            "GPU still busy",
+        ),
+        0x887A_0020 => (
+            "DXGI_ERROR_DRIVER_INTERNAL_ERROR",
+            HResultKind::DeviceRemoved,
+            "driver internal error",
+        ),
+        0x887A_0022 => (
+            "DXGI_ERROR_NOT_CURRENTLY_AVAILABLE",
+            HResultKind::Unsupported,
+            "resource not currently available",
+        ),
+        0x887A_002D => (
+            "DXGI_ERROR_SDK_COMPONENT_MISSING",
+            HResultKind::Unsupported,
+            "SDK component missing",
+        ),
+        0x887E_0001 => (
+            "D3D12_ERROR_ADAPTER_NOT_FOUND",
+            HResultKind::Unsupported,
+            "cached PSO adapter mismatch",
+        ),
+        0x887E_0002 => (
+            "D3D12_ERROR_DRIVER_VERSION_MISMATCH",
+            HResultKind::Unsupported,
+            "cached PSO driver version mismatch",
+        ),
+        _ => return None,
+    };
+    Some(entry)
+}
+
+/// Check if the `HRESULT` reports success.
+pub fn hresult_succeeded(hr: HResult) -> bool {
+    hr >= 0
+}
+
+/// Category of the `HRESULT`, unknown codes map to `HResultKind::Other`.
+pub fn hresult_kind(hr: HResult) -> HResultKind {
+    hresult_entry(hr).map_or(HResultKind::Other, |entry| entry.1)
+}
+
+/// Symbolic name of the `HRESULT`, e.g `E_OUTOFMEMORY`.
+pub fn hresult_name(hr: HResult) -> Option<&'static str> {
+    if hr == 0 {
+        return Some("S_OK");
+    }
+    hresult_entry(hr).map(|entry| entry.0)
+}
+
+/// Human readable description of the `HRESULT`.
+pub fn hresult_description(hr: HResult) -> Option<&'static str> {
+    if hr == 0 {
+        return Some("success");
+    }
+    hresult_entry(hr).map(|entry| entry.2)
+}
+
+/// Convert an `HRESULT` into a result.
+pub fn check_hresult(hr: HResult) -> Result<(), Error> {
+    if hresult_succeeded(hr) {
+        Ok(())
+    } else {
+        Err(Error::from_hresult(hr))
+    }
+}
+
+/// Formats an `HRESULT` as `NAME (0x887A0005): description`.
+pub struct HResultDisplay(pub HResult);
+
+impl fmt::Display for HResultDisplay {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match (hresult_name(self.0), hresult_description(self.0)) {
+            (Some(name), Some(description)) => {
+                write!(fmt, "{} (0x{:08X}): {}", name, self.0 as u32, description)
+            }
+            _ => write!(fmt, "0x{:08X}", self.0 as u32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const E_OUTOFMEMORY: HResult = 0x8007_000E_u32 as _;
+    const E_UNEXPECTED: HResult = 0x8000_FFFF_u32 as _;
+    const DXGI_ERROR_DEVICE_HUNG: HResult = 0x887A_0006_u32 as _;
+    const S_FALSE: HResult = 1;
+
+    #[test]
+    fn known_codes() {
+        assert_eq!(hresult_kind(E_OUTOFMEMORY), HResultKind::OutOfMemory);
+        assert_eq!(hresult_name(E_OUTOFMEMORY), Some("E_OUTOFMEMORY"));
+        assert_eq!(
+            HResultDisplay(E_OUTOFMEMORY).to_string(),
+            "E_OUTOFMEMORY (0x8007000E): failed to allocate memory"
+        );
+        assert_eq!(
+            hresult_kind(0x887A_0001_u32 as _),
+            HResultKind::InvalidArgument
+        );
+        assert_eq!(hresult_kind(0x8000_4001_u32 as _), HResultKind::Unsupported);
+    }
+
+    #[test]
+    fn unknown_codes() {
+        assert_eq!(hresult_kind(E_UNEXPECTED), HResultKind::Other);
+        assert_eq!(hresult_name(E_UNEXPECTED), None);
+        assert_eq!(hresult_description(E_UNEXPECTED), None);
+        assert_eq!(HResultDisplay(E_UNEXPECTED).to_string(), "0x8000FFFF");
+        match check_hresult(E_UNEXPECTED) {
+            Err(Error::Api { hr }) => assert_eq!(hr, E_UNEXPECTED),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn success_codes() {
+        assert!(check_hresult(0).is_ok());
+        assert!(check_hresult(S_FALSE).is_ok());
+        assert!(hresult_succeeded(S_FALSE));
+        assert_eq!(hresult_name(0), Some("S_OK"));
+        assert_eq!(hresult_description(0), Some("success"));
+    }
+
+    #[test]
+    fn device_removed() {
+        assert_eq!(
+            hresult_kind(DXGI_ERROR_DEVICE_HUNG),
+            HResultKind::DeviceRemoved
+        );
+        let err = check_hresult(DXGI_ERROR_DEVICE_HUNG).unwrap_err();
+        match err {
+            Error::DeviceLost { hr, reason } => {
+                assert_eq!(hr, DXGI_ERROR_DEVICE_HUNG);
+                assert_eq!(reason, DXGI_ERROR_DEVICE_HUNG);
+            }
+            ref err => panic!("unexpected error {:?}", err),
+        }
+        assert!(err.is_device_lost());
+        assert_eq!(err.hresult(), Some(DXGI_ERROR_DEVICE_HUNG));
+        assert!(!Error::from_hresult(E_OUTOFMEMORY).is_device_lost());
+    }
+}
@@ -0,0 +1,187 @@
+//! Layout of image data in buffers for buffer-image copies.
+//!
+//! Follows the D3D12 placement rules: rows are aligned to
+//! `ROW_PITCH_ALIGNMENT` bytes and each subresource starts at a multiple of
+//! `PLACEMENT_ALIGNMENT` bytes, equal to `GetCopyableFootprints` for
+//! uncompressed formats.
+
+use crate::backend::Extent;
+
+/// Alignment of the row pitch (`D3D12_TEXTURE_DATA_PITCH_ALIGNMENT`).
+pub const ROW_PITCH_ALIGNMENT: u64 = 256;
+/// Alignment of subresource offsets (`D3D12_TEXTURE_DATA_PLACEMENT_ALIGNMENT`).
+pub const PLACEMENT_ALIGNMENT: u64 = 512;
+
+// `alignment` must be a power of two.
+fn align_up(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+/// Extent of a mip level, at least one texel in each dimension.
+pub fn mip_extent(extent: Extent, level: u32) -> Extent {
+    Extent {
+        width: (extent.width >> level).max(1),
+        height: (extent.height >> level).max(1),
+        depth: (extent.depth >> level).max(1),
+    }
+}
+
+/// Placement of a region of texels in a buffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Footprint {
+    /// Offset of the first texel, multiple of `PLACEMENT_ALIGNMENT`.
+    pub offset: u64,
+    pub extent: Extent,
+    pub texel_size: u32,
+    /// Bytes between consecutive rows, multiple of `ROW_PITCH_ALIGNMENT`.
+    pub row_pitch: u32,
+    /// Bytes of texel data in a row.
+    pub row_size: u32,
+    /// Bytes covered by the footprint, the last row isn't padded.
+    pub size: u64,
+}
+
+impl Footprint {
+    /// Tightly placed footprint at `offset` with the minimal row pitch.
+    pub fn new(offset: u64, extent: Extent, texel_size: u32) -> Self {
+        assert_eq!(
+            offset % PLACEMENT_ALIGNMENT,
+            0,
+            "footprint offset {} isn't aligned to {} bytes",
+            offset,
+            PLACEMENT_ALIGNMENT
+        );
+        assert!(
+            extent.width > 0 && extent.height > 0 && extent.depth > 0,
+            "empty footprint {:?}",
+            extent
+        );
+
+        let row_size = extent.width as u64 * texel_size as u64;
+        let row_pitch = align_up(row_size, ROW_PITCH_ALIGNMENT);
+        let num_rows = extent.height as u64 * extent.depth as u64;
+        Footprint {
+            offset,
+            extent,
+            texel_size,
+            row_pitch: row_pitch as _,
+            row_size: row_size as _,
+            size: row_pitch * (num_rows - 1) + row_size,
+        }
+    }
+
+    /// Bytes between consecutive depth slices.
+    pub fn slice_pitch(&self) -> u64 {
+        self.row_pitch as u64 * self.extent.height as u64
+    }
+
+    /// Buffer offset of a row in a depth slice.
+    pub fn row_offset(&self, y: u32, z: u32) -> u64 {
+        self.offset + z as u64 * self.slice_pitch() + y as u64 * self.row_pitch as u64
+    }
+
+    /// Buffer offset after the last texel.
+    pub fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+}
+
+/// Footprints of all subresources of an image placed consecutively starting at `offset`.
+///
+/// Footprints are ordered by subresource index (`mip + layer * mip_levels`).
+/// Returns the footprints and the number of bytes required starting at `offset`.
+pub fn subresource_footprints(
+    extent: Extent,
+    texel_size: u32,
+    mip_levels: u32,
+    array_layers: u32,
+    offset: u64,
+) -> (Vec<Footprint>, u64) {
+    let mut footprints = Vec::with_capacity((mip_levels * array_layers) as usize);
+    let mut end = offset;
+    for _ in 0..array_layers {
+        for level in 0..mip_levels {
+            let footprint = Footprint::new(
+                align_up(end, PLACEMENT_ALIGNMENT),
+                mip_extent(extent, level),
+                texel_size,
+            );
+            end = footprint.end();
+            footprints.push(footprint);
+        }
+    }
+    (footprints, end - offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32, depth: u32) -> Extent {
+        Extent {
+            width,
+            height,
+            depth,
+        }
+    }
+
+    #[test]
+    fn row_pitch_alignment() {
+        let footprint = Footprint::new(0, extent(100, 10, 1), 4);
+        assert_eq!(footprint.row_size, 400);
+        assert_eq!(footprint.row_pitch, 512);
+        assert_eq!(footprint.size, 512 * 9 + 400);
+
+        let footprint = Footprint::new(512, extent(64, 2, 1), 4);
+        assert_eq!(footprint.row_pitch, 256);
+        assert_eq!(footprint.end(), 512 + 512);
+
+        let footprint = Footprint::new(0, extent(1, 1, 1), 16);
+        assert_eq!(footprint.row_pitch, 256);
+        assert_eq!(footprint.size, 16);
+    }
+
+    #[test]
+    fn depth_slices() {
+        let footprint = Footprint::new(0, extent(8, 4, 3), 4);
+        assert_eq!(footprint.slice_pitch(), 256 * 4);
+        assert_eq!(footprint.row_offset(1, 2), 256 * 4 * 2 + 256);
+        assert_eq!(footprint.size, 256 * 11 + 32);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unaligned_offset() {
+        Footprint::new(256, extent(4, 4, 1), 4);
+    }
+
+    #[test]
+    fn mip_chain() {
+        let (footprints, size) = subresource_footprints(extent(64, 64, 1), 4, 3, 1, 0);
+        let offsets = footprints.iter().map(|f| f.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, [0, 16384, 24576]);
+        assert_eq!(footprints[1].extent, extent(32, 32, 1));
+        assert_eq!(footprints[2].row_pitch, 256);
+        assert_eq!(size, 24576 + 256 * 15 + 64);
+    }
+
+    #[test]
+    fn array_layers() {
+        let (footprints, size) = subresource_footprints(extent(4, 4, 1), 4, 2, 2, 1024);
+        assert_eq!(footprints.len(), 4);
+        // Subresource 2 is the first mip of the second layer.
+        assert_eq!(footprints[2].extent, extent(4, 4, 1));
+        assert_eq!(footprints[2].offset, 2560);
+        assert_eq!(footprints[3].extent, extent(2, 2, 1));
+        assert_eq!(footprints[3].offset, 3584);
+        assert_eq!(size, 3584 + 256 + 8 - 1024);
+    }
+
+    #[test]
+    fn volume_mips() {
+        let (footprints, _) = subresource_footprints(extent(16, 16, 4), 4, 3, 1, 0);
+        assert_eq!(footprints[1].extent, extent(8, 8, 2));
+        assert_eq!(footprints[2].extent, extent(4, 4, 1));
+        assert_eq!(footprints[1].offset, align_up(256 * 63 + 64, 512));
+    }
+}
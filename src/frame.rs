@@ -0,0 +1,216 @@
+//! Frames in flight.
+//!
+//! A `FrameRing` owns resources for each frame in flight and paces the CPU
+//! with a semaphore signaled after each frame's submissions. Frame `n`
+//! signals value `n + 1`, its slot is reused for frame `n + num_frames` once
+//! the GPU finished it.
+
+use crate::backend::{Backend, BackendDevice, BackendQueue, BackendSemaphore};
+use crate::Error;
+use std::mem;
+
+type Cleanup<T> = Box<dyn FnOnce(&mut T)>;
+
+struct FrameSlot<T> {
+    resources: T,
+    // Semaphore value signaled by the latest frame using this slot.
+    value: u64,
+    // Cleanup callbacks with the semaphore value of their frame.
+    cleanups: Vec<(u64, Cleanup<T>)>,
+}
+
+pub struct FrameRing<B: Backend, T> {
+    semaphore: B::Semaphore,
+    slots: Vec<FrameSlot<T>>,
+    // Index of the current or next frame.
+    frame: u64,
+    active: bool,
+}
+
+impl<B: Backend, T> FrameRing<B, T> {
+    /// Create a ring of `num_frames` slots with resources created by `init` for each slot.
+    pub fn new<F>(device: &B::Device, num_frames: usize, mut init: F) -> Result<Self, Error>
+    where
+        F: FnMut(usize) -> Result<T, Error>,
+    {
+        assert!(num_frames > 0, "at least one frame in flight required");
+        let slots = (0..num_frames)
+            .map(|i| {
+                Ok(FrameSlot {
+                    resources: init(i)?,
+                    value: 0,
+                    cleanups: Vec::new(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(FrameRing {
+            semaphore: device.create_semaphore()?,
+            slots,
+            frame: 0,
+            active: false,
+        })
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Index of the current frame, counting from 0.
+    pub fn frame_index(&self) -> u64 {
+        self.frame
+    }
+
+    /// Slot of the current frame.
+    pub fn slot(&self) -> usize {
+        (self.frame % self.slots.len() as u64) as _
+    }
+
+    /// Semaphore signaled with `signal_value` after each frame.
+    pub fn semaphore(&self) -> &B::Semaphore {
+        &self.semaphore
+    }
+
+    /// Value signaled after the current frame, for tying other resources to its completion.
+    pub fn signal_value(&self) -> u64 {
+        self.frame + 1
+    }
+
+    /// Index of the latest frame finished by the GPU.
+    ///
+    /// Values beyond the ended frames, e.g `u64::MAX` signaled on device
+    /// removal, are clamped to the latest ended frame.
+    pub fn completed_frame(&self) -> Option<u64> {
+        self.semaphore
+            .completed_value()
+            .min(self.frame)
+            .checked_sub(1)
+    }
+
+    /// Wait until the slot of the next frame is available and return its resources.
+    ///
+    /// Cleanup callbacks of retired frames run before returning.
+    pub fn begin_frame(&mut self) -> Result<&mut T, Error> {
+        assert!(!self.active, "`begin_frame` without `end_frame`");
+        let slot = self.slot();
+        let value = self.slots[slot].value;
+        self.semaphore.wait(value)?;
+        self.retire();
+
+        self.active = true;
+        Ok(&mut self.slots[slot].resources)
+    }
+
+    /// Signal the end of the current frame on `queue`, after submitting its command buffers.
+    pub fn end_frame(&mut self, queue: &B::Queue) -> Result<(), Error> {
+        assert!(self.active, "`end_frame` without `begin_frame`");
+        let value = self.signal_value();
+        queue.signal(&self.semaphore, value)?;
+
+        let slot = self.slot();
+        self.slots[slot].value = value;
+        self.active = false;
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Resources of the current frame.
+    pub fn resources(&self) -> &T {
+        &self.slots[self.slot()].resources
+    }
+
+    pub fn resources_mut(&mut self) -> &mut T {
+        let slot = self.slot();
+        &mut self.slots[slot].resources
+    }
+
+    /// Run `cleanup` on the slot's resources once the current frame retired.
+    pub fn on_retire<F>(&mut self, cleanup: F)
+    where
+        F: FnOnce(&mut T) + 'static,
+    {
+        assert!(self.active, "`on_retire` outside of a frame");
+        let value = self.signal_value();
+        let slot = self.slot();
+        self.slots[slot].cleanups.push((value, Box::new(cleanup)));
+    }
+
+    /// Run the cleanup callbacks of all frames finished by the GPU.
+    pub fn retire(&mut self) {
+        let completed = self.semaphore.completed_value();
+        for slot in &mut self.slots {
+            let (retired, pending): (Vec<_>, Vec<_>) = mem::take(&mut slot.cleanups)
+                .into_iter()
+                .partition(|&(value, _)| value <= completed);
+            slot.cleanups = pending;
+            for (_, cleanup) in retired {
+                cleanup(&mut slot.resources);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Cpu, CpuDevice, QueueTy};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn slot_cycling() {
+        let device = CpuDevice::new();
+        let queue = device.create_queue(QueueTy::Direct).unwrap();
+        let mut ring = FrameRing::<Cpu, _>::new(&device, 2, Ok).unwrap();
+        assert_eq!(ring.num_frames(), 2);
+        assert_eq!(ring.completed_frame(), None);
+
+        for frame in 0..5 {
+            let slot = *ring.begin_frame().unwrap();
+            assert_eq!(slot, frame % 2);
+            assert_eq!(ring.slot(), slot);
+            assert_eq!(ring.frame_index(), frame as u64);
+            assert_eq!(ring.signal_value(), frame as u64 + 1);
+            ring.end_frame(&queue).unwrap();
+            assert_eq!(ring.completed_frame(), Some(frame as u64));
+        }
+    }
+
+    #[test]
+    fn completed_frame_clamped() {
+        let device = CpuDevice::new();
+        let queue = device.create_queue(QueueTy::Direct).unwrap();
+        let mut ring = FrameRing::<Cpu, _>::new(&device, 2, |_| Ok(())).unwrap();
+        ring.begin_frame().unwrap();
+        ring.end_frame(&queue).unwrap();
+
+        // Signaled on device removal.
+        queue.signal(ring.semaphore(), !0).unwrap();
+        assert_eq!(ring.completed_frame(), Some(0));
+    }
+
+    #[test]
+    fn retire_cleanups() {
+        let device = CpuDevice::new();
+        let queue = device.create_queue(QueueTy::Direct).unwrap();
+        let mut ring = FrameRing::<Cpu, _>::new(&device, 2, |_| Ok(Vec::new())).unwrap();
+        let retired = Rc::new(RefCell::new(Vec::new()));
+
+        ring.begin_frame().unwrap().push(0);
+        let frame_retired = retired.clone();
+        ring.on_retire(move |resources: &mut Vec<u32>| {
+            frame_retired.borrow_mut().push(resources.len());
+            resources.clear();
+        });
+        ring.end_frame(&queue).unwrap();
+        assert!(retired.borrow().is_empty());
+
+        // Cleanups of finished frames run when a frame begins.
+        assert!(ring.begin_frame().unwrap().is_empty());
+        assert_eq!(*retired.borrow(), [1]);
+        ring.end_frame(&queue).unwrap();
+
+        assert!(ring.begin_frame().unwrap().is_empty());
+        assert_eq!(*retired.borrow(), [1]);
+    }
+}
@@ -0,0 +1,184 @@
+//! D3D12 execution of compiled render graphs.
+
+use super::*;
+use crate::backend::{BackendDevice, HeapType};
+use crate::resource::Tracking;
+use crate::{Buffer, CommandBuffer, Device, Error, Image, MarkerColor, ResourceStates};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const PASS_MARKER_COLOR: MarkerColor = 0x4080c0;
+
+enum Transient {
+    Buffer(Buffer),
+    Image(Image),
+}
+
+/// Physical resources of transient graph resources, reused across frames.
+#[derive(Default)]
+pub struct TransientPool {
+    resources: Vec<Option<(TransientDesc, Transient)>>,
+}
+
+impl TransientPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate the transient resources of a plan, keeping previously allocated matching resources.
+    ///
+    /// Replaced resources are dropped, which defers their release until the GPU
+    /// finished all submitted work. Command buffers recorded with the previous
+    /// plan must be submitted before calling `prepare`.
+    pub fn prepare<S>(&mut self, device: &Device, plan: &GraphPlan<S>) -> Result<(), Error> {
+        self.resources
+            .resize_with(plan.physical.len().max(self.resources.len()), || None);
+        for (i, physical) in plan.physical.iter().enumerate() {
+            let desc = match *physical {
+                PhysicalResource::Transient(desc) => desc,
+                PhysicalResource::Imported(_) => continue,
+            };
+            if let Some((current, _)) = &self.resources[i] {
+                if *current == desc {
+                    continue;
+                }
+            }
+            let resource = match desc {
+                TransientDesc::Buffer { size } => {
+                    Transient::Buffer(device.create_buffer(size, HeapType::Device)?)
+                }
+                TransientDesc::Image { extent, format } => {
+                    Transient::Image(device.create_image_2d(extent, format)?)
+                }
+            };
+            self.resources[i] = Some((desc, resource));
+        }
+        Ok(())
+    }
+}
+
+/// Resources bound to a compiled graph for execution.
+pub struct GraphResources<'a> {
+    plan: &'a GraphPlan<ResourceStates>,
+    transients: &'a TransientPool,
+    buffers: HashMap<GraphResource, &'a Buffer>,
+    images: HashMap<GraphResource, &'a Image>,
+}
+
+impl<'a> GraphResources<'a> {
+    /// `transients` must have been prepared for `plan`.
+    pub fn new(plan: &'a GraphPlan<ResourceStates>, transients: &'a TransientPool) -> Self {
+        GraphResources {
+            plan,
+            transients,
+            buffers: HashMap::new(),
+            images: HashMap::new(),
+        }
+    }
+
+    pub fn bind_buffer(&mut self, resource: GraphResource, buffer: &'a Buffer) {
+        self.buffers.insert(resource, buffer);
+    }
+
+    pub fn bind_image(&mut self, resource: GraphResource, image: &'a Image) {
+        self.images.insert(resource, image);
+    }
+
+    fn transient(&self, resource: GraphResource) -> &'a Transient {
+        let index = self
+            .plan
+            .physical_index(resource)
+            .unwrap_or_else(|| panic!("resource {:?} is unused by the plan", resource));
+        let transients = self.transients;
+        match transients.resources.get(index) {
+            Some(Some((_, transient))) => transient,
+            _ => panic!("transient pool isn't prepared for the plan"),
+        }
+    }
+
+    // State tracking of a physical resource of the plan.
+    fn tracking(&self, index: usize) -> &'a Arc<Tracking> {
+        match self.plan.physical[index] {
+            PhysicalResource::Imported(resource) => {
+                match (self.buffers.get(&resource), self.images.get(&resource)) {
+                    (Some(&buffer), _) => &buffer.1,
+                    (None, Some(&image)) => &image.1,
+                    (None, None) => panic!("imported resource {:?} isn't bound", resource),
+                }
+            }
+            PhysicalResource::Transient(_) => match self.transients.resources.get(index) {
+                Some(Some((_, Transient::Buffer(buffer)))) => &buffer.1,
+                Some(Some((_, Transient::Image(image)))) => &image.1,
+                _ => panic!("transient pool isn't prepared for the plan"),
+            },
+        }
+    }
+
+    pub fn buffer(&self, resource: GraphResource) -> &'a Buffer {
+        if let Some(&buffer) = self.buffers.get(&resource) {
+            return buffer;
+        }
+        match self.transient(resource) {
+            Transient::Buffer(buffer) => buffer,
+            Transient::Image(_) => panic!("resource {:?} isn't a buffer", resource),
+        }
+    }
+
+    pub fn image(&self, resource: GraphResource) -> &'a Image {
+        if let Some(&image) = self.images.get(&resource) {
+            return image;
+        }
+        match self.transient(resource) {
+            Transient::Image(image) => image,
+            Transient::Buffer(_) => panic!("resource {:?} isn't an image", resource),
+        }
+    }
+}
+
+impl CommandBuffer {
+    /// Record the passes of a compiled graph.
+    ///
+    /// First uses and barriers of the plan are applied via the state tracking
+    /// of the command buffer before `record` is called for the pass, which
+    /// also accounts for commands recorded before the graph. Each pass is
+    /// recorded into a region named after the pass.
+    pub fn record_graph<F>(
+        &self,
+        graph: &RenderGraph<ResourceStates>,
+        resources: &GraphResources,
+        mut record: F,
+    ) where
+        F: FnMut(PassId, &CommandBuffer, &GraphResources),
+    {
+        for step in &resources.plan.steps {
+            match step {
+                Step::FirstUses(first_uses) => {
+                    for first_use in first_uses {
+                        let tracking = resources.tracking(first_use.resource.0);
+                        self.track(tracking, first_use.subresource, first_use.state);
+                    }
+                }
+                Step::Barriers(barriers) => {
+                    for barrier in barriers {
+                        match *barrier {
+                            Barrier::Transition {
+                                resource,
+                                subresource,
+                                after,
+                                ..
+                            } => self.track(resources.tracking(resource.0), subresource, after),
+                            Barrier::Unordered { resource } => {
+                                self.order_unordered(resources.tracking(resource.0))
+                            }
+                        }
+                    }
+                }
+                Step::Pass(pass_id) => {
+                    let pass = graph.pass(*pass_id);
+                    let _region = self.scoped_region(&pass.name, PASS_MARKER_COLOR);
+                    record(*pass_id, self, resources);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,712 @@
+//! Render graph of compute, copy and present passes.
+//!
+//! Passes declare the buffers and images they read and write. Compiling the
+//! graph is a pure transformation into a `GraphPlan`: unused passes are
+//! culled, transient resources are assigned to physical resources and the
+//! barriers between passes are resolved with the same rules as the command
+//! buffer state tracking.
+//!
+//! Passes are executed in declaration order, which is a valid dependency order
+//! as a pass can only access contents written by previously declared passes.
+//! The state before the first use of each physical resource isn't known at
+//! compile time, first uses are part of the plan without barriers and are
+//! reconciled with the queue level state on submission.
+
+#[cfg(feature = "d3d12")]
+mod dx12;
+
+#[cfg(feature = "d3d12")]
+pub use self::dx12::*;
+
+use crate::backend::{Extent, TexelFormat};
+use crate::{Barrier, CommandTracker, ResourceId, TrackedResource, TrackedState, ALL_SUBRESOURCES};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Buffer or image declared in a graph.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GraphResource(usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PassId(usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PassTy {
+    Compute,
+    Copy,
+    /// Hands the resources over to presentation, never culled.
+    Present,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Description of a resource owned by the graph.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransientDesc {
+    Buffer { size: u64 },
+    Image { extent: Extent, format: TexelFormat },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResourceKind {
+    Buffer,
+    Image,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResourceNode {
+    pub name: String,
+    pub kind: ResourceKind,
+    pub num_subresources: u32,
+    /// `None` for resources imported into the graph.
+    pub transient: Option<TransientDesc>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PassAccess<S> {
+    pub resource: GraphResource,
+    /// Subresource index or `ALL_SUBRESOURCES`.
+    pub subresource: u32,
+    pub state: S,
+    pub access: Access,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pass<S> {
+    pub name: String,
+    pub ty: PassTy,
+    pub accesses: Vec<PassAccess<S>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderGraph<S> {
+    resources: Vec<ResourceNode>,
+    passes: Vec<Pass<S>>,
+}
+
+/// Declares the accesses of a pass.
+pub struct PassBuilder<'a, S> {
+    pass: &'a mut Pass<S>,
+    id: PassId,
+    num_resources: usize,
+}
+
+impl<'a, S: TrackedState> PassBuilder<'a, S> {
+    /// Access a subresource (or `ALL_SUBRESOURCES`) of a resource in `state`.
+    pub fn access(
+        self,
+        resource: GraphResource,
+        subresource: u32,
+        state: S,
+        access: Access,
+    ) -> Self {
+        assert!(
+            resource.0 < self.num_resources,
+            "resource {:?} isn't part of the graph",
+            resource
+        );
+        self.pass.accesses.push(PassAccess {
+            resource,
+            subresource,
+            state,
+            access,
+        });
+        self
+    }
+
+    pub fn read(self, resource: GraphResource, state: S) -> Self {
+        self.access(resource, ALL_SUBRESOURCES, state, Access::Read)
+    }
+
+    pub fn write(self, resource: GraphResource, state: S) -> Self {
+        self.access(resource, ALL_SUBRESOURCES, state, Access::Write)
+    }
+
+    pub fn id(self) -> PassId {
+        self.id
+    }
+}
+
+impl<S: TrackedState> Default for RenderGraph<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: TrackedState> RenderGraph<S> {
+    pub fn new() -> Self {
+        RenderGraph {
+            resources: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    fn add_resource(&mut self, node: ResourceNode) -> GraphResource {
+        self.resources.push(node);
+        GraphResource(self.resources.len() - 1)
+    }
+
+    /// Declare an externally owned buffer, bound on execution.
+    pub fn import_buffer(&mut self, name: &str) -> GraphResource {
+        self.add_resource(ResourceNode {
+            name: name.to_string(),
+            kind: ResourceKind::Buffer,
+            num_subresources: 1,
+            transient: None,
+        })
+    }
+
+    /// Declare an externally owned image, bound on execution.
+    pub fn import_image(&mut self, name: &str, num_subresources: u32) -> GraphResource {
+        self.add_resource(ResourceNode {
+            name: name.to_string(),
+            kind: ResourceKind::Image,
+            num_subresources,
+            transient: None,
+        })
+    }
+
+    /// Declare a device local buffer allocated by the graph.
+    pub fn create_buffer(&mut self, name: &str, size: u64) -> GraphResource {
+        self.add_resource(ResourceNode {
+            name: name.to_string(),
+            kind: ResourceKind::Buffer,
+            num_subresources: 1,
+            transient: Some(TransientDesc::Buffer { size }),
+        })
+    }
+
+    /// Declare a 2D image allocated by the graph.
+    pub fn create_image(
+        &mut self,
+        name: &str,
+        extent: Extent,
+        format: TexelFormat,
+    ) -> GraphResource {
+        self.add_resource(ResourceNode {
+            name: name.to_string(),
+            kind: ResourceKind::Image,
+            num_subresources: 1,
+            transient: Some(TransientDesc::Image { extent, format }),
+        })
+    }
+
+    pub fn add_pass(&mut self, name: &str, ty: PassTy) -> PassBuilder<'_, S> {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            ty,
+            accesses: Vec::new(),
+        });
+        PassBuilder {
+            id: PassId(self.passes.len() - 1),
+            num_resources: self.resources.len(),
+            pass: self.passes.last_mut().unwrap(),
+        }
+    }
+
+    pub fn resource(&self, resource: GraphResource) -> &ResourceNode {
+        &self.resources[resource.0]
+    }
+
+    pub fn pass(&self, pass: PassId) -> &Pass<S> {
+        &self.passes[pass.0]
+    }
+
+    fn subresources(&self, access: &PassAccess<S>) -> Range<usize> {
+        let node = &self.resources[access.resource.0];
+        if access.subresource == ALL_SUBRESOURCES {
+            0..node.num_subresources as usize
+        } else {
+            assert!(
+                access.subresource < node.num_subresources,
+                "subresource {} of `{}` out of range, resource has {} subresources",
+                access.subresource,
+                node.name,
+                node.num_subresources
+            );
+            access.subresource as usize..access.subresource as usize + 1
+        }
+    }
+
+    // Walk the passes backwards, keeping passes with side effects and
+    // passes producing contents read by live passes.
+    fn cull(&self) -> Vec<bool> {
+        let mut live = vec![false; self.passes.len()];
+        // Subresources of transients read by live passes and not written yet.
+        let mut needed = HashMap::<GraphResource, Vec<bool>>::new();
+        for (i, pass) in self.passes.iter().enumerate().rev() {
+            live[i] = pass.ty == PassTy::Present
+                || pass.accesses.iter().any(|access| {
+                    access.access == Access::Write
+                        && (self.resources[access.resource.0].transient.is_none()
+                            || needed.get(&access.resource).is_some_and(|needed| {
+                                needed[self.subresources(access)].contains(&true)
+                            }))
+                });
+            if !live[i] {
+                continue;
+            }
+
+            for access in &pass.accesses {
+                if access.access == Access::Write {
+                    if let Some(needed) = needed.get_mut(&access.resource) {
+                        for needed in &mut needed[self.subresources(access)] {
+                            *needed = false;
+                        }
+                    }
+                }
+            }
+            for access in &pass.accesses {
+                let node = &self.resources[access.resource.0];
+                if access.access == Access::Read && node.transient.is_some() {
+                    let needed = needed
+                        .entry(access.resource)
+                        .or_insert_with(|| vec![false; node.num_subresources as usize]);
+                    for needed in &mut needed[self.subresources(access)] {
+                        *needed = true;
+                    }
+                }
+            }
+        }
+
+        for (resource, needed) in &needed {
+            if let Some(subresource) = needed.iter().position(|&needed| needed) {
+                panic!(
+                    "subresource {} of transient resource `{}` is read before being written",
+                    subresource, self.resources[resource.0].name
+                );
+            }
+        }
+
+        live
+    }
+
+    /// Compile the graph into a plan of barriers and passes.
+    pub fn compile(&self) -> GraphPlan<S> {
+        let live = self.cull();
+        let order = (0..self.passes.len())
+            .filter(|&i| live[i])
+            .collect::<Vec<_>>();
+        let culled = (0..self.passes.len())
+            .filter(|&i| !live[i])
+            .map(PassId)
+            .collect();
+
+        // Lifetime of each used resource in terms of executed passes.
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.resources.len()];
+        for (step, &i) in order.iter().enumerate() {
+            for access in &self.passes[i].accesses {
+                let lifetime = &mut lifetimes[access.resource.0];
+                *lifetime = Some(match *lifetime {
+                    Some((first, _)) => (first, step),
+                    None => (step, step),
+                });
+            }
+        }
+
+        // Transients with disjoint lifetimes and equal descriptions share a physical resource.
+        let mut physical = Vec::<PhysicalResource>::new();
+        let mut available = Vec::<usize>::new();
+        let mut assignment = vec![None; self.resources.len()];
+        let mut resources = (0..self.resources.len())
+            .filter_map(|i| lifetimes[i].map(|lifetime| (i, lifetime)))
+            .collect::<Vec<_>>();
+        resources.sort_by_key(|&(i, (first, _))| (first, i));
+        for (i, (first, last)) in resources {
+            let index = match self.resources[i].transient {
+                None => {
+                    physical.push(PhysicalResource::Imported(GraphResource(i)));
+                    physical.len() - 1
+                }
+                Some(desc) => {
+                    let reuse = (0..physical.len()).find(|&p| {
+                        available[p] < first && physical[p] == PhysicalResource::Transient(desc)
+                    });
+                    match reuse {
+                        Some(p) => p,
+                        None => {
+                            physical.push(PhysicalResource::Transient(desc));
+                            physical.len() - 1
+                        }
+                    }
+                }
+            };
+            available.resize(physical.len(), 0);
+            available[index] = last;
+            assignment[i] = Some(index);
+        }
+
+        let mut tracker = CommandTracker::new();
+        let mut steps = Vec::new();
+        // Subresources of each physical resource accessed by previous passes.
+        let mut used = HashMap::<usize, Vec<bool>>::new();
+        for &i in &order {
+            let mut first_uses = Vec::new();
+            for access in &self.passes[i].accesses {
+                let node = &self.resources[access.resource.0];
+                let index = assignment[access.resource.0].unwrap();
+                let used = used
+                    .entry(index)
+                    .or_insert_with(|| vec![false; node.num_subresources as usize]);
+                let subresources = self.subresources(access);
+                if used[subresources.clone()].iter().all(|&used| !used) {
+                    first_uses.push(FirstUse {
+                        resource: ResourceId(index),
+                        subresource: access.subresource,
+                        state: access.state,
+                    });
+                } else {
+                    first_uses.extend(subresources.clone().filter(|&j| !used[j]).map(|j| {
+                        FirstUse {
+                            resource: ResourceId(index),
+                            subresource: j as u32,
+                            state: access.state,
+                        }
+                    }));
+                }
+                for used in &mut used[subresources] {
+                    *used = true;
+                }
+
+                let resource = TrackedResource {
+                    id: ResourceId(index),
+                    num_subresources: node.num_subresources,
+                    decay: None,
+                };
+                tracker.require(&resource, access.subresource, access.state);
+            }
+            if !first_uses.is_empty() {
+                steps.push(Step::FirstUses(first_uses));
+            }
+            let barriers = tracker.flush();
+            if !barriers.is_empty() {
+                steps.push(Step::Barriers(barriers));
+            }
+            steps.push(Step::Pass(PassId(i)));
+        }
+
+        GraphPlan {
+            steps,
+            culled,
+            physical,
+            assignment,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PhysicalResource {
+    Imported(GraphResource),
+    Transient(TransientDesc),
+}
+
+/// Access of a physical resource not preceded by other accesses in the plan.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FirstUse<S> {
+    /// Physical resource index.
+    pub resource: ResourceId,
+    /// Subresource index or `ALL_SUBRESOURCES`.
+    pub subresource: u32,
+    pub state: S,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step<S> {
+    /// First uses of the next pass, transitions into their states depend on
+    /// the state on submission.
+    FirstUses(Vec<FirstUse<S>>),
+    /// Barriers recorded before the next pass, resources refer to physical resource indices.
+    Barriers(Vec<Barrier<S>>),
+    Pass(PassId),
+}
+
+/// Result of compiling a render graph.
+#[derive(Debug, Clone)]
+pub struct GraphPlan<S> {
+    pub steps: Vec<Step<S>>,
+    pub culled: Vec<PassId>,
+    /// Resources backing the graph resources, indexed by `ResourceId` of the barriers.
+    pub physical: Vec<PhysicalResource>,
+    assignment: Vec<Option<usize>>,
+}
+
+impl<S> GraphPlan<S> {
+    /// Index of the physical resource backing a graph resource, `None` if unused.
+    pub fn physical_index(&self, resource: GraphResource) -> Option<usize> {
+        self.assignment[resource.0]
+    }
+
+    /// Executed passes in order.
+    pub fn passes(&self) -> impl Iterator<Item = PassId> + '_ {
+        self.steps.iter().filter_map(|step| match *step {
+            Step::Pass(pass) => Some(pass),
+            Step::FirstUses(_) | Step::Barriers(_) => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    enum State {
+        Unordered,
+        CopySrc,
+        CopyDst,
+        Present,
+    }
+
+    impl TrackedState for State {
+        fn satisfies(self, required: Self) -> bool {
+            self == required
+        }
+
+        fn is_unordered(self) -> bool {
+            self == State::Unordered
+        }
+    }
+
+    const EXTENT: Extent = Extent {
+        width: 64,
+        height: 64,
+        depth: 1,
+    };
+
+    fn first_use(resource: usize, subresource: u32, state: State) -> FirstUse<State> {
+        FirstUse {
+            resource: ResourceId(resource),
+            subresource,
+            state,
+        }
+    }
+
+    fn transition(resource: usize, before: State, after: State) -> Barrier<State> {
+        Barrier::Transition {
+            resource: ResourceId(resource),
+            subresource: 0,
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn barrier_steps() {
+        let mut graph = RenderGraph::new();
+        let target = graph.import_image("target", 1);
+        let backbuffer = graph.import_image("backbuffer", 1);
+        let raster = graph
+            .add_pass("raster", PassTy::Compute)
+            .write(target, State::Unordered)
+            .id();
+        let blit = graph
+            .add_pass("blit", PassTy::Copy)
+            .read(target, State::CopySrc)
+            .write(backbuffer, State::CopyDst)
+            .id();
+        let present = graph
+            .add_pass("present", PassTy::Present)
+            .read(backbuffer, State::Present)
+            .id();
+
+        let plan = graph.compile();
+        assert!(plan.culled.is_empty());
+        let target = plan.physical_index(target).unwrap();
+        let backbuffer = plan.physical_index(backbuffer).unwrap();
+        assert_eq!(
+            plan.steps,
+            [
+                Step::FirstUses(vec![first_use(target, ALL_SUBRESOURCES, State::Unordered)]),
+                Step::Pass(raster),
+                Step::FirstUses(vec![first_use(
+                    backbuffer,
+                    ALL_SUBRESOURCES,
+                    State::CopyDst
+                )]),
+                Step::Barriers(vec![transition(target, State::Unordered, State::CopySrc)]),
+                Step::Pass(blit),
+                Step::Barriers(vec![transition(backbuffer, State::CopyDst, State::Present)]),
+                Step::Pass(present),
+            ]
+        );
+    }
+
+    #[test]
+    fn unordered_barrier_steps() {
+        let mut graph = RenderGraph::new();
+        let buffer = graph.import_buffer("buffer");
+        let first = graph
+            .add_pass("first", PassTy::Compute)
+            .write(buffer, State::Unordered)
+            .id();
+        let second = graph
+            .add_pass("second", PassTy::Compute)
+            .write(buffer, State::Unordered)
+            .id();
+
+        let plan = graph.compile();
+        let buffer = plan.physical_index(buffer).unwrap();
+        assert_eq!(
+            plan.steps,
+            [
+                Step::FirstUses(vec![first_use(buffer, ALL_SUBRESOURCES, State::Unordered)]),
+                Step::Pass(first),
+                Step::Barriers(vec![Barrier::Unordered {
+                    resource: ResourceId(buffer)
+                }]),
+                Step::Pass(second),
+            ]
+        );
+    }
+
+    #[test]
+    fn cull_unused_passes() {
+        let mut graph = RenderGraph::new();
+        let output = graph.import_buffer("output");
+        let used = graph.create_buffer("used", 256);
+        let unused = graph.create_buffer("unused", 256);
+        let produce = graph
+            .add_pass("produce", PassTy::Compute)
+            .write(used, State::Unordered)
+            .id();
+        let dead = graph
+            .add_pass("dead", PassTy::Compute)
+            .read(used, State::Unordered)
+            .write(unused, State::Unordered)
+            .id();
+        let consume = graph
+            .add_pass("consume", PassTy::Copy)
+            .read(used, State::CopySrc)
+            .write(output, State::CopyDst)
+            .id();
+
+        let plan = graph.compile();
+        assert_eq!(plan.culled, [dead]);
+        assert_eq!(plan.passes().collect::<Vec<_>>(), [produce, consume]);
+        assert_eq!(plan.physical_index(unused), None);
+    }
+
+    #[test]
+    fn alias_transients() {
+        let mut graph = RenderGraph::new();
+        let output = graph.import_buffer("output");
+        let a = graph.create_image("a", EXTENT, TexelFormat::R32Uint);
+        let b = graph.create_image("b", EXTENT, TexelFormat::R32Uint);
+        let c = graph.create_image("c", EXTENT, TexelFormat::Rgba8Unorm);
+        graph
+            .add_pass("write a", PassTy::Compute)
+            .write(a, State::Unordered);
+        graph
+            .add_pass("read a", PassTy::Copy)
+            .read(a, State::CopySrc)
+            .write(output, State::CopyDst);
+        graph
+            .add_pass("write b c", PassTy::Compute)
+            .write(b, State::Unordered)
+            .write(c, State::Unordered);
+        graph
+            .add_pass("read b c", PassTy::Copy)
+            .read(b, State::CopySrc)
+            .read(c, State::CopySrc)
+            .write(output, State::CopyDst);
+
+        let plan = graph.compile();
+        // `b` reuses the image of `a`, `c` differs in format.
+        assert_eq!(plan.physical_index(a), plan.physical_index(b));
+        assert_ne!(plan.physical_index(a), plan.physical_index(c));
+        assert_eq!(plan.physical.len(), 3);
+
+        // The first use of `b` transitions the aliased image.
+        let image = plan.physical_index(b).unwrap();
+        assert!(plan.steps.contains(&Step::Barriers(vec![transition(
+            image,
+            State::CopySrc,
+            State::Unordered
+        )])));
+    }
+
+    #[test]
+    fn subresource_writes() {
+        let mut graph = RenderGraph::new();
+        let output = graph.import_buffer("output");
+        let mips = graph.add_resource(ResourceNode {
+            name: "mips".to_string(),
+            kind: ResourceKind::Image,
+            num_subresources: 2,
+            transient: Some(TransientDesc::Image {
+                extent: EXTENT,
+                format: TexelFormat::R32Float,
+            }),
+        });
+        let write_0 = graph
+            .add_pass("write 0", PassTy::Compute)
+            .access(mips, 0, State::Unordered, Access::Write)
+            .id();
+        let write_1 = graph
+            .add_pass("write 1", PassTy::Compute)
+            .access(mips, 0, State::CopySrc, Access::Read)
+            .access(mips, 1, State::Unordered, Access::Write)
+            .id();
+        graph
+            .add_pass("read", PassTy::Copy)
+            .read(mips, State::CopySrc)
+            .write(output, State::CopyDst);
+
+        let plan = graph.compile();
+        assert!(plan.culled.is_empty());
+        let mips = plan.physical_index(mips).unwrap();
+        assert_eq!(
+            plan.steps[..2],
+            [
+                Step::FirstUses(vec![first_use(mips, 0, State::Unordered)]),
+                Step::Pass(write_0),
+            ]
+        );
+        assert_eq!(
+            plan.steps[2],
+            Step::FirstUses(vec![first_use(mips, 1, State::Unordered)])
+        );
+        assert_eq!(plan.steps[4], Step::Pass(write_1));
+    }
+
+    #[test]
+    #[should_panic(expected = "read before being written")]
+    fn read_before_write() {
+        let mut graph = RenderGraph::new();
+        let output = graph.import_buffer("output");
+        let transient = graph.create_buffer("transient", 4);
+        graph
+            .add_pass("copy", PassTy::Copy)
+            .read(transient, State::CopySrc)
+            .write(output, State::CopyDst);
+        graph.compile();
+    }
+
+    #[test]
+    #[should_panic(expected = "subresource 1 of transient resource `mips`")]
+    fn partial_write() {
+        let mut graph = RenderGraph::new();
+        let output = graph.import_buffer("output");
+        let mips = graph.add_resource(ResourceNode {
+            name: "mips".to_string(),
+            kind: ResourceKind::Image,
+            num_subresources: 2,
+            transient: Some(TransientDesc::Image {
+                extent: EXTENT,
+                format: TexelFormat::R32Float,
+            }),
+        });
+        graph
+            .add_pass("write 0", PassTy::Compute)
+            .access(mips, 0, State::Unordered, Access::Write);
+        graph
+            .add_pass("read", PassTy::Copy)
+            .read(mips, State::CopySrc)
+            .write(output, State::CopyDst);
+        graph.compile();
+    }
+}
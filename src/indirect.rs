@@ -0,0 +1,233 @@
+//! Indirect execution with GPU generated arguments.
+//!
+//! Argument buffers contain tightly packed argument structs, optionally
+//! accompanied by a count buffer holding the number of commands as `u32`.
+
+use crate::deletion::Dx12DeletionQueue;
+use crate::{Buffer, CommandBuffer, Device, Error};
+use bytemuck::{Pod, Zeroable};
+use std::{mem, ptr, sync::Arc};
+use winapi::um::d3d12::{
+    D3D12_DISPATCH_ARGUMENTS, D3D12_DRAW_ARGUMENTS, D3D12_DRAW_INDEXED_ARGUMENTS,
+    D3D12_RESOURCE_STATE_INDIRECT_ARGUMENT,
+};
+
+/// Arguments of an indirect dispatch (`D3D12_DISPATCH_ARGUMENTS`).
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct DispatchArgs {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+/// Arguments of an indirect draw (`D3D12_DRAW_ARGUMENTS`).
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct DrawArgs {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub first_vertex: u32,
+    pub first_instance: u32,
+}
+
+/// Arguments of an indirect indexed draw (`D3D12_DRAW_INDEXED_ARGUMENTS`).
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct DrawIndexedArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    pub first_instance: u32,
+}
+
+// Argument structs are written to buffers consumed by `ExecuteIndirect` as is.
+const _: () = assert!(mem::size_of::<DispatchArgs>() == mem::size_of::<D3D12_DISPATCH_ARGUMENTS>());
+const _: () = assert!(mem::size_of::<DrawArgs>() == mem::size_of::<D3D12_DRAW_ARGUMENTS>());
+const _: () =
+    assert!(mem::size_of::<DrawIndexedArgs>() == mem::size_of::<D3D12_DRAW_INDEXED_ARGUMENTS>());
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IndirectTy {
+    Dispatch,
+    Draw,
+    DrawIndexed,
+}
+
+impl IndirectTy {
+    /// Size of the argument struct in bytes.
+    pub fn stride(&self) -> u32 {
+        let size = match self {
+            IndirectTy::Dispatch => mem::size_of::<DispatchArgs>(),
+            IndirectTy::Draw => mem::size_of::<DrawArgs>(),
+            IndirectTy::DrawIndexed => mem::size_of::<DrawIndexedArgs>(),
+        };
+        size as _
+    }
+
+    fn argument(&self) -> d3d12::IndirectArgument {
+        match self {
+            IndirectTy::Dispatch => d3d12::IndirectArgument::dispatch(),
+            IndirectTy::Draw => d3d12::IndirectArgument::draw(),
+            IndirectTy::DrawIndexed => d3d12::IndirectArgument::draw_indexed(),
+        }
+    }
+}
+
+/// Command signature, released once the GPU finished using it after drop.
+pub struct CommandSignature {
+    pub(crate) signature: d3d12::CommandSignature,
+    ty: IndirectTy,
+    deletion: Arc<Dx12DeletionQueue>,
+}
+
+impl Drop for CommandSignature {
+    fn drop(&mut self) {
+        unsafe { self.deletion.defer_release(self.signature) };
+    }
+}
+
+impl CommandSignature {
+    pub fn ty(&self) -> IndirectTy {
+        self.ty
+    }
+}
+
+// Command signature without root signature changes, executing commands of a single type.
+pub(crate) fn create_signature(
+    device: d3d12::Device,
+    ty: IndirectTy,
+) -> (d3d12::CommandSignature, d3d12::HRESULT) {
+    device.create_command_signature(
+        d3d12::RootSignature::null(),
+        &[ty.argument()],
+        ty.stride(),
+        0,
+    )
+}
+
+impl Device {
+    pub fn create_command_signature(&self, ty: IndirectTy) -> Result<CommandSignature, Error> {
+        let (signature, hr) = create_signature(**self, ty);
+        self.check(hr)?;
+        Ok(CommandSignature {
+            signature,
+            ty,
+            deletion: self.deletion.clone(),
+        })
+    }
+}
+
+impl CommandBuffer {
+    // Validate and track an argument or count buffer range.
+    fn track_indirect(&self, buffer: &Buffer, offset: u64, size: u64) {
+        assert_eq!(
+            offset % 4,
+            0,
+            "indirect offset {} isn't 4 byte aligned",
+            offset
+        );
+        assert!(
+            offset + size <= buffer.size(),
+            "indirect range {}..{} exceeds buffer size {}",
+            offset,
+            offset + size,
+            buffer.size()
+        );
+        self.transition_buffer(buffer, D3D12_RESOURCE_STATE_INDIRECT_ARGUMENT);
+    }
+
+    /// Dispatch with `DispatchArgs` read from `args` at `offset`.
+    pub fn dispatch_indirect(&self, args: &Buffer, offset: u64) {
+        self.assert_compute_bound();
+        self.track_unordered();
+        self.track_indirect(args, offset, IndirectTy::Dispatch.stride() as _);
+        self.flush_barriers();
+        unsafe {
+            self.cmd_buffer.ExecuteIndirect(
+                self.dispatch_signature.as_mut_ptr(),
+                1,
+                args.0.as_mut_ptr(),
+                offset,
+                ptr::null_mut(),
+                0,
+            );
+        }
+    }
+
+    /// Dispatch up to `max_count` times with consecutive `DispatchArgs` read from `args` at `offset`.
+    ///
+    /// The number of dispatches is the minimum of `max_count` and the `u32`
+    /// read from `count` at `count_offset`.
+    pub fn dispatch_indirect_count(
+        &self,
+        args: &Buffer,
+        offset: u64,
+        count: &Buffer,
+        count_offset: u64,
+        max_count: u32,
+    ) {
+        self.assert_compute_bound();
+        self.track_unordered();
+        self.track_indirect(
+            args,
+            offset,
+            max_count as u64 * IndirectTy::Dispatch.stride() as u64,
+        );
+        self.track_indirect(count, count_offset, 4);
+        self.flush_barriers();
+        unsafe {
+            self.cmd_buffer.ExecuteIndirect(
+                self.dispatch_signature.as_mut_ptr(),
+                max_count,
+                args.0.as_mut_ptr(),
+                offset,
+                count.0.as_mut_ptr(),
+                count_offset,
+            );
+        }
+    }
+
+    /// Execute up to `max_count` commands with consecutive arguments read from `args` at `offset`.
+    ///
+    /// With a count buffer the number of commands is the minimum of `max_count`
+    /// and the `u32` read from the count buffer at the given offset.
+    pub fn execute_indirect(
+        &self,
+        signature: &CommandSignature,
+        max_count: u32,
+        args: &Buffer,
+        offset: u64,
+        count: Option<(&Buffer, u64)>,
+    ) {
+        if signature.ty == IndirectTy::Dispatch {
+            self.assert_compute_bound();
+            self.track_unordered();
+        }
+        self.track_indirect(
+            args,
+            offset,
+            max_count as u64 * signature.ty.stride() as u64,
+        );
+        if let Some((count_buffer, count_offset)) = count {
+            self.track_indirect(count_buffer, count_offset, 4);
+        }
+        self.flush_barriers();
+
+        let (count_buffer, count_offset) = match count {
+            Some((buffer, offset)) => (buffer.0.as_mut_ptr(), offset),
+            None => (ptr::null_mut(), 0),
+        };
+        unsafe {
+            self.cmd_buffer.ExecuteIndirect(
+                signature.signature.as_mut_ptr(),
+                max_count,
+                args.0.as_mut_ptr(),
+                offset,
+                count_buffer,
+                count_offset,
+            );
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Backend independent pipeline layout descriptions.
+
+use std::ops::Range;
+
+#[derive(Debug, Clone)]
+pub enum LayoutDesc {
+    Constant { space: u32, binding: u32, num: u32 },
+    Descriptors(Vec<BindingDesc>),
+}
+
+/// Resource kind behind a shader resource or unordered access view.
+///
+/// D3D12 doesn't distinguish views in the layout, Vulkan requires a
+/// matching descriptor type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ViewTy {
+    /// `Texture2D` or `RWTexture2D`.
+    Texture,
+    /// `Buffer<T>` or `RWBuffer<T>`.
+    TypedBuffer,
+    /// `StructuredBuffer<T>` or `RWStructuredBuffer<T>`.
+    StructuredBuffer,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DescriptorTy {
+    SRV(ViewTy),
+    UAV(ViewTy),
+    CBV,
+    Sampler,
+}
+
+#[derive(Debug, Clone)]
+pub struct BindingDesc {
+    pub ty: DescriptorTy,
+    pub space: u32,
+    pub bindings: Range<u32>,
+}
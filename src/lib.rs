@@ -1,60 +1,95 @@
+//! GPU vector rasterization.
+//!
+//! Path processing (`svg`, `GpuData`, `Canvas`), layout descriptions, copy
+//! footprints, resource state tracking, render graph compilation and trace
+//! export are platform independent. The D3D12 device, command, descriptor and
+//! window system code is only available with the `d3d12` feature (enabled by
+//! default), the Vulkan backend with the `vulkan` feature.
+
+mod adapter;
+pub mod backend;
+mod canvas;
+#[cfg(feature = "d3d12")]
+mod com;
+#[cfg(feature = "d3d12")]
 mod command;
+#[cfg(feature = "d3d12")]
 mod debug;
+#[cfg(any(feature = "d3d12", test))]
+mod deletion;
+#[cfg(feature = "d3d12")]
 mod descriptor;
+#[cfg(feature = "d3d12")]
 mod device;
 mod error;
+mod footprint;
+mod frame;
+mod graph;
+#[cfg(feature = "d3d12")]
+mod indirect;
+mod layout;
+#[cfg(feature = "d3d12")]
+mod lost;
+mod marker;
+#[cfg(feature = "d3d12")]
 mod pipeline;
+#[cfg(feature = "d3d12")]
+mod pool;
+mod profiler;
+#[cfg(feature = "d3d12")]
 mod query;
+#[cfg(feature = "d3d12")]
+mod queue;
+#[cfg(feature = "d3d12")]
 mod resource;
 mod svg;
+mod svg_export;
+mod trace;
+mod tracker;
+#[cfg(feature = "d3d12")]
 mod wsi;
 
+pub use crate::adapter::*;
+pub use crate::backend::{Extent, HeapType};
+pub use crate::canvas::*;
+#[cfg(feature = "d3d12")]
 pub use crate::command::*;
+#[cfg(feature = "d3d12")]
 pub use crate::debug::*;
+#[cfg(feature = "d3d12")]
 pub use crate::descriptor::*;
+#[cfg(feature = "d3d12")]
 pub use crate::device::*;
 pub use crate::error::*;
+pub use crate::footprint::*;
+pub use crate::frame::*;
+pub use crate::graph::*;
+#[cfg(feature = "d3d12")]
+pub use crate::indirect::*;
+pub use crate::layout::*;
+#[cfg(feature = "d3d12")]
+pub use crate::lost::LostCallback;
+pub use crate::marker::*;
+#[cfg(feature = "d3d12")]
 pub use crate::pipeline::*;
+#[cfg(feature = "d3d12")]
+pub use crate::pool::*;
+pub use crate::profiler::*;
+#[cfg(feature = "d3d12")]
 pub use crate::query::*;
+#[cfg(feature = "d3d12")]
+pub use crate::queue::*;
+#[cfg(feature = "d3d12")]
 pub use crate::resource::*;
 pub use crate::svg::*;
+pub use crate::svg_export::*;
+pub use crate::trace::*;
+pub use crate::tracker::*;
+#[cfg(feature = "d3d12")]
 pub use crate::wsi::*;
 
-pub struct Semaphore {
-    fence: d3d12::Fence,
-    event: d3d12::Event,
-}
-
-impl Semaphore {
-    pub fn wait(&self, timestamp: u64) {
-        self.fence.set_event_on_completion(self.event, timestamp);
-        self.event.wait(!0);
-    }
-}
-
-pub struct Queue {
-    queue: d3d12::CommandQueue,
-}
-
-impl Queue {
-    pub fn signal(&self, semaphore: &Semaphore, value: u64) {
-        self.queue.signal(semaphore.fence, value);
-    }
-
-    pub fn submit(&self, cmd_buffers: &[&CommandBuffer]) {
-        let cmd_lists = cmd_buffers
-            .iter()
-            .map(|buffer| buffer.command_list())
-            .collect::<Vec<_>>();
-        self.queue.execute_command_lists(&cmd_lists);
-    }
-
-    pub fn timing_frequency(&self) -> u64 {
-        let mut freq = 0u64;
-        unsafe { self.queue.GetTimestampFrequency(&mut freq); }
-        freq
-    }
-}
+pub use bytemuck;
+pub use pathbreaker::kurbo;
 
 pub unsafe fn as_u8_slice<T>(data: &[T]) -> &[u8] {
     let len = std::mem::size_of::<T>() * data.len();
@@ -0,0 +1,162 @@
+//! Device removal detection.
+//!
+//! Device removal is detected on `Queue::submit`, `Swapchain::present`,
+//! `Semaphore::wait` and failing API calls, which return `Error::DeviceLost`
+//! with the removal reason.
+//!
+//! Recovery follows these steps:
+//!
+//! 1. Register a callback with `Device::set_lost_callback`, it's invoked once
+//!    on the first detection of the removal.
+//! 2. Stop recording and submitting work. All objects created from the lost
+//!    device are unusable and need to be dropped.
+//! 3. Create a new `Device` and re-create queues, swapchain, pipelines and
+//!    resources, re-uploading any resource content.
+//!
+//! `Device::simulate_removal` marks the device as lost without affecting the
+//! underlying driver, allowing to exercise the recovery path.
+
+use crate::{check_hresult, Error, HResult};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Mutex;
+use winapi::shared::winerror::DXGI_ERROR_DEVICE_REMOVED;
+
+/// Callback invoked with the removal reason on device loss.
+pub type LostCallback = Box<dyn FnMut(HResult) + Send>;
+
+/// Device removal state shared between the device and its queues, semaphores and swapchains.
+pub(crate) struct DeviceState {
+    device: d3d12::Device,
+    simulated: AtomicI32,
+    notified: AtomicBool,
+    // Callback together with the number of `set_callback` calls.
+    callback: Mutex<(u64, Option<LostCallback>)>,
+}
+
+impl DeviceState {
+    pub(crate) fn new(device: d3d12::Device) -> Self {
+        DeviceState {
+            device,
+            simulated: AtomicI32::new(0),
+            notified: AtomicBool::new(false),
+            callback: Mutex::new((0, None)),
+        }
+    }
+
+    pub(crate) fn device(&self) -> d3d12::Device {
+        self.device
+    }
+
+    /// Removal reason if the device has been lost.
+    pub(crate) fn removed_reason(&self) -> Option<HResult> {
+        let simulated = self.simulated.load(Ordering::Acquire);
+        if simulated != 0 {
+            return Some(simulated);
+        }
+
+        let reason = unsafe { self.device.GetDeviceRemovedReason() };
+        if reason != 0 {
+            Some(reason)
+        } else {
+            None
+        }
+    }
+
+    /// Check for device removal, notifying the lost callback on first detection.
+    pub(crate) fn check_lost(&self) -> Result<(), Error> {
+        match self.removed_reason() {
+            Some(_) => Err(self.lost(DXGI_ERROR_DEVICE_REMOVED)),
+            None => Ok(()),
+        }
+    }
+
+    /// Convert an `HRESULT` into a result, querying the removal reason on device loss.
+    pub(crate) fn check(&self, hr: HResult) -> Result<(), Error> {
+        check_hresult(hr).map_err(|err| match err {
+            Error::DeviceLost { hr, .. } => self.lost(hr),
+            err => err,
+        })
+    }
+
+    // Error for a call failing with `hr` due to device removal.
+    fn lost(&self, hr: HResult) -> Error {
+        let reason = self.removed_reason().unwrap_or(hr);
+        self.notify(reason);
+        Error::DeviceLost { hr, reason }
+    }
+
+    pub(crate) fn set_callback(&self, callback: Option<LostCallback>) {
+        let mut slot = self.callback.lock().unwrap();
+        slot.0 += 1;
+        slot.1 = callback;
+    }
+
+    pub(crate) fn simulate_removal(&self, reason: Option<HResult>) {
+        let reason = reason.unwrap_or(DXGI_ERROR_DEVICE_REMOVED);
+        self.simulated.store(reason, Ordering::Release);
+        self.notify(reason);
+    }
+
+    fn notify(&self, reason: HResult) {
+        if self.notified.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // Invoked without holding the lock, the callback may replace itself.
+        let (generation, callback) = {
+            let mut slot = self.callback.lock().unwrap();
+            (slot.0, slot.1.take())
+        };
+        if let Some(mut callback) = callback {
+            callback(reason);
+            let mut slot = self.callback.lock().unwrap();
+            if slot.0 == generation {
+                slot.1 = Some(callback);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AdapterSelection, CmdBufferTy, Device, DeviceCreateFlags, Error, HResult};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const DXGI_ERROR_DEVICE_HUNG: HResult = 0x887A_0006_u32 as _;
+
+    fn is_lost(result: Result<(), Error>) -> bool {
+        matches!(
+            result,
+            Err(Error::DeviceLost {
+                reason: DXGI_ERROR_DEVICE_HUNG,
+                ..
+            })
+        )
+    }
+
+    #[test]
+    fn simulated_removal() {
+        let device = Device::new(DeviceCreateFlags::empty(), AdapterSelection::default()).unwrap();
+        let queue = device.create_queue(CmdBufferTy::Direct).unwrap();
+        let semaphore = device.create_semaphore().unwrap();
+
+        let notified = Arc::new(AtomicUsize::new(0));
+        let callback_notified = notified.clone();
+        device.set_lost_callback(move |reason| {
+            assert_eq!(reason, DXGI_ERROR_DEVICE_HUNG);
+            callback_notified.fetch_add(1, Ordering::SeqCst);
+        });
+
+        queue.signal(&semaphore, 1).unwrap();
+        semaphore.wait(1).unwrap();
+        queue.submit(&[]).unwrap();
+        assert_eq!(notified.load(Ordering::SeqCst), 0);
+
+        device.simulate_removal(Some(DXGI_ERROR_DEVICE_HUNG));
+        assert_eq!(device.removed_reason(), Some(DXGI_ERROR_DEVICE_HUNG));
+        assert!(is_lost(semaphore.wait(1)));
+        assert!(is_lost(queue.submit(&[])));
+        assert!(is_lost(device.wait_idle()));
+        assert_eq!(notified.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,73 @@
+//! Debug markers and named regions.
+//!
+//! Labels recorded into command buffers are mirrored into a `MarkerStream`,
+//! allowing profilers and crash breadcrumbs to refer to the same names as
+//! external tools like PIX.
+
+/// Marker color as `0xRRGGBB`.
+pub type MarkerColor = u32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarkerEvent {
+    BeginRegion { name: String, color: MarkerColor },
+    EndRegion,
+    Marker { name: String },
+}
+
+/// Markers of a single command buffer in recording order.
+#[derive(Debug, Clone, Default)]
+pub struct MarkerStream {
+    events: Vec<MarkerEvent>,
+    // Indices of the `BeginRegion` events of the open regions.
+    open: Vec<usize>,
+}
+
+impl MarkerStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.open.clear();
+    }
+
+    pub fn begin_region(&mut self, name: &str, color: MarkerColor) {
+        self.open.push(self.events.len());
+        self.events.push(MarkerEvent::BeginRegion {
+            name: name.to_string(),
+            color,
+        });
+    }
+
+    pub fn end_region(&mut self) {
+        assert!(
+            self.open.pop().is_some(),
+            "`end_region` without matching `begin_region`"
+        );
+        self.events.push(MarkerEvent::EndRegion);
+    }
+
+    pub fn insert_marker(&mut self, name: &str) {
+        self.events.push(MarkerEvent::Marker {
+            name: name.to_string(),
+        });
+    }
+
+    pub fn events(&self) -> &[MarkerEvent] {
+        &self.events
+    }
+
+    /// Number of regions not ended yet.
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Names of the open regions, outermost first.
+    pub fn open_regions(&self) -> impl Iterator<Item = &str> + '_ {
+        self.open.iter().map(move |&i| match &self.events[i] {
+            MarkerEvent::BeginRegion { name, .. } => name.as_str(),
+            _ => unreachable!(),
+        })
+    }
+}
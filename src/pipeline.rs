@@ -1,8 +1,48 @@
-use crate::{Device, Error};
-use std::{fs::File, io::Read, ops::Range, path::Path};
+use crate::deletion::Dx12DeletionQueue;
+use crate::{DescriptorTy, Device, Error, LayoutDesc};
+use std::{fs::File, io::Read, path::Path, sync::Arc};
 
-pub use d3d12::PipelineState as Pipeline;
-pub use d3d12::RootSignature as PipelineLayout;
+/// Compute pipeline, released once the GPU finished using it after drop.
+pub struct Pipeline(
+    pub(crate) d3d12::PipelineState,
+    pub(crate) Arc<Dx12DeletionQueue>,
+);
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        unsafe { self.1.defer_release(self.0) };
+    }
+}
+
+/// Kind of a root parameter, used for validating bindings on command recording.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum LayoutParameter {
+    Constants { num: u32 },
+    Table,
+}
+
+pub struct PipelineLayout {
+    pub(crate) signature: d3d12::RootSignature,
+    pub(crate) parameters: Arc<[LayoutParameter]>,
+    deletion: Arc<Dx12DeletionQueue>,
+}
+
+impl Drop for PipelineLayout {
+    fn drop(&mut self) {
+        unsafe { self.deletion.defer_release(self.signature) };
+    }
+}
+
+impl PipelineLayout {
+    pub fn signature(&self) -> d3d12::RootSignature {
+        self.signature
+    }
+
+    /// Number of root parameters, one for each `LayoutDesc`.
+    pub fn num_parameters(&self) -> usize {
+        self.parameters.len()
+    }
+}
 
 pub struct Shader {
     data: Vec<u8>,
@@ -36,16 +76,15 @@ impl Shader {
     }
 }
 
-pub enum LayoutDesc {
-    Constant { space: u32, binding: u32, num: u32 },
-    Descriptors(Vec<BindingDesc>),
-}
-
-pub use d3d12::DescriptorRangeType as DescriptorTy;
-pub struct BindingDesc {
-    pub ty: DescriptorTy,
-    pub space: u32,
-    pub bindings: Range<u32>,
+impl DescriptorTy {
+    fn as_d3d12(&self) -> d3d12::DescriptorRangeType {
+        match self {
+            DescriptorTy::SRV(_) => d3d12::DescriptorRangeType::SRV,
+            DescriptorTy::UAV(_) => d3d12::DescriptorRangeType::UAV,
+            DescriptorTy::CBV => d3d12::DescriptorRangeType::CBV,
+            DescriptorTy::Sampler => d3d12::DescriptorRangeType::Sampler,
+        }
+    }
 }
 
 impl Device {
@@ -61,13 +100,14 @@ impl Device {
         let mut last_range = 0;
         let mut descriptor_ranges = Vec::with_capacity(num_ranges);
         let mut parameters = Vec::new();
+        let mut layout_parameters = Vec::with_capacity(descs.len());
 
         for desc in descs {
             match desc {
                 LayoutDesc::Descriptors(ref bindings) => {
                     for binding in bindings {
                         descriptor_ranges.push(d3d12::DescriptorRange::new(
-                            binding.ty,
+                            binding.ty.as_d3d12(),
                             binding.bindings.end - binding.bindings.start,
                             d3d12::Binding {
                                 register: binding.bindings.start,
@@ -82,6 +122,7 @@ impl Device {
                         &descriptor_ranges[last_range..cur_range],
                     ));
                     last_range = cur_range;
+                    layout_parameters.push(LayoutParameter::Table);
                 }
                 LayoutDesc::Constant {
                     space,
@@ -96,17 +137,34 @@ impl Device {
                         },
                         *num,
                     ));
+                    layout_parameters.push(LayoutParameter::Constants { num: *num });
                 }
             }
         }
 
-        let ((signature, _), _) = d3d12::RootSignature::serialize(
+        let ((signature, error), hr) = d3d12::RootSignature::serialize(
             d3d12::RootSignatureVersion::V1_0,
             &parameters,
             &[], // TODO
             d3d12::RootSignatureFlags::empty(),
         );
-        let (layout, _) = self.create_root_signature(signature, 0);
-        Ok(layout)
+        if !error.is_null() {
+            unsafe {
+                error.destroy();
+            }
+        }
+        self.check(hr)?;
+
+        let (layout, hr) = self.create_root_signature(signature, 0);
+        unsafe {
+            signature.destroy();
+        }
+        self.check(hr)?;
+
+        Ok(PipelineLayout {
+            signature: layout,
+            parameters: layout_parameters.into(),
+            deletion: self.deletion.clone(),
+        })
     }
 }
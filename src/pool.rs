@@ -0,0 +1,113 @@
+//! Command buffer recycling.
+//!
+//! A `CommandPool` hands out command buffers and takes them back together with
+//! the semaphore value signaled after their submission. Buffers are only handed
+//! out again once the semaphore reached this value, so resetting their
+//! allocators in `begin` never races with the GPU.
+//!
+//! The pool can be shared between threads, each thread acquiring and recording
+//! its own command buffers.
+
+use crate::com::Owned;
+use crate::lost::DeviceState;
+use crate::{CmdBufferTy, CommandBuffer, Device, Error, Semaphore};
+use std::sync::{Arc, Mutex};
+use winapi::um::d3d12::ID3D12Fence;
+
+// Command buffers in flight, waiting for `fence` to reach `value`.
+struct Retired {
+    fence: Owned<ID3D12Fence>,
+    value: u64,
+    cmd_buffers: Vec<CommandBuffer>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    free: Vec<CommandBuffer>,
+    retired: Vec<Retired>,
+}
+
+pub struct CommandPool {
+    ty: CmdBufferTy,
+    dispatch_signature: d3d12::CommandSignature,
+    state: Arc<DeviceState>,
+    inner: Mutex<PoolState>,
+}
+
+// Command buffers and fences are only accessed while holding the mutex.
+unsafe impl Send for CommandPool {}
+unsafe impl Sync for CommandPool {}
+
+impl Device {
+    pub fn create_command_pool(&self, ty: CmdBufferTy) -> CommandPool {
+        CommandPool {
+            ty,
+            dispatch_signature: self.dispatch_signature,
+            state: self.state.clone(),
+            inner: Mutex::new(PoolState::default()),
+        }
+    }
+}
+
+impl CommandPool {
+    pub fn ty(&self) -> CmdBufferTy {
+        self.ty
+    }
+
+    /// Take a command buffer which isn't in use by the GPU anymore.
+    ///
+    /// Allocates a new command buffer if none has been recycled yet.
+    pub fn acquire(&self) -> Result<CommandBuffer, Error> {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let PoolState { free, retired } = &mut *inner;
+            retired.retain_mut(|retired| {
+                // Fences are signaled with `u64::MAX` on device removal, recycling all buffers.
+                let completed = retired.fence.get_value() >= retired.value;
+                if completed {
+                    free.append(&mut retired.cmd_buffers);
+                }
+                !completed
+            });
+            if let Some(cmd_buffer) = free.pop() {
+                return Ok(cmd_buffer);
+            }
+        }
+
+        CommandBuffer::new(
+            self.state.device(),
+            self.dispatch_signature,
+            self.ty,
+            |hr| self.state.check(hr),
+        )
+    }
+
+    /// Return command buffers submitted before signaling `semaphore` with `value`.
+    ///
+    /// The buffers are recycled once the semaphore reached `value`.
+    pub fn retire<I>(&self, cmd_buffers: I, semaphore: &Semaphore, value: u64)
+    where
+        I: IntoIterator<Item = CommandBuffer>,
+    {
+        let cmd_buffers = cmd_buffers.into_iter().collect::<Vec<_>>();
+        if cmd_buffers.is_empty() {
+            return;
+        }
+        self.inner.lock().unwrap().retired.push(Retired {
+            fence: semaphore.fence.clone(),
+            value,
+            cmd_buffers,
+        });
+    }
+
+    /// Number of command buffers still in flight.
+    pub fn num_in_flight(&self) -> usize {
+        self.inner
+            .lock()
+            .unwrap()
+            .retired
+            .iter()
+            .map(|retired| retired.cmd_buffers.len())
+            .sum()
+    }
+}
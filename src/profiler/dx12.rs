@@ -0,0 +1,203 @@
+//! D3D12 timer query backed profiler.
+
+use super::*;
+use crate::backend::{BackendDevice, HeapType};
+use crate::{Buffer, CommandBuffer, Device, Error, MarkerColor, Queue, TimerQueries};
+use std::cell::RefCell;
+
+const SCOPE_MARKER_COLOR: MarkerColor = 0xc08040;
+/// Number of frames the rolling statistics are computed over.
+const HISTORY_WINDOW: usize = 60;
+
+// Queries and readback of a frame in flight.
+struct ProfilerFrame {
+    queries: TimerQueries,
+    readback: Buffer,
+    recorder: ScopeRecorder,
+    // Timestamps are copied to the readback buffer, resolved on the slot's reuse.
+    pending: bool,
+}
+
+struct ProfilerState {
+    frames: Vec<ProfilerFrame>,
+    current: usize,
+    // Scopes of the current frame are recorded.
+    active: bool,
+    // Incremented on each `begin_frame`, scopes only end within their frame.
+    generation: u64,
+    // Slot of the ended frame awaiting its submission result.
+    ended: Option<usize>,
+    history: ProfileHistory,
+    results: Vec<ProfileScope>,
+}
+
+/// Profiler with nested named scopes measured by timestamp queries.
+///
+/// Each frame in flight owns its queries and readback buffer, indexed by the
+/// slot of a `FrameRing`. Results of a frame are read back when its slot is
+/// reused, the ring already waited for the GPU to finish the slot's frame.
+///
+/// Scopes still alive at the next `begin_frame` are discarded with their
+/// frame, dropping them afterwards doesn't record an end timestamp.
+pub struct GpuProfiler {
+    frequency: u64,
+    state: RefCell<ProfilerState>,
+}
+
+/// Ends the profiler scope on drop, see `GpuProfiler::scope`.
+pub struct ProfilerScope<'a> {
+    profiler: &'a GpuProfiler,
+    cmd_buffer: &'a CommandBuffer,
+    // Generation of the frame the scope has been recorded in.
+    generation: Option<u64>,
+}
+
+impl Drop for ProfilerScope<'_> {
+    fn drop(&mut self) {
+        self.profiler.end_scope(self.cmd_buffer, self.generation);
+    }
+}
+
+impl GpuProfiler {
+    /// Create a profiler with `max_queries` timestamps for each of `frames_in_flight` frames.
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        frames_in_flight: usize,
+        max_queries: u32,
+    ) -> Result<Self, Error> {
+        let frames = (0..frames_in_flight)
+            .map(|_| {
+                Ok(ProfilerFrame {
+                    queries: device.create_timer_queries(max_queries as _)?,
+                    readback: device.create_buffer(max_queries as u64 * 8, HeapType::Readback)?,
+                    recorder: ScopeRecorder::new(max_queries),
+                    pending: false,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(GpuProfiler {
+            frequency: queue.timing_frequency(),
+            state: RefCell::new(ProfilerState {
+                frames,
+                current: 0,
+                active: false,
+                generation: 0,
+                ended: None,
+                history: ProfileHistory::new(HISTORY_WINDOW),
+                results: Vec::new(),
+            }),
+        })
+    }
+
+    /// Begin a frame in `slot`, resolving the results of the slot's previous frame.
+    ///
+    /// The GPU must have finished the previous frame of the slot, e.g the
+    /// current slot of a `FrameRing` after its `begin_frame`. Scopes of a
+    /// previous frame which hasn't been ended, e.g due to an error while
+    /// recording, are discarded.
+    pub fn begin_frame(&self, slot: usize) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        assert!(
+            slot < state.frames.len(),
+            "slot {} out of range, profiler has {} frames",
+            slot,
+            state.frames.len()
+        );
+        if state.active {
+            state.frames[state.current].recorder.reset();
+            state.active = false;
+        }
+        state.ended = None;
+
+        state.current = slot;
+        let frame = &mut state.frames[slot];
+        if frame.pending {
+            frame.pending = false;
+
+            let num_queries = frame.recorder.num_queries() as usize;
+            let mut timestamps = vec![0u64; num_queries];
+            frame
+                .readback
+                .copy_to_host(0, bytemuck::cast_slice_mut(&mut timestamps));
+            let mut results = frame.recorder.resolve(&timestamps, self.frequency);
+            state.history.update(&mut results);
+            state.results = results;
+        }
+
+        frame.recorder.reset();
+        state.active = true;
+        state.generation += 1;
+    }
+
+    /// Open a named scope, also recorded as marker region.
+    pub fn scope<'a>(&'a self, cmd_buffer: &'a CommandBuffer, name: &str) -> ProfilerScope<'a> {
+        cmd_buffer.begin_region(name, SCOPE_MARKER_COLOR);
+        let mut state = self.state.borrow_mut();
+        let mut generation = None;
+        if state.active {
+            let current = state.current;
+            let frame = &mut state.frames[current];
+            if let Some(query) = frame.recorder.begin(name) {
+                cmd_buffer.timestamp(&frame.queries, query as _);
+            }
+            generation = Some(state.generation);
+        }
+        ProfilerScope {
+            profiler: self,
+            cmd_buffer,
+            generation,
+        }
+    }
+
+    fn end_scope(&self, cmd_buffer: &CommandBuffer, generation: Option<u64>) {
+        let mut state = self.state.borrow_mut();
+        if state.active && generation == Some(state.generation) {
+            let current = state.current;
+            let frame = &mut state.frames[current];
+            if let Some(query) = frame.recorder.end() {
+                cmd_buffer.timestamp(&frame.queries, query as _);
+            }
+        }
+        cmd_buffer.end_region();
+    }
+
+    /// Resolve the queries of the frame into the readback buffer of its slot.
+    ///
+    /// The results are only read back once the command buffer has been
+    /// submitted successfully, see `frame_submitted`.
+    pub fn end_frame(&self, cmd_buffer: &CommandBuffer) {
+        let mut state = self.state.borrow_mut();
+        if !state.active {
+            return;
+        }
+        state.active = false;
+
+        let current = state.current;
+        let frame = &mut state.frames[current];
+        assert_eq!(frame.recorder.depth(), 0, "unterminated profiler scopes");
+        let num_queries = frame.recorder.num_queries() as usize;
+        if num_queries > 0 {
+            cmd_buffer.copy_timestamps(&frame.queries, 0..num_queries, &frame.readback, 0);
+        }
+        state.ended = Some(current);
+    }
+
+    /// Pass the submission result of the command buffer of the ended frame.
+    ///
+    /// Frames which failed to submit are never read back. Returns `result`.
+    pub fn frame_submitted(&self, result: Result<(), Error>) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        if let Some(slot) = state.ended.take() {
+            state.frames[slot].pending = result.is_ok();
+        }
+        result
+    }
+
+    /// Scopes of the latest resolved frame.
+    pub fn results(&self) -> Vec<ProfileScope> {
+        self.state.borrow().results.clone()
+    }
+}
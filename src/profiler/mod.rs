@@ -0,0 +1,333 @@
+//! Hierarchical GPU profiling.
+//!
+//! A `ScopeRecorder` assigns timestamp queries to nested named scopes of a
+//! frame. Once the timestamps are read back the scopes are resolved into a
+//! tree of `ProfileScope`s, `ProfileHistory` augments them with rolling
+//! statistics over the last frames.
+
+#[cfg(feature = "d3d12")]
+mod dx12;
+
+#[cfg(feature = "d3d12")]
+pub use self::dx12::*;
+
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Debug, Clone)]
+struct RecordedScope {
+    name: String,
+    parent: Option<usize>,
+    begin: u32,
+    end: Option<u32>,
+}
+
+/// Query allocation of the scopes of a single frame.
+#[derive(Debug, Clone)]
+pub struct ScopeRecorder {
+    max_queries: u32,
+    next_query: u32,
+    scopes: Vec<RecordedScope>,
+    // Open scopes, `None` for scopes dropped due to missing queries.
+    stack: Vec<Option<usize>>,
+}
+
+impl ScopeRecorder {
+    pub fn new(max_queries: u32) -> Self {
+        ScopeRecorder {
+            max_queries,
+            next_query: 0,
+            scopes: Vec::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.next_query = 0;
+        self.scopes.clear();
+        self.stack.clear();
+    }
+
+    /// Open a scope, returns the query for the begin timestamp.
+    ///
+    /// Scopes opened while running out of queries are dropped with all their children.
+    pub fn begin(&mut self, name: &str) -> Option<u32> {
+        let parent = match self.stack.last() {
+            Some(None) => {
+                self.stack.push(None);
+                return None;
+            }
+            Some(&Some(parent)) => Some(parent),
+            None => None,
+        };
+
+        // Each scope needs two queries.
+        if self.next_query + 2 > self.max_queries {
+            self.stack.push(None);
+            return None;
+        }
+
+        let begin = self.next_query;
+        self.next_query += 2;
+        self.stack.push(Some(self.scopes.len()));
+        self.scopes.push(RecordedScope {
+            name: name.to_string(),
+            parent,
+            begin,
+            end: None,
+        });
+        Some(begin)
+    }
+
+    /// Close the innermost scope, returns the query for the end timestamp.
+    pub fn end(&mut self) -> Option<u32> {
+        let scope = self.stack.pop().expect("`end` without matching `begin`")?;
+        let end = self.scopes[scope].begin + 1;
+        self.scopes[scope].end = Some(end);
+        Some(end)
+    }
+
+    /// Number of open scopes.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Number of queries written by the recorded scopes.
+    pub fn num_queries(&self) -> u32 {
+        self.next_query
+    }
+
+    /// Build the scope tree from the timestamps of the queries.
+    ///
+    /// `frequency` is the number of timestamp ticks per second.
+    pub fn resolve(&self, timestamps: &[u64], frequency: u64) -> Vec<ProfileScope> {
+        assert_eq!(self.depth(), 0, "unterminated scopes");
+        assert!(timestamps.len() >= self.next_query as usize);
+
+        let mut nodes = self
+            .scopes
+            .iter()
+            .map(|scope| {
+                let begin = timestamps[scope.begin as usize];
+                let end = timestamps[scope.end.unwrap() as usize];
+                ProfileScope {
+                    name: scope.name.clone(),
+                    begin,
+                    end,
+                    duration_ms: end.saturating_sub(begin) as f64 / frequency as f64 * 1000.0,
+                    stats: DurationStats::default(),
+                    children: Vec::new(),
+                }
+            })
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        // Children are recorded after their parents, attach them in reverse.
+        let mut roots = Vec::new();
+        for i in (0..nodes.len()).rev() {
+            let node = nodes[i].take().unwrap();
+            match self.scopes[i].parent {
+                Some(parent) => nodes[parent].as_mut().unwrap().children.insert(0, node),
+                None => roots.insert(0, node),
+            }
+        }
+        roots
+    }
+}
+
+/// Statistics over the last frames.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct DurationStats {
+    pub mean_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub samples: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileScope {
+    pub name: String,
+    /// GPU timestamps in ticks of the queue's timing frequency.
+    pub begin: u64,
+    pub end: u64,
+    pub duration_ms: f64,
+    pub stats: DurationStats,
+    pub children: Vec<ProfileScope>,
+}
+
+impl ProfileScope {
+    /// Find a scope by its `/` separated path of names, e.g `frame/raster`.
+    pub fn find<'a>(scopes: &'a [ProfileScope], path: &str) -> Option<&'a ProfileScope> {
+        let (name, rest) = match path.find('/') {
+            Some(i) => (&path[..i], Some(&path[i + 1..])),
+            None => (path, None),
+        };
+        let scope = scopes.iter().find(|scope| scope.name == name)?;
+        match rest {
+            Some(rest) => Self::find(&scope.children, rest),
+            None => Some(scope),
+        }
+    }
+}
+
+/// Rolling window of durations per scope path.
+#[derive(Debug, Clone)]
+pub struct ProfileHistory {
+    window: usize,
+    samples: HashMap<String, VecDeque<f64>>,
+}
+
+impl ProfileHistory {
+    pub fn new(window: usize) -> Self {
+        assert!(window > 0);
+        ProfileHistory {
+            window,
+            samples: HashMap::new(),
+        }
+    }
+
+    /// Add the durations of a frame and update the statistics of its scopes.
+    pub fn update(&mut self, scopes: &mut [ProfileScope]) {
+        self.update_level(scopes, "");
+    }
+
+    fn update_level(&mut self, scopes: &mut [ProfileScope], prefix: &str) {
+        for scope in scopes {
+            let path = format!("{}{}", prefix, scope.name);
+            let samples = self.samples.entry(path.clone()).or_default();
+            if samples.len() == self.window {
+                samples.pop_front();
+            }
+            samples.push_back(scope.duration_ms);
+
+            scope.stats = DurationStats {
+                mean_ms: samples.iter().sum::<f64>() / samples.len() as f64,
+                min_ms: samples.iter().cloned().fold(f64::INFINITY, f64::min),
+                max_ms: samples.iter().cloned().fold(0.0, f64::max),
+                samples: samples.len(),
+            };
+            self.update_level(&mut scope.children, &format!("{}/", path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(name: &str, duration_ms: f64, children: Vec<ProfileScope>) -> ProfileScope {
+        ProfileScope {
+            name: name.to_string(),
+            begin: 0,
+            end: 0,
+            duration_ms,
+            stats: DurationStats::default(),
+            children,
+        }
+    }
+
+    #[test]
+    fn nested_scopes() {
+        let mut recorder = ScopeRecorder::new(8);
+        assert_eq!(recorder.begin("frame"), Some(0));
+        assert_eq!(recorder.begin("shadow"), Some(2));
+        assert_eq!(recorder.end(), Some(3));
+        assert_eq!(recorder.begin("lighting"), Some(4));
+        assert_eq!(recorder.begin("tiles"), Some(6));
+        assert_eq!(recorder.depth(), 3);
+        assert_eq!(recorder.end(), Some(7));
+        assert_eq!(recorder.end(), Some(5));
+        assert_eq!(recorder.end(), Some(1));
+        assert_eq!(recorder.num_queries(), 8);
+
+        // 1000 ticks per second, one tick is a millisecond.
+        let scopes = recorder.resolve(&[0, 100, 10, 30, 40, 90, 50, 70], 1000);
+        assert_eq!(scopes.len(), 1);
+        let frame = &scopes[0];
+        assert_eq!((frame.begin, frame.end, frame.duration_ms), (0, 100, 100.0));
+        let children = frame
+            .children
+            .iter()
+            .map(|c| &c.name[..])
+            .collect::<Vec<_>>();
+        assert_eq!(children, ["shadow", "lighting"]);
+        assert_eq!(
+            ProfileScope::find(&scopes, "frame/lighting/tiles")
+                .unwrap()
+                .duration_ms,
+            20.0
+        );
+        assert!(ProfileScope::find(&scopes, "frame/tiles").is_none());
+
+        recorder.reset();
+        assert_eq!(recorder.num_queries(), 0);
+        assert_eq!(recorder.begin("frame"), Some(0));
+    }
+
+    #[test]
+    fn query_exhaustion() {
+        let mut recorder = ScopeRecorder::new(5);
+        assert_eq!(recorder.begin("frame"), Some(0));
+        assert_eq!(recorder.begin("a"), Some(2));
+        assert_eq!(recorder.end(), Some(3));
+
+        // Dropped with all its children, even if queries would be available for them.
+        assert_eq!(recorder.begin("b"), None);
+        assert_eq!(recorder.begin("c"), None);
+        assert_eq!(recorder.end(), None);
+        assert_eq!(recorder.end(), None);
+        assert_eq!(recorder.end(), Some(1));
+        assert_eq!(recorder.num_queries(), 4);
+
+        let scopes = recorder.resolve(&[0, 10, 2, 4], 1000);
+        assert_eq!(scopes[0].children.len(), 1);
+        assert!(ProfileScope::find(&scopes, "frame/b").is_none());
+    }
+
+    #[test]
+    fn rolling_stats() {
+        let mut history = ProfileHistory::new(3);
+        let mut frame = |durations: [f64; 2]| {
+            let mut scopes = vec![scope(
+                "frame",
+                durations[0],
+                vec![scope("pass", durations[1], Vec::new())],
+            )];
+            history.update(&mut scopes);
+            scopes
+        };
+
+        frame([4.0, 1.0]);
+        frame([8.0, 2.0]);
+        let scopes = frame([6.0, 3.0]);
+        assert_eq!(
+            scopes[0].stats,
+            DurationStats {
+                mean_ms: 6.0,
+                min_ms: 4.0,
+                max_ms: 8.0,
+                samples: 3,
+            }
+        );
+        assert_eq!(
+            ProfileScope::find(&scopes, "frame/pass").unwrap().stats,
+            DurationStats {
+                mean_ms: 2.0,
+                min_ms: 1.0,
+                max_ms: 3.0,
+                samples: 3,
+            }
+        );
+
+        // The oldest sample leaves the window.
+        let scopes = frame([10.0, 6.0]);
+        assert_eq!(
+            scopes[0].stats,
+            DurationStats {
+                mean_ms: 8.0,
+                min_ms: 6.0,
+                max_ms: 10.0,
+                samples: 3,
+            }
+        );
+    }
+}
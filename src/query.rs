@@ -1,10 +1,203 @@
-use crate::{Device, Error};
+use crate::deletion::Dx12DeletionQueue;
+use crate::{Buffer, CommandBuffer, Device, Error};
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+use winapi::um::d3d12::*;
 
-pub struct TimerQueries(pub(crate) d3d12::QueryHeap);
+pub struct TimerQueries(pub(crate) d3d12::QueryHeap, Arc<Dx12DeletionQueue>);
+
+pub struct PipelineStatisticsQueries(pub(crate) d3d12::QueryHeap, Arc<Dx12DeletionQueue>);
+
+/// Queries counting the samples passing depth and stencil tests.
+pub struct OcclusionQueries(pub(crate) d3d12::QueryHeap, Arc<Dx12DeletionQueue>);
+
+impl Drop for TimerQueries {
+    fn drop(&mut self) {
+        unsafe { self.1.defer_release(self.0) };
+    }
+}
+
+impl Drop for PipelineStatisticsQueries {
+    fn drop(&mut self) {
+        unsafe { self.1.defer_release(self.0) };
+    }
+}
+
+impl Drop for OcclusionQueries {
+    fn drop(&mut self) {
+        unsafe { self.1.defer_release(self.0) };
+    }
+}
+
+/// Resolved pipeline statistics (`D3D12_QUERY_DATA_PIPELINE_STATISTICS`).
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    pub ia_vertices: u64,
+    pub ia_primitives: u64,
+    pub vs_invocations: u64,
+    pub gs_invocations: u64,
+    pub gs_primitives: u64,
+    pub clipper_invocations: u64,
+    pub clipper_primitives: u64,
+    pub ps_invocations: u64,
+    pub hs_invocations: u64,
+    pub ds_invocations: u64,
+    pub cs_invocations: u64,
+}
+
+unsafe impl bytemuck::Zeroable for PipelineStatistics {}
+unsafe impl bytemuck::Pod for PipelineStatistics {}
+
+// Resolved queries are copied from readback buffers as is.
+const _: () = assert!(
+    mem::size_of::<PipelineStatistics>() == mem::size_of::<D3D12_QUERY_DATA_PIPELINE_STATISTICS>()
+);
+
+impl PipelineStatistics {
+    /// Size of a resolved query in bytes.
+    pub const SIZE: u32 = mem::size_of::<PipelineStatistics>() as _;
+
+    /// Read `num` consecutive resolved queries from a readback buffer at `offset`.
+    pub fn read(buffer: &Buffer, offset: u64, num: usize) -> Vec<Self> {
+        assert!(
+            offset + num as u64 * Self::SIZE as u64 <= buffer.size(),
+            "queries out of buffer range"
+        );
+        let mut statistics = vec![Self::default(); num];
+        buffer.copy_to_host(offset as _, bytemuck::cast_slice_mut(&mut statistics));
+        statistics
+    }
+}
+
+/// Read `num` consecutive resolved occlusion queries (sample counts) from a readback buffer at `offset`.
+pub fn read_occlusion(buffer: &Buffer, offset: u64, num: usize) -> Vec<u64> {
+    assert!(
+        offset + num as u64 * 8 <= buffer.size(),
+        "queries out of buffer range"
+    );
+    let mut samples = vec![0u64; num];
+    buffer.copy_to_host(offset as _, bytemuck::cast_slice_mut(&mut samples));
+    samples
+}
 
 impl Device {
     pub fn create_timer_queries(&self, num: usize) -> Result<TimerQueries, Error> {
-        let (heap, _) = self.create_query_heap(d3d12::QueryHeapType::Timestamp, num as _, 0);
-        Ok(TimerQueries(heap))
+        let (heap, hr) = self.create_query_heap(d3d12::QueryHeapType::Timestamp, num as _, 0);
+        self.check(hr)?;
+        Ok(TimerQueries(heap, self.deletion.clone()))
+    }
+
+    pub fn create_pipeline_statistics_queries(
+        &self,
+        num: usize,
+    ) -> Result<PipelineStatisticsQueries, Error> {
+        let (heap, hr) =
+            self.create_query_heap(d3d12::QueryHeapType::PipelineStatistics, num as _, 0);
+        self.check(hr)?;
+        Ok(PipelineStatisticsQueries(heap, self.deletion.clone()))
+    }
+
+    pub fn create_occlusion_queries(&self, num: usize) -> Result<OcclusionQueries, Error> {
+        let (heap, hr) = self.create_query_heap(d3d12::QueryHeapType::Occlusion, num as _, 0);
+        self.check(hr)?;
+        Ok(OcclusionQueries(heap, self.deletion.clone()))
+    }
+}
+
+impl CommandBuffer {
+    fn resolve_queries(
+        &self,
+        heap: d3d12::QueryHeap,
+        ty: D3D12_QUERY_TYPE,
+        queries: Range<usize>,
+        buffer: &Buffer,
+        buffer_offset: u64,
+    ) {
+        self.transition_buffer(buffer, D3D12_RESOURCE_STATE_COPY_DEST);
+        self.flush_barriers();
+        unsafe {
+            self.cmd_buffer.ResolveQueryData(
+                heap.as_mut_ptr(),
+                ty,
+                queries.start as _,
+                (queries.end - queries.start) as _,
+                buffer.resource().as_mut_ptr(),
+                buffer_offset,
+            );
+        }
+    }
+
+    /// Start counting pipeline statistics, not supported on copy queues.
+    pub fn begin_pipeline_statistics(&self, heap: &PipelineStatisticsQueries, query: usize) {
+        unsafe {
+            self.cmd_buffer.BeginQuery(
+                heap.0.as_mut_ptr(),
+                D3D12_QUERY_TYPE_PIPELINE_STATISTICS,
+                query as _,
+            );
+        }
+    }
+
+    pub fn end_pipeline_statistics(&self, heap: &PipelineStatisticsQueries, query: usize) {
+        unsafe {
+            self.cmd_buffer.EndQuery(
+                heap.0.as_mut_ptr(),
+                D3D12_QUERY_TYPE_PIPELINE_STATISTICS,
+                query as _,
+            );
+        }
+    }
+
+    /// Resolve queries into `buffer`, each taking `PipelineStatistics::SIZE` bytes.
+    pub fn copy_pipeline_statistics(
+        &self,
+        heap: &PipelineStatisticsQueries,
+        queries: Range<usize>,
+        buffer: &Buffer,
+        buffer_offset: u64,
+    ) {
+        assert_eq!(buffer_offset % 8, 0, "query offsets must be 8 byte aligned");
+        self.resolve_queries(
+            heap.0,
+            D3D12_QUERY_TYPE_PIPELINE_STATISTICS,
+            queries,
+            buffer,
+            buffer_offset,
+        );
+    }
+
+    /// Start counting samples, only supported on direct queues.
+    pub fn begin_occlusion(&self, heap: &OcclusionQueries, query: usize) {
+        unsafe {
+            self.cmd_buffer
+                .BeginQuery(heap.0.as_mut_ptr(), D3D12_QUERY_TYPE_OCCLUSION, query as _);
+        }
+    }
+
+    pub fn end_occlusion(&self, heap: &OcclusionQueries, query: usize) {
+        unsafe {
+            self.cmd_buffer
+                .EndQuery(heap.0.as_mut_ptr(), D3D12_QUERY_TYPE_OCCLUSION, query as _);
+        }
+    }
+
+    /// Resolve queries into `buffer` as `u64` sample counts.
+    pub fn copy_occlusion(
+        &self,
+        heap: &OcclusionQueries,
+        queries: Range<usize>,
+        buffer: &Buffer,
+        buffer_offset: u64,
+    ) {
+        assert_eq!(buffer_offset % 8, 0, "query offsets must be 8 byte aligned");
+        self.resolve_queries(
+            heap.0,
+            D3D12_QUERY_TYPE_OCCLUSION,
+            queries,
+            buffer,
+            buffer_offset,
+        );
     }
 }
@@ -0,0 +1,663 @@
+use crate::com::Owned;
+use crate::command::PendingStates;
+use crate::deletion::Dx12Timeline;
+use crate::lost::DeviceState;
+use crate::{
+    check_hresult, Barrier, ClockCalibration, CmdBufferTy, CommandBuffer, Error, ResourceStates,
+};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::ntdef::{BOOLEAN, HANDLE, PVOID};
+use winapi::shared::winerror::E_INVALIDARG;
+use winapi::um::d3d12::*;
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::profileapi::{QueryPerformanceCounter, QueryPerformanceFrequency};
+use winapi::um::synchapi::WaitForMultipleObjects;
+use winapi::um::threadpoollegacyapiset::UnregisterWaitEx;
+use winapi::um::winbase::{RegisterWaitForSingleObject, INFINITE, WAIT_FAILED};
+use winapi::um::winnt::{MAXIMUM_WAIT_OBJECTS, WT_EXECUTEONLYONCE};
+
+/// Current CPU timestamp in ticks of the performance counter.
+///
+/// Matches the CPU timestamps of `Queue::clock_calibration`.
+pub fn cpu_timestamp() -> u64 {
+    unsafe {
+        let mut counter = std::mem::zeroed();
+        QueryPerformanceCounter(&mut counter);
+        *counter.QuadPart() as u64
+    }
+}
+
+fn cpu_frequency() -> u64 {
+    unsafe {
+        let mut frequency = std::mem::zeroed();
+        QueryPerformanceFrequency(&mut frequency);
+        *frequency.QuadPart() as u64
+    }
+}
+
+pub struct Semaphore {
+    pub(crate) fence: Owned<ID3D12Fence>,
+    pub(crate) event: d3d12::Event,
+    pub(crate) state: Arc<DeviceState>,
+}
+
+impl Drop for Semaphore {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.event.0);
+        }
+    }
+}
+
+// Milliseconds until `deadline` rounded up, waiting forever without deadline.
+fn timeout_ms(deadline: Option<Instant>) -> u32 {
+    match deadline {
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let ms = remaining.as_micros().div_ceil(1000);
+            ms.min(INFINITE as u128 - 1) as _
+        }
+        None => INFINITE,
+    }
+}
+
+fn is_expired(deadline: Option<Instant>) -> bool {
+    matches!(deadline, Some(deadline) if Instant::now() >= deadline)
+}
+
+impl Semaphore {
+    /// Latest value the semaphore reached.
+    pub fn completed_value(&self) -> u64 {
+        self.fence.get_value()
+    }
+
+    fn is_completed(&self, value: u64) -> Result<bool, Error> {
+        let completed = self.completed_value();
+        // Fences are signaled with `u64::MAX` on device removal.
+        if completed == !0 {
+            self.state.check_lost()?;
+        }
+        Ok(completed >= value)
+    }
+
+    fn wait_until(&self, value: u64, deadline: Option<Instant>) -> Result<bool, Error> {
+        self.state.check_lost()?;
+        loop {
+            if self.is_completed(value)? {
+                return Ok(true);
+            }
+            if is_expired(deadline) {
+                return Ok(false);
+            }
+            // The event may still be signaled from an earlier wait, hence checking the value again.
+            check_hresult(self.fence.set_event_on_completion(self.event, value))?;
+            self.event.wait(timeout_ms(deadline));
+        }
+    }
+
+    pub fn wait(&self, timestamp: u64) -> Result<(), Error> {
+        self.wait_until(timestamp, None).map(|_| ())
+    }
+
+    /// Wait until the semaphore reached `value`, returns `false` if `timeout` elapsed before.
+    pub fn wait_timeout(&self, value: u64, timeout: Duration) -> Result<bool, Error> {
+        self.wait_until(value, Instant::now().checked_add(timeout))
+    }
+
+    /// Future resolving once the semaphore reached `value`.
+    ///
+    /// Polling doesn't block, the waker is triggered from a thread pool wait
+    /// on the semaphore.
+    pub fn wait_async(&self, value: u64) -> SemaphoreWait {
+        SemaphoreWait {
+            fence: self.fence.clone(),
+            value,
+            state: self.state.clone(),
+            wait: None,
+        }
+    }
+
+    /// Wait until all semaphores reached their values, returns `false` if `timeout` elapsed before.
+    pub fn wait_all(waits: &[(&Semaphore, u64)], timeout: Duration) -> Result<bool, Error> {
+        let deadline = Instant::now().checked_add(timeout);
+        for &(semaphore, value) in waits {
+            if !semaphore.wait_until(value, deadline)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Wait until any semaphore reached its value.
+    ///
+    /// Returns the index of a completed semaphore or `None` if `timeout` elapsed before.
+    pub fn wait_any(
+        waits: &[(&Semaphore, u64)],
+        timeout: Duration,
+    ) -> Result<Option<usize>, Error> {
+        assert!(
+            !waits.is_empty(),
+            "`wait_any` requires at least one semaphore"
+        );
+        assert!(
+            waits.len() <= MAXIMUM_WAIT_OBJECTS as usize,
+            "`wait_any` supports at most {} semaphores",
+            MAXIMUM_WAIT_OBJECTS
+        );
+
+        let deadline = Instant::now().checked_add(timeout);
+        for (semaphore, _) in waits {
+            semaphore.state.check_lost()?;
+        }
+        let events = waits
+            .iter()
+            .map(|(semaphore, _)| semaphore.event.0)
+            .collect::<Vec<_>>();
+        loop {
+            for (i, &(semaphore, value)) in waits.iter().enumerate() {
+                if semaphore.is_completed(value)? {
+                    return Ok(Some(i));
+                }
+            }
+            if is_expired(deadline) {
+                return Ok(None);
+            }
+            for &(semaphore, value) in waits {
+                check_hresult(
+                    semaphore
+                        .fence
+                        .set_event_on_completion(semaphore.event, value),
+                )?;
+            }
+            let result = unsafe {
+                WaitForMultipleObjects(
+                    events.len() as _,
+                    events.as_ptr(),
+                    FALSE,
+                    timeout_ms(deadline),
+                )
+            };
+            if result == WAIT_FAILED {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+    }
+}
+
+// Event wait registered with the thread pool, unregistered on drop.
+struct RegisteredWait {
+    handle: HANDLE,
+    event: d3d12::Event,
+    // Waker of the latest poll, taken by the wait callback.
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl Drop for RegisteredWait {
+    fn drop(&mut self) {
+        unsafe {
+            // Blocks until a running callback returned, the callback borrows `waker`.
+            UnregisterWaitEx(self.handle, INVALID_HANDLE_VALUE);
+            CloseHandle(self.event.0);
+        }
+    }
+}
+
+unsafe extern "system" fn wake_waiter(context: PVOID, _timed_out: BOOLEAN) {
+    let waker = &*(context as *const Mutex<Option<Waker>>);
+    if let Some(waker) = waker.lock().unwrap().take() {
+        waker.wake();
+    }
+}
+
+/// Future returned by `Semaphore::wait_async`.
+pub struct SemaphoreWait {
+    fence: Owned<ID3D12Fence>,
+    value: u64,
+    state: Arc<DeviceState>,
+    wait: Option<RegisteredWait>,
+}
+
+// Fences and thread pool waits are free-threaded.
+unsafe impl Send for SemaphoreWait {}
+
+impl Future for SemaphoreWait {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Simulated removals don't signal the fence.
+        if let Err(err) = self.state.check_lost() {
+            return Poll::Ready(Err(err));
+        }
+
+        // Register the waker before checking the value to not miss a completion in between.
+        if let Some(wait) = &self.wait {
+            *wait.waker.lock().unwrap() = Some(cx.waker().clone());
+        }
+
+        let completed = self.fence.get_value();
+        if completed >= self.value {
+            // Fences are signaled with `u64::MAX` on device removal.
+            if completed == !0 {
+                return Poll::Ready(self.state.check_lost());
+            }
+            return Poll::Ready(Ok(()));
+        }
+
+        if self.wait.is_none() {
+            let event = d3d12::Event::create(false, false);
+            if event.0.is_null() {
+                return Poll::Ready(Err(io::Error::last_os_error().into()));
+            }
+            if let Err(err) = check_hresult(self.fence.set_event_on_completion(event, self.value)) {
+                unsafe {
+                    CloseHandle(event.0);
+                }
+                return Poll::Ready(Err(err));
+            }
+
+            let waker = Arc::new(Mutex::new(Some(cx.waker().clone())));
+            let mut handle = ptr::null_mut();
+            let registered = unsafe {
+                RegisterWaitForSingleObject(
+                    &mut handle,
+                    event.0,
+                    Some(wake_waiter),
+                    Arc::as_ptr(&waker) as PVOID,
+                    INFINITE,
+                    WT_EXECUTEONLYONCE,
+                )
+            };
+            if registered == FALSE {
+                let err = io::Error::last_os_error();
+                unsafe {
+                    CloseHandle(event.0);
+                }
+                return Poll::Ready(Err(err.into()));
+            }
+            self.wait = Some(RegisteredWait {
+                handle,
+                event,
+                waker,
+            });
+        }
+        Poll::Pending
+    }
+}
+
+// Command list recording the state transitions between submitted command buffers.
+struct Fixup {
+    allocator: d3d12::CommandAllocator,
+    cmd_buffer: d3d12::GraphicsCommandList,
+    // Fence value signaled after execution.
+    value: u64,
+}
+
+/// Pool of fixup command lists, recycled once the GPU finished executing them.
+pub(crate) struct Fixups {
+    ty: CmdBufferTy,
+    fence: d3d12::Fence,
+    next_value: u64,
+    free: Vec<Fixup>,
+    pending: VecDeque<Fixup>,
+}
+
+impl Fixups {
+    pub(crate) fn new(ty: CmdBufferTy, fence: d3d12::Fence) -> Self {
+        Fixups {
+            ty,
+            fence,
+            next_value: 1,
+            free: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn acquire(&mut self, state: &DeviceState) -> Result<Fixup, Error> {
+        let completed = self.fence.get_value();
+        while self
+            .pending
+            .front()
+            .map_or(false, |fixup| fixup.value <= completed)
+        {
+            let fixup = self.pending.pop_front().unwrap();
+            self.free.push(fixup);
+        }
+
+        let fixup = match self.free.pop() {
+            Some(fixup) => {
+                fixup.allocator.reset();
+                check_hresult(
+                    fixup
+                        .cmd_buffer
+                        .reset(fixup.allocator, d3d12::PipelineState::null()),
+                )?;
+                fixup
+            }
+            None => {
+                let device = state.device();
+                let (allocator, hr) = device.create_command_allocator(self.ty);
+                check_hresult(hr)?;
+                let (cmd_buffer, hr) = device.create_graphics_command_list(
+                    self.ty,
+                    allocator,
+                    d3d12::PipelineState::null(),
+                    0,
+                );
+                if let Err(err) = check_hresult(hr) {
+                    unsafe {
+                        allocator.destroy();
+                    }
+                    return Err(err);
+                }
+                Fixup {
+                    allocator,
+                    cmd_buffer,
+                    value: 0,
+                }
+            }
+        };
+        Ok(fixup)
+    }
+}
+
+/// Command buffers submitted together with GPU-side semaphore operations.
+///
+/// The queue waits for all `waits` before executing the command buffers and
+/// signals the `signals` afterwards.
+#[derive(Default)]
+pub struct SubmitBatch<'a> {
+    pub waits: &'a [(&'a Semaphore, u64)],
+    pub cmd_buffers: &'a [&'a CommandBuffer],
+    pub signals: &'a [(&'a Semaphore, u64)],
+}
+
+// Resource states command lists of a queue type can transition between, `None` if unrestricted.
+fn legal_states(ty: CmdBufferTy) -> Option<ResourceStates> {
+    match ty {
+        CmdBufferTy::Direct | CmdBufferTy::Bundle => None,
+        CmdBufferTy::Compute => Some(
+            D3D12_RESOURCE_STATE_COMMON
+                | D3D12_RESOURCE_STATE_VERTEX_AND_CONSTANT_BUFFER
+                | D3D12_RESOURCE_STATE_UNORDERED_ACCESS
+                | D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE
+                | D3D12_RESOURCE_STATE_INDIRECT_ARGUMENT
+                | D3D12_RESOURCE_STATE_COPY_DEST
+                | D3D12_RESOURCE_STATE_COPY_SOURCE,
+        ),
+        CmdBufferTy::Copy => Some(
+            D3D12_RESOURCE_STATE_COMMON
+                | D3D12_RESOURCE_STATE_COPY_SOURCE
+                | D3D12_RESOURCE_STATE_COPY_DEST,
+        ),
+    }
+}
+
+// Fixups are recorded on the submitting queue, check that its type supports the transitions.
+fn check_fixup(ty: CmdBufferTy, barriers: &[Barrier<ResourceStates>]) -> Result<(), Error> {
+    let legal = match legal_states(ty) {
+        Some(legal) => legal,
+        None => return Ok(()),
+    };
+    let illegal = barriers.iter().any(|barrier| match *barrier {
+        Barrier::Transition { before, after, .. } => (before | after) & !legal != 0,
+        Barrier::Unordered { .. } => false,
+    });
+    if illegal {
+        return Err(Error::InvalidArgument { hr: E_INVALIDARG });
+    }
+    Ok(())
+}
+
+pub struct Queue {
+    pub(crate) ty: CmdBufferTy,
+    pub(crate) queue: d3d12::CommandQueue,
+    pub(crate) timeline: Arc<Dx12Timeline>,
+    pub(crate) state: Arc<DeviceState>,
+    pub(crate) fixups: Mutex<Fixups>,
+}
+
+impl Queue {
+    pub fn ty(&self) -> CmdBufferTy {
+        self.ty
+    }
+
+    pub fn signal(&self, semaphore: &Semaphore, value: u64) -> Result<(), Error> {
+        check_hresult(self.queue.signal(*semaphore.fence, value))
+    }
+
+    /// Let the GPU wait until the semaphore reached `value` before executing
+    /// further submissions, without blocking the CPU.
+    pub fn wait(&self, semaphore: &Semaphore, value: u64) -> Result<(), Error> {
+        check_hresult(unsafe { self.queue.Wait(semaphore.fence.as_mut_ptr(), value) })
+    }
+
+    /// Submit command buffers for execution.
+    ///
+    /// Resource states are reconciled with the state left by previous
+    /// submissions, missing transitions are recorded into internal command
+    /// lists executed in between the command buffers. Fails with
+    /// `Error::InvalidArgument` if a transition isn't supported by the queue
+    /// type, e.g from a pixel shader resource on a compute queue.
+    pub fn submit(&self, cmd_buffers: &[&CommandBuffer]) -> Result<(), Error> {
+        self.submit_batch(&SubmitBatch {
+            cmd_buffers,
+            ..SubmitBatch::default()
+        })
+    }
+
+    /// Submit command buffers between semaphore waits and signals, see `submit`.
+    ///
+    /// Fails with `Error::InvalidArgument` if a command buffer type doesn't
+    /// match the queue type.
+    pub fn submit_batch(&self, batch: &SubmitBatch<'_>) -> Result<(), Error> {
+        self.state.check_lost()?;
+        if batch
+            .cmd_buffers
+            .iter()
+            .any(|buffer| buffer.ty() as u32 != self.ty as u32)
+        {
+            return Err(Error::InvalidArgument { hr: E_INVALIDARG });
+        }
+
+        // Validate the whole batch before enqueueing any wait, a rejected
+        // batch leaves the queue untouched.
+        let mut fixups = self.fixups.lock().unwrap();
+        let mut pending = PendingStates::default();
+        let barriers = batch
+            .cmd_buffers
+            .iter()
+            .map(|buffer| {
+                let barriers = buffer.reconcile(&mut pending);
+                check_fixup(self.ty, &barriers)?;
+                Ok(barriers)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        for &(semaphore, value) in batch.waits {
+            self.wait(semaphore, value)?;
+        }
+        if !batch.cmd_buffers.is_empty() {
+            self.execute(&mut fixups, batch.cmd_buffers, &barriers, pending)?;
+        }
+        drop(fixups);
+        for &(semaphore, value) in batch.signals {
+            check_hresult(self.queue.signal(*semaphore.fence, value))?;
+        }
+        self.state.check_lost()
+    }
+
+    fn execute(
+        &self,
+        fixups: &mut Fixups,
+        cmd_buffers: &[&CommandBuffer],
+        barriers: &[Vec<Barrier<ResourceStates>>],
+        pending: PendingStates,
+    ) -> Result<(), Error> {
+        let mut cmd_lists = Vec::with_capacity(cmd_buffers.len());
+        let mut used = Vec::new();
+        for (buffer, barriers) in cmd_buffers.iter().zip(barriers) {
+            if !barriers.is_empty() {
+                let mut fixup = match fixups.acquire(&self.state) {
+                    Ok(fixup) => fixup,
+                    Err(err) => {
+                        fixups.free.extend(used);
+                        return Err(err);
+                    }
+                };
+                buffer.record_reconcile(fixup.cmd_buffer, barriers);
+                if let Err(err) = check_hresult(fixup.cmd_buffer.close()) {
+                    fixups.free.push(fixup);
+                    fixups.free.extend(used);
+                    return Err(err);
+                }
+                fixup.value = fixups.next_value;
+                cmd_lists.push(fixup.cmd_buffer.as_list());
+                used.push(fixup);
+            }
+            cmd_lists.push(buffer.command_list());
+        }
+        // Queue level states only change once the batch is actually executed.
+        pending.commit();
+        self.queue.execute_command_lists(&cmd_lists);
+
+        if !used.is_empty() {
+            check_hresult(self.queue.signal(fixups.fence, fixups.next_value))?;
+            fixups.next_value += 1;
+            fixups.pending.extend(used);
+        }
+        // Signaled while holding the fixups lock to keep the timeline ordered.
+        self.timeline.signal(self.queue)
+    }
+
+    pub fn timing_frequency(&self) -> u64 {
+        let mut freq = 0u64;
+        unsafe { self.queue.GetTimestampFrequency(&mut freq); }
+        freq
+    }
+
+    /// Sample the GPU timestamp of the queue together with the CPU timestamp.
+    ///
+    /// CPU timestamps are in ticks of the performance counter, see `cpu_timestamp`.
+    pub fn clock_calibration(&self) -> Result<ClockCalibration, Error> {
+        let mut gpu_timestamp = 0u64;
+        let mut cpu_timestamp = 0u64;
+        check_hresult(unsafe {
+            self.queue
+                .GetClockCalibration(&mut gpu_timestamp, &mut cpu_timestamp)
+        })?;
+        Ok(ClockCalibration {
+            gpu_timestamp,
+            gpu_frequency: self.timing_frequency(),
+            cpu_timestamp,
+            cpu_frequency: cpu_frequency(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AdapterSelection, Device, DeviceCreateFlags, HResult};
+    use std::task::Wake;
+    use std::thread::{self, Thread};
+
+    const DXGI_ERROR_DEVICE_HUNG: HResult = 0x887A_0006_u32 as _;
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Polls `future` on the current thread until it resolves.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    fn is_lost<T>(result: Result<T, Error>) -> bool {
+        matches!(
+            result,
+            Err(Error::DeviceLost {
+                reason: DXGI_ERROR_DEVICE_HUNG,
+                ..
+            })
+        )
+    }
+
+    fn device() -> Device {
+        Device::new(DeviceCreateFlags::empty(), AdapterSelection::default()).unwrap()
+    }
+
+    #[test]
+    fn wait_timeout() {
+        let device = device();
+        let semaphore = device.create_semaphore().unwrap();
+        assert!(!semaphore.wait_timeout(1, TIMEOUT).unwrap());
+
+        check_hresult(semaphore.fence.signal(1)).unwrap();
+        assert!(semaphore.wait_timeout(1, TIMEOUT).unwrap());
+    }
+
+    #[test]
+    fn wait_any() {
+        let device = device();
+        let a = device.create_semaphore().unwrap();
+        let b = device.create_semaphore().unwrap();
+        assert_eq!(
+            Semaphore::wait_any(&[(&a, 1), (&b, 1)], TIMEOUT).unwrap(),
+            None
+        );
+
+        check_hresult(b.fence.signal(1)).unwrap();
+        assert_eq!(
+            Semaphore::wait_any(&[(&a, 1), (&b, 1)], TIMEOUT).unwrap(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn wait_async() {
+        let device = device();
+        let semaphore = device.create_semaphore().unwrap();
+
+        // Signaled after the first poll registered the thread pool wait.
+        let fence = semaphore.fence.clone();
+        let signal = thread::spawn(move || {
+            thread::sleep(TIMEOUT);
+            check_hresult(fence.signal(1))
+        });
+        block_on(semaphore.wait_async(1)).unwrap();
+        signal.join().unwrap().unwrap();
+        assert_eq!(semaphore.completed_value(), 1);
+    }
+
+    #[test]
+    fn waits_lost() {
+        let device = device();
+        let semaphore = device.create_semaphore().unwrap();
+
+        device.simulate_removal(Some(DXGI_ERROR_DEVICE_HUNG));
+        assert!(is_lost(semaphore.wait_timeout(1, TIMEOUT)));
+        assert!(is_lost(Semaphore::wait_any(&[(&semaphore, 1)], TIMEOUT)));
+        assert!(is_lost(block_on(semaphore.wait_async(1))));
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{Device, Error};
+use crate::com::Owned;
+use crate::deletion::Dx12DeletionQueue;
+use crate::{
+    mip_extent, Device, Error, Extent, HeapType, ResourceId, TrackedResource, TrackedState,
+};
 
 pub use winapi::um::d3d12::{
     D3D12_RESOURCE_FLAG_ALLOW_DEPTH_STENCIL as RESOURCE_FLAG_DEPTH_STENCIL,
@@ -15,18 +19,80 @@ pub use winapi::um::d3d12::{
 pub use winapi::shared::dxgiformat::DXGI_FORMAT as Format;
 pub use winapi::shared::dxgiformat::*;
 
+use std::mem::ManuallyDrop;
 use std::ptr;
+use std::sync::{Arc, Mutex};
 use winapi::shared::dxgitype::DXGI_SAMPLE_DESC;
 use winapi::um::d3d12::*;
 use winapi::Interface;
 
-pub struct Buffer(pub(crate) d3d12::Resource);
+impl TrackedState for ResourceStates {
+    fn satisfies(self, required: Self) -> bool {
+        if required == D3D12_RESOURCE_STATE_COMMON {
+            self == D3D12_RESOURCE_STATE_COMMON
+        } else {
+            self & required == required
+        }
+    }
+
+    fn is_unordered(self) -> bool {
+        self == D3D12_RESOURCE_STATE_UNORDERED_ACCESS
+    }
+}
+
+/// Resource state tracking shared between the resource and command buffers using it.
+///
+/// Owns the reference to the resource, which is released once the GPU
+/// finished all work submitted before the resource and all command buffers
+/// using it have been dropped.
+pub(crate) struct Tracking {
+    pub(crate) resource: ManuallyDrop<Owned<ID3D12Resource>>,
+    pub(crate) desc: TrackedResource<ResourceStates>,
+    /// Queue level state of each subresource, updated on submission.
+    pub(crate) states: Mutex<Vec<ResourceStates>>,
+    deletion: Arc<Dx12DeletionQueue>,
+}
+
+impl Tracking {
+    pub(crate) fn new(
+        resource: Owned<ID3D12Resource>,
+        num_subresources: u32,
+        decay: Option<ResourceStates>,
+        initial: ResourceStates,
+        deletion: Arc<Dx12DeletionQueue>,
+    ) -> Arc<Self> {
+        Arc::new(Tracking {
+            desc: TrackedResource {
+                id: ResourceId(resource.as_mut_ptr() as _),
+                num_subresources,
+                decay,
+            },
+            resource: ManuallyDrop::new(resource),
+            states: Mutex::new(vec![initial; num_subresources as usize]),
+            deletion,
+        })
+    }
+}
+
+impl Drop for Tracking {
+    fn drop(&mut self) {
+        let resource = unsafe { ManuallyDrop::take(&mut self.resource) };
+        self.deletion.defer(resource.into_unknown());
+    }
+}
+
+/// Buffer resource, released once the GPU finished using it after drop.
+pub struct Buffer(pub(crate) d3d12::Resource, pub(crate) Arc<Tracking>);
 
 impl Buffer {
     pub fn resource(&self) -> &d3d12::Resource {
         &self.0
     }
 
+    pub fn size(&self) -> u64 {
+        unsafe { self.0.GetDesc().Width }
+    }
+
     pub fn copy_from_host(&self, offset: isize, data: &[u8]) {
         unsafe {
             let mut ptr = ptr::null_mut();
@@ -46,14 +112,44 @@ impl Buffer {
     }
 }
 
-pub struct Image(pub(crate) d3d12::Resource);
+/// Image resource, released once the GPU finished using it after drop.
+pub struct Image(pub(crate) d3d12::Resource, pub(crate) Arc<Tracking>);
 
 impl Image {
     pub fn resource(&self) -> &d3d12::Resource {
         &self.0
     }
+
+    pub fn format(&self) -> Format {
+        unsafe { self.0.GetDesc().Format }
+    }
+
+    /// Extent of a subresource, array layers count as separate subresources.
+    pub fn subresource_extent(&self, subresource: u32) -> Extent {
+        let desc = unsafe { self.0.GetDesc() };
+        let mip_levels = desc.MipLevels as u32;
+        let depth = match desc.Dimension {
+            D3D12_RESOURCE_DIMENSION_TEXTURE3D => desc.DepthOrArraySize as u32,
+            _ => 1,
+        };
+        mip_extent(
+            Extent {
+                width: desc.Width as _,
+                height: desc.Height,
+                depth,
+            },
+            subresource % mip_levels,
+        )
+    }
 }
 
+// D3D12 resources are free-threaded and the queue level state is guarded by a
+// mutex, allowing command buffers to be recorded on multiple threads.
+unsafe impl Send for Buffer {}
+unsafe impl Sync for Buffer {}
+unsafe impl Send for Image {}
+unsafe impl Sync for Image {}
+
 #[derive(Debug, Copy, Clone)]
 pub enum ImageType {
     D1,
@@ -61,13 +157,6 @@ pub enum ImageType {
     D3,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum HeapType {
-    Device,
-    Upload,
-    Readback,
-}
-
 impl HeapType {
     fn as_d3d12(&self) -> u32 {
         match self {
@@ -78,13 +167,6 @@ impl HeapType {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct Extent {
-    pub width: u32,
-    pub height: u32,
-    pub depth: u32,
-}
-
 #[derive(Debug, Clone)]
 pub struct ImageDesc {
     pub ty: ImageType,
@@ -130,7 +212,7 @@ impl Device {
         let heap_properties = unsafe { self.GetCustomHeapProperties(0, heap.as_d3d12()) };
 
         let mut image = d3d12::Resource::null();
-        let _hr = unsafe {
+        let hr = unsafe {
             self.CreateCommittedResource(
                 &heap_properties,
                 D3D12_HEAP_FLAG_ALLOW_ALL_BUFFERS_AND_TEXTURES, // Resource Heap Tier 2 required
@@ -141,8 +223,23 @@ impl Device {
                 image.mut_void(),
             )
         };
+        self.check(hr)?;
 
-        Ok(Image(image))
+        let num_subresources = match desc.ty {
+            ImageType::D3 => desc.mip_levels,
+            ImageType::D1 | ImageType::D2 => desc.mip_levels * desc.extent.depth,
+        };
+        let image = unsafe { Owned::new(image) };
+        Ok(Image(
+            *image,
+            Tracking::new(
+                image,
+                num_subresources,
+                None,
+                initial,
+                self.deletion.clone(),
+            ),
+        ))
     }
 
     pub fn create_buffer_committed(
@@ -170,7 +267,7 @@ impl Device {
         let heap_properties = unsafe { self.GetCustomHeapProperties(0, heap.as_d3d12()) };
 
         let mut buffer = d3d12::Resource::null();
-        let _hr = unsafe {
+        let hr = unsafe {
             self.CreateCommittedResource(
                 &heap_properties,
                 D3D12_HEAP_FLAG_ALLOW_ALL_BUFFERS_AND_TEXTURES, // Resource Heap Tier 2 required
@@ -181,7 +278,18 @@ impl Device {
                 buffer.mut_void(),
             )
         };
+        self.check(hr)?;
 
-        Ok(Buffer(buffer))
+        // Buffers in default heaps are implicitly promoted from and decay to the common state,
+        // buffers in upload and readback heaps stay in their initial state.
+        let decay = match heap {
+            HeapType::Device => D3D12_RESOURCE_STATE_COMMON,
+            HeapType::Upload | HeapType::Readback => initial,
+        };
+        let buffer = unsafe { Owned::new(buffer) };
+        Ok(Buffer(
+            *buffer,
+            Tracking::new(buffer, 1, Some(decay), initial, self.deletion.clone()),
+        ))
     }
 }
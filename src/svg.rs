@@ -1,22 +1,27 @@
 use kurbo::Shape;
 use pathbreaker::kurbo;
+use std::borrow::Cow;
 use std::error;
 use std::path::Path;
 
-const PRIMITIVE_LINE: u32 = 1;
+pub(crate) const PRIMITIVE_LINE: u32 = 1;
 
 #[repr(C)]
 #[derive(Debug)]
 pub struct Object {
-    primitives: [u32; 2],
-    offset_data: u32,
-    bbox: [f32; 4],
+    pub(crate) primitives: [u32; 2],
+    pub(crate) offset_data: u32,
+    pub(crate) bbox: [f32; 4],
 }
 
 pub struct GpuData {
     pub objects: Vec<Object>,
     pub primitives: Vec<u32>,
     pub data: Vec<f32>,
+    /// Style index of each object, the position of its path in the input.
+    ///
+    /// Kept on the CPU for looking up styles, e.g. on export.
+    pub styles: Vec<u32>,
 }
 
 impl GpuData {
@@ -25,6 +30,7 @@ impl GpuData {
             objects: Vec::new(),
             primitives: Vec::new(),
             data: Vec::new(),
+            styles: Vec::new(),
         }
     }
 }
@@ -33,10 +39,36 @@ fn up_align(x: u32, alignment: u32) -> u32 {
     (x + alignment - 1) & !(alignment - 1)
 }
 
+fn push_line(gpu_data: &mut GpuData, p0: kurbo::Point, p1: kurbo::Point) {
+    gpu_data.primitives.push(PRIMITIVE_LINE);
+    // p0
+    gpu_data.data.push(p0.x as f32);
+    gpu_data.data.push(p0.y as f32);
+    // p1
+    gpu_data.data.push(p1.x as f32);
+    gpu_data.data.push(p1.y as f32);
+}
+
+fn push_object(
+    gpu_data: &mut GpuData,
+    aabb: kurbo::Rect,
+    primitive_start: usize,
+    data_offset: usize,
+    style: usize,
+) {
+    let primitive_end = gpu_data.primitives.len();
+    gpu_data.objects.push(Object {
+        primitives: [primitive_start as _, primitive_end as _],
+        offset_data: data_offset as _,
+        bbox: [aabb.x0 as _, aabb.y0 as _, aabb.x1 as _, aabb.y1 as _],
+    });
+    gpu_data.styles.push(style as _);
+}
+
 pub fn generate_gpu_data(paths: &[kurbo::BezPath]) -> GpuData {
     let mut gpu_data = GpuData::new();
 
-    for path in paths {
+    for (i, path) in paths.iter().enumerate() {
         let aabb = path.bounding_box();
 
         let data_offset = gpu_data.data.len() / 4;
@@ -51,43 +83,519 @@ pub fn generate_gpu_data(paths: &[kurbo::BezPath]) -> GpuData {
                     last = p;
                 }
                 kurbo::PathEl::LineTo(p) => {
-                    gpu_data.primitives.push(PRIMITIVE_LINE);
-                    // p0
-                    gpu_data.data.push(last.x as f32);
-                    gpu_data.data.push(last.y as f32);
-                    // p1
-                    gpu_data.data.push(p.x as f32);
-                    gpu_data.data.push(p.y as f32);
-
+                    push_line(&mut gpu_data, last, p);
                     last = p;
                 }
                 kurbo::PathEl::ClosePath => {
-                    gpu_data.primitives.push(PRIMITIVE_LINE);
-                    // p0
-                    gpu_data.data.push(last.x as f32);
-                    gpu_data.data.push(last.y as f32);
-                    // p1
-                    gpu_data.data.push(first.x as f32);
-                    gpu_data.data.push(first.y as f32);
-
+                    push_line(&mut gpu_data, last, first);
                     last = first;
                 }
                 _ => todo!(),
             }
         }
 
-        let primitive_end = gpu_data.primitives.len();
-        gpu_data.objects.push(Object {
-            primitives: [primitive_start as _, primitive_end as _],
-            offset_data: data_offset as _,
-            bbox: [aabb.x0 as _, aabb.y0 as _, aabb.x1 as _, aabb.y1 as _],
-        });
+        push_object(&mut gpu_data, aabb, primitive_start, data_offset, i);
     }
 
     gpu_data
 }
 
-pub fn parse_svg<P: AsRef<Path>>(path: P) -> Result<Vec<kurbo::BezPath>, Box<dyn error::Error>> {
+/// Geometry cleanup applied while generating gpu data.
+#[derive(Debug, Copy, Clone)]
+pub struct Normalize {
+    /// Maximum distance of a point from the line through its neighbors
+    /// for merging both adjacent segments into one.
+    ///
+    /// A tolerance of `0.0` only merges exactly collinear runs.
+    pub collinear_tolerance: f64,
+    /// Flattening tolerance in document units for paths containing curves.
+    pub curve_tolerance: f64,
+}
+
+impl Default for Normalize {
+    fn default() -> Self {
+        Normalize {
+            collinear_tolerance: 0.0,
+            curve_tolerance: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NormalizeStats {
+    /// Number of line segments emitted without normalization.
+    pub input_segments: usize,
+    /// Number of line segments dropped or merged.
+    pub removed_segments: usize,
+}
+
+// Flatten all curves of a path, paths without curves are passed through.
+fn flatten_curves(path: &kurbo::BezPath, tolerance: f64) -> Cow<kurbo::BezPath> {
+    let has_curves = path
+        .into_iter()
+        .any(|elem| matches!(elem, kurbo::PathEl::QuadTo(..) | kurbo::PathEl::CurveTo(..)));
+    if has_curves {
+        Cow::Owned(pathbreaker::break_path(
+            path,
+            pathbreaker::CubicApprox::Flatten(tolerance),
+        ))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+// Points are compared in the precision they are uploaded with.
+fn same_point(a: kurbo::Point, b: kurbo::Point) -> bool {
+    a.x as f32 == b.x as f32 && a.y as f32 == b.y as f32
+}
+
+// Check if `b` lies on the segment `a`-`c` within the given tolerance.
+fn is_collinear(a: kurbo::Point, b: kurbo::Point, c: kurbo::Point, tolerance: f64) -> bool {
+    let ac = c - a;
+    let ab = b - a;
+    let len2 = ac.hypot2();
+    if len2 == 0.0 {
+        return false;
+    }
+
+    // Reject points outside of the segment, these would fold back the contour.
+    let t = ab.dot(ac) / len2;
+    if t < 0.0 || t > 1.0 {
+        return false;
+    }
+
+    let dist = ac.cross(ab).abs() / len2.sqrt();
+    dist <= tolerance
+}
+
+fn normalize_contour(
+    gpu_data: &mut GpuData,
+    contour: &mut Vec<kurbo::Point>,
+    closed: bool,
+    normalize: &Normalize,
+) {
+    // A closed contour ending at its start point doesn't require a closing segment.
+    if closed && contour.len() > 1 && same_point(contour[0], contour[contour.len() - 1]) {
+        contour.pop();
+    }
+
+    let mut points: Vec<kurbo::Point> = Vec::with_capacity(contour.len());
+    // Points merged into the last segment, each extension of the segment has
+    // to stay within the tolerance for all of them.
+    let mut merged: Vec<kurbo::Point> = Vec::new();
+    for &p in contour.iter() {
+        if points.last().map_or(false, |&last| same_point(last, p)) {
+            continue;
+        }
+        if points.len() >= 2 {
+            let a = points[points.len() - 2];
+            let b = points[points.len() - 1];
+            let collinear = merged
+                .iter()
+                .chain(Some(&b))
+                .all(|&m| is_collinear(a, m, p, normalize.collinear_tolerance));
+            if collinear {
+                points.pop();
+                merged.push(b);
+            } else {
+                merged.clear();
+            }
+        }
+        points.push(p);
+    }
+
+    let num_points = points.len();
+    let num_segments = if closed {
+        num_points
+    } else {
+        num_points.saturating_sub(1)
+    };
+    for i in 0..num_segments {
+        let p0 = points[i];
+        let p1 = points[(i + 1) % num_points];
+
+        // Coverage of a line is weighted by its x-extent, vertical lines don't contribute.
+        if p0.x as f32 == p1.x as f32 {
+            continue;
+        }
+
+        push_line(gpu_data, p0, p1);
+    }
+
+    contour.clear();
+}
+
+/// Generate gpu data, dropping degenerate segments and merging collinear runs.
+///
+/// Zero-length and vertical segments are removed as they don't contribute
+/// to coverage. Closing segments of already closed contours are skipped.
+/// Curves are flattened with `Normalize::curve_tolerance` beforehand.
+pub fn generate_gpu_data_normalized(
+    paths: &[kurbo::BezPath],
+    normalize: &Normalize,
+) -> (GpuData, NormalizeStats) {
+    let mut gpu_data = GpuData::new();
+    let mut stats = NormalizeStats::default();
+
+    let mut contour = Vec::new();
+    for (i, path) in paths.iter().enumerate() {
+        let path = flatten_curves(path, normalize.curve_tolerance);
+        let aabb = path.bounding_box();
+
+        let data_offset = gpu_data.data.len() / 4;
+        let primitive_start = gpu_data.primitives.len();
+
+        for elem in &*path {
+            match elem {
+                kurbo::PathEl::MoveTo(p) => {
+                    normalize_contour(&mut gpu_data, &mut contour, false, normalize);
+                    contour.push(p);
+                }
+                kurbo::PathEl::LineTo(p) => {
+                    stats.input_segments += 1;
+                    contour.push(p);
+                }
+                kurbo::PathEl::ClosePath => {
+                    stats.input_segments += 1;
+                    let first = contour.first().copied();
+                    normalize_contour(&mut gpu_data, &mut contour, true, normalize);
+                    // Subsequent segments continue from the start of the closed contour.
+                    contour.extend(first);
+                }
+                _ => unreachable!(),
+            }
+        }
+        normalize_contour(&mut gpu_data, &mut contour, false, normalize);
+
+        push_object(&mut gpu_data, aabb, primitive_start, data_offset, i);
+    }
+
+    stats.removed_segments = stats.input_segments - gpu_data.primitives.len();
+
+    (gpu_data, stats)
+}
+
+/// Check if the bounding box overlaps the viewport rectangle.
+pub fn is_visible(bbox: kurbo::Rect, viewport: kurbo::Rect) -> bool {
+    bbox.x1 >= viewport.x0
+        && bbox.x0 <= viewport.x1
+        && bbox.y1 >= viewport.y0
+        && bbox.y0 <= viewport.y1
+}
+
+/// Generate gpu data only for paths overlapping the viewport.
+///
+/// Style indices refer to the position of the paths in `paths`.
+pub fn generate_gpu_data_culled(paths: &[kurbo::BezPath], viewport: kurbo::Rect) -> GpuData {
+    let (styles, visible): (Vec<u32>, Vec<_>) = paths
+        .iter()
+        .enumerate()
+        .filter(|(_, path)| is_visible(path.bounding_box(), viewport))
+        .map(|(i, path)| (i as u32, path.clone()))
+        .unzip();
+    let mut gpu_data = generate_gpu_data(&visible);
+    gpu_data.styles = styles;
+    gpu_data
+}
+
+/// Drop paths outside of the viewport and clip paths exceeding the guard band.
+///
+/// The guard band extends the viewport by `guard_band` document units on each side.
+/// Paths fully inside the guard band are passed through unmodified, clipped paths
+/// are flattened with `tolerance` in document units.
+pub fn cull_paths(
+    paths: &[kurbo::BezPath],
+    viewport: kurbo::Rect,
+    guard_band: f64,
+    tolerance: f64,
+) -> Vec<kurbo::BezPath> {
+    let guard = viewport.inflate(guard_band, guard_band);
+    paths
+        .iter()
+        .filter_map(|path| cull_path(path, viewport, guard, tolerance))
+        .collect()
+}
+
+/// Cull paths together with their styles, see `cull_paths`.
+///
+/// Keeps styles aligned with the visible paths for generating and exporting gpu data.
+pub fn cull_styled_paths(
+    paths: &[(kurbo::BezPath, Style)],
+    viewport: kurbo::Rect,
+    guard_band: f64,
+    tolerance: f64,
+) -> Vec<(kurbo::BezPath, Style)> {
+    let guard = viewport.inflate(guard_band, guard_band);
+    paths
+        .iter()
+        .filter_map(|(path, style)| {
+            cull_path(path, viewport, guard, tolerance).map(|path| (path, *style))
+        })
+        .collect()
+}
+
+fn cull_path(
+    path: &kurbo::BezPath,
+    viewport: kurbo::Rect,
+    guard: kurbo::Rect,
+    tolerance: f64,
+) -> Option<kurbo::BezPath> {
+    let bbox = path.bounding_box();
+    if !is_visible(bbox, viewport) {
+        return None;
+    }
+
+    if bbox.x0 >= guard.x0 && bbox.x1 <= guard.x1 && bbox.y0 >= guard.y0 && bbox.y1 <= guard.y1 {
+        Some(path.clone())
+    } else {
+        Some(clip_path(path, guard, tolerance))
+    }
+}
+
+/// Clip a path against a rectangle, flattening curves with `tolerance` beforehand.
+///
+/// Each contour is treated as implicitly closed, matching fill semantics.
+pub fn clip_path(path: &kurbo::BezPath, rect: kurbo::Rect, tolerance: f64) -> kurbo::BezPath {
+    let path = flatten_curves(path, tolerance);
+    let mut clipped = kurbo::BezPath::new();
+
+    let mut clip_contour = |contour: &mut Vec<kurbo::Point>| {
+        let polygon = clip_polygon(contour, rect);
+        if polygon.len() > 2 {
+            clipped.move_to(polygon[0]);
+            for &p in &polygon[1..] {
+                clipped.line_to(p);
+            }
+            clipped.close_path();
+        }
+        contour.clear();
+    };
+
+    let mut contour = Vec::new();
+    for elem in &*path {
+        match elem {
+            kurbo::PathEl::MoveTo(p) => {
+                clip_contour(&mut contour);
+                contour.push(p);
+            }
+            kurbo::PathEl::LineTo(p) => {
+                contour.push(p);
+            }
+            kurbo::PathEl::ClosePath => {
+                let first = contour.first().copied();
+                clip_contour(&mut contour);
+                contour.extend(first);
+            }
+            _ => unreachable!(),
+        }
+    }
+    clip_contour(&mut contour);
+
+    clipped
+}
+
+// Sutherland-Hodgman clipping of a closed polygon against an axis aligned rectangle.
+fn clip_polygon(points: &[kurbo::Point], rect: kurbo::Rect) -> Vec<kurbo::Point> {
+    fn clip_edge<I, S>(points: &[kurbo::Point], inside: I, intersect: S) -> Vec<kurbo::Point>
+    where
+        I: Fn(kurbo::Point) -> bool,
+        S: Fn(kurbo::Point, kurbo::Point) -> kurbo::Point,
+    {
+        let mut output = Vec::with_capacity(points.len() + 1);
+        for i in 0..points.len() {
+            let cur = points[i];
+            let prev = points[(i + points.len() - 1) % points.len()];
+            match (inside(prev), inside(cur)) {
+                (true, true) => output.push(cur),
+                (true, false) => output.push(intersect(prev, cur)),
+                (false, true) => {
+                    output.push(intersect(prev, cur));
+                    output.push(cur);
+                }
+                (false, false) => {}
+            }
+        }
+        output
+    }
+
+    let at_x = |x: f64| {
+        move |a: kurbo::Point, b: kurbo::Point| {
+            let t = (x - a.x) / (b.x - a.x);
+            kurbo::Point::new(x, a.y + t * (b.y - a.y))
+        }
+    };
+    let at_y = |y: f64| {
+        move |a: kurbo::Point, b: kurbo::Point| {
+            let t = (y - a.y) / (b.y - a.y);
+            kurbo::Point::new(a.x + t * (b.x - a.x), y)
+        }
+    };
+
+    let points = clip_edge(points, |p| p.x >= rect.x0, at_x(rect.x0));
+    let points = clip_edge(&points, |p| p.x <= rect.x1, at_x(rect.x1));
+    let points = clip_edge(&points, |p| p.y >= rect.y0, at_y(rect.y0));
+    clip_edge(&points, |p| p.y <= rect.y1, at_y(rect.y1))
+}
+
+/// Default flattening tolerance in screen space (pixels).
+pub const DEFAULT_SCREEN_TOLERANCE: f64 = 0.25;
+
+/// Scale from document units to pixels for a viewport of `viewport_extent`
+/// document units rendered onto a target of `target_extent` pixels.
+pub fn view_scale(viewport_extent: [f32; 2], target_extent: [u32; 2]) -> f64 {
+    let sx = target_extent[0] as f64 / viewport_extent[0] as f64;
+    let sy = target_extent[1] as f64 / viewport_extent[1] as f64;
+    sx.max(sy)
+}
+
+/// Flattening tolerance in document units required to stay within
+/// `screen_tolerance` pixels at the given view scale.
+pub fn flatten_tolerance(screen_tolerance: f64, scale: f64) -> f64 {
+    screen_tolerance / scale
+}
+
+fn is_valid_scale(scale: f64) -> bool {
+    scale.is_finite() && scale > 0.0
+}
+
+/// Flattened path sets of a document at multiple levels of detail.
+///
+/// Each level is flattened for a fixed view scale. Levels are sorted from
+/// coarse (small scale) to fine (large scale).
+pub struct PathLod {
+    curves: Vec<kurbo::BezPath>,
+    screen_tolerance: f64,
+    levels: Vec<LodLevel>,
+}
+
+struct LodLevel {
+    scale: f64,
+    paths: Vec<kurbo::BezPath>,
+}
+
+impl PathLod {
+    /// Flatten `curves` for each of the view `scales`.
+    pub fn new(curves: Vec<kurbo::BezPath>, screen_tolerance: f64, scales: &[f64]) -> Self {
+        assert!(!scales.is_empty(), "at least one view scale required");
+        let mut lod = PathLod {
+            curves,
+            screen_tolerance,
+            levels: Vec::new(),
+        };
+        for &scale in scales {
+            lod.insert_level(scale);
+        }
+        lod
+    }
+
+    /// Build power-of-two levels between `min_scale` and `max_scale`.
+    ///
+    /// Both scales must be positive and finite with `min_scale <= max_scale`.
+    pub fn with_scale_range(
+        curves: Vec<kurbo::BezPath>,
+        screen_tolerance: f64,
+        min_scale: f64,
+        max_scale: f64,
+    ) -> Self {
+        assert!(
+            is_valid_scale(min_scale) && is_valid_scale(max_scale) && min_scale <= max_scale,
+            "invalid scale range {}..{}",
+            min_scale,
+            max_scale
+        );
+
+        let mut scales = Vec::new();
+        let mut scale = min_scale;
+        while scale < max_scale {
+            scales.push(scale);
+            scale *= 2.0;
+        }
+        scales.push(max_scale);
+        Self::new(curves, screen_tolerance, &scales)
+    }
+
+    /// Flatten and cache an additional level for the given view scale.
+    ///
+    /// The scale must be positive and finite.
+    pub fn insert_level(&mut self, scale: f64) -> usize {
+        assert!(is_valid_scale(scale), "invalid view scale {}", scale);
+
+        let idx = match self
+            .levels
+            .binary_search_by(|level| level.scale.total_cmp(&scale))
+        {
+            Ok(idx) => return idx,
+            Err(idx) => idx,
+        };
+
+        let tolerance = flatten_tolerance(self.screen_tolerance, scale);
+        let paths = self
+            .curves
+            .iter()
+            .map(|path| pathbreaker::break_path(path, pathbreaker::CubicApprox::Flatten(tolerance)))
+            .collect();
+        self.levels.insert(idx, LodLevel { scale, paths });
+        idx
+    }
+
+    pub fn num_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn level_scale(&self, level: usize) -> f64 {
+        self.levels[level].scale
+    }
+
+    /// Index of the coarsest cached level which is still fine enough for `scale`.
+    ///
+    /// Falls back to the finest level if `scale` exceeds all cached levels.
+    pub fn select_level(&self, scale: f64) -> usize {
+        assert!(!self.levels.is_empty());
+        self.levels
+            .iter()
+            .position(|level| level.scale >= scale)
+            .unwrap_or(self.levels.len() - 1)
+    }
+
+    /// Flattened paths for rendering at the given view scale.
+    pub fn select(&self, scale: f64) -> &[kurbo::BezPath] {
+        &self.levels[self.select_level(scale)].paths
+    }
+
+    pub fn level(&self, level: usize) -> &[kurbo::BezPath] {
+        &self.levels[level].paths
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FillRule {
+    NonZero,
+    EvenOdd,
+}
+
+/// Fill style of a path.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Style {
+    /// Straight (non-premultiplied) RGBA color in `[0, 1]`.
+    pub color: [f32; 4],
+    pub fill_rule: FillRule,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            color: [0.0, 0.0, 0.0, 1.0],
+            fill_rule: FillRule::NonZero,
+        }
+    }
+}
+
+/// Parse all color filled paths of an svg document together with their fill style.
+///
+/// Curves are kept intact.
+pub fn parse_svg_styled<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<(kurbo::BezPath, Style)>, Box<dyn error::Error>> {
     let mut paths = Vec::new();
 
     let tree = usvg::Tree::from_file(path, &usvg::Options::default())?;
@@ -123,11 +631,20 @@ pub fn parse_svg<P: AsRef<Path>>(path: P) -> Result<Vec<kurbo::BezPath>, Box<dyn
                     }
                 }
                 if let Some(ref fill) = p.fill {
-                    if let usvg::Paint::Color(_) = fill.paint {
-                        let path =
-                            pathbreaker::break_path(&path, pathbreaker::CubicApprox::Flatten(0.1));
-
-                        paths.push(path);
+                    if let usvg::Paint::Color(color) = fill.paint {
+                        let style = Style {
+                            color: [
+                                color.red as f32 / 255.0,
+                                color.green as f32 / 255.0,
+                                color.blue as f32 / 255.0,
+                                fill.opacity.value() as f32,
+                            ],
+                            fill_rule: match fill.rule {
+                                usvg::FillRule::NonZero => FillRule::NonZero,
+                                usvg::FillRule::EvenOdd => FillRule::EvenOdd,
+                            },
+                        };
+                        paths.push((path, style));
                     }
                 }
             }
@@ -137,3 +654,311 @@ pub fn parse_svg<P: AsRef<Path>>(path: P) -> Result<Vec<kurbo::BezPath>, Box<dyn
 
     Ok(paths)
 }
+
+/// Parse all color filled paths of an svg document, keeping curves intact.
+pub fn parse_svg_curves<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<kurbo::BezPath>, Box<dyn error::Error>> {
+    let paths = parse_svg_styled(path)?
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    Ok(paths)
+}
+
+/// Parse an svg document and flatten all curves with a fixed tolerance in document units.
+pub fn parse_svg<P: AsRef<Path>>(path: P) -> Result<Vec<kurbo::BezPath>, Box<dyn error::Error>> {
+    let paths = parse_svg_curves(path)?
+        .iter()
+        .map(|path| pathbreaker::break_path(path, pathbreaker::CubicApprox::Flatten(0.1)))
+        .collect();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scales(lod: &PathLod) -> Vec<f64> {
+        (0..lod.num_levels()).map(|i| lod.level_scale(i)).collect()
+    }
+
+    #[test]
+    fn lod_levels_sorted() {
+        let mut lod = PathLod::new(Vec::new(), DEFAULT_SCREEN_TOLERANCE, &[4.0, 1.0, 2.0]);
+        assert_eq!(scales(&lod), [1.0, 2.0, 4.0]);
+
+        // Existing levels are reused.
+        assert_eq!(lod.insert_level(2.0), 1);
+        assert_eq!(lod.insert_level(3.0), 2);
+        assert_eq!(scales(&lod), [1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn lod_scale_range() {
+        let lod = PathLod::with_scale_range(Vec::new(), DEFAULT_SCREEN_TOLERANCE, 0.5, 5.0);
+        assert_eq!(scales(&lod), [0.5, 1.0, 2.0, 4.0, 5.0]);
+
+        let lod = PathLod::with_scale_range(Vec::new(), DEFAULT_SCREEN_TOLERANCE, 2.0, 2.0);
+        assert_eq!(scales(&lod), [2.0]);
+    }
+
+    #[test]
+    fn lod_select_level() {
+        let lod = PathLod::new(Vec::new(), DEFAULT_SCREEN_TOLERANCE, &[1.0, 2.0, 4.0]);
+        assert_eq!(lod.select_level(0.25), 0);
+        assert_eq!(lod.select_level(1.0), 0);
+        assert_eq!(lod.select_level(1.5), 1);
+        assert_eq!(lod.select_level(4.0), 2);
+        // Finest level for scales beyond the cached range.
+        assert_eq!(lod.select_level(16.0), 2);
+    }
+
+    fn rect_path(x0: f64, y0: f64, x1: f64, y1: f64) -> kurbo::BezPath {
+        let mut path = kurbo::BezPath::new();
+        path.move_to((x0, y0));
+        path.line_to((x1, y0));
+        path.line_to((x1, y1));
+        path.line_to((x0, y1));
+        path.close_path();
+        path
+    }
+
+    fn elements(path: &kurbo::BezPath) -> Vec<kurbo::PathEl> {
+        path.into_iter().collect()
+    }
+
+    const VIEWPORT: kurbo::Rect = kurbo::Rect {
+        x0: 0.0,
+        y0: 0.0,
+        x1: 100.0,
+        y1: 100.0,
+    };
+
+    #[test]
+    fn cull_outside_viewport() {
+        let paths = [
+            rect_path(200.0, 20.0, 250.0, 80.0),
+            // Inside the guard band but not visible.
+            rect_path(-8.0, 20.0, -2.0, 80.0),
+            rect_path(20.0, 20.0, 80.0, 80.0),
+        ];
+        let visible = cull_paths(&paths, VIEWPORT, 10.0, 0.1);
+        assert_eq!(visible.len(), 1);
+        assert_eq!(elements(&visible[0]), elements(&paths[2]));
+    }
+
+    #[test]
+    fn cull_inside_guard_band() {
+        let mut curve = kurbo::BezPath::new();
+        curve.move_to((-5.0, 50.0));
+        curve.curve_to((-5.0, 0.0), (105.0, 0.0), (105.0, 50.0));
+        curve.close_path();
+
+        // Paths exceeding the viewport within the guard band aren't modified.
+        let paths = [rect_path(-10.0, -10.0, 110.0, 110.0), curve];
+        let visible = cull_paths(&paths, VIEWPORT, 10.0, 0.1);
+        assert_eq!(visible.len(), 2);
+        assert_eq!(elements(&visible[0]), elements(&paths[0]));
+        assert_eq!(elements(&visible[1]), elements(&paths[1]));
+    }
+
+    #[test]
+    fn clip_to_guard_band() {
+        let paths = [rect_path(-50.0, 20.0, 50.0, 500.0)];
+        let visible = cull_paths(&paths, VIEWPORT, 10.0, 0.1);
+        assert_eq!(visible.len(), 1);
+        assert_eq!(
+            visible[0].bounding_box(),
+            kurbo::Rect::new(-10.0, 20.0, 50.0, 110.0)
+        );
+    }
+
+    #[test]
+    fn cull_styled() {
+        let red = Style {
+            color: [1.0, 0.0, 0.0, 1.0],
+            ..Style::default()
+        };
+        let paths = [
+            (rect_path(200.0, 20.0, 250.0, 80.0), Style::default()),
+            (rect_path(-50.0, 20.0, 50.0, 80.0), red),
+        ];
+        let visible = cull_styled_paths(&paths, VIEWPORT, 10.0, 0.1);
+        assert_eq!(visible.len(), 1);
+        assert_eq!(visible[0].1, red);
+        assert_eq!(
+            visible[0].0.bounding_box(),
+            kurbo::Rect::new(-10.0, 20.0, 50.0, 80.0)
+        );
+    }
+
+    #[test]
+    fn clip_curves() {
+        let mut path = kurbo::BezPath::new();
+        path.move_to((-50.0, 50.0));
+        path.curve_to((-50.0, -50.0), (50.0, -50.0), (50.0, 50.0));
+        path.quad_to((0.0, 100.0), (-50.0, 50.0));
+        path.close_path();
+
+        let guard = kurbo::Rect::new(-10.0, -10.0, 110.0, 110.0);
+        let clipped = clip_path(&path, guard, 0.1);
+        assert!(elements(&clipped).into_iter().all(|elem| match elem {
+            kurbo::PathEl::MoveTo(p) | kurbo::PathEl::LineTo(p) => {
+                p.x >= guard.x0 && p.x <= guard.x1 && p.y >= guard.y0 && p.y <= guard.y1
+            }
+            kurbo::PathEl::ClosePath => true,
+            _ => false,
+        }));
+        let bbox = clipped.bounding_box();
+        assert_eq!(bbox.x0, -10.0);
+        assert!(bbox.x1 > 49.9 && bbox.x1 <= 50.0);
+    }
+
+    fn polyline(points: &[(f64, f64)], closed: bool) -> kurbo::BezPath {
+        let mut path = kurbo::BezPath::new();
+        path.move_to(points[0]);
+        for &p in &points[1..] {
+            path.line_to(p);
+        }
+        if closed {
+            path.close_path();
+        }
+        path
+    }
+
+    fn lines(gpu_data: &GpuData) -> Vec<[f32; 4]> {
+        gpu_data
+            .data
+            .chunks_exact(4)
+            .map(|line| [line[0], line[1], line[2], line[3]])
+            .collect()
+    }
+
+    fn normalize(paths: &[kurbo::BezPath], collinear_tolerance: f64) -> (GpuData, NormalizeStats) {
+        let normalize = Normalize {
+            collinear_tolerance,
+            ..Normalize::default()
+        };
+        generate_gpu_data_normalized(paths, &normalize)
+    }
+
+    #[test]
+    fn normalize_duplicate_points() {
+        let path = polyline(&[(0.0, 0.0), (5.0, 5.0), (5.0, 5.0), (10.0, 0.0)], false);
+        let (gpu_data, stats) = normalize(&[path], 0.0);
+        assert_eq!(
+            lines(&gpu_data),
+            [[0.0, 0.0, 5.0, 5.0], [5.0, 5.0, 10.0, 0.0]]
+        );
+        assert_eq!((stats.input_segments, stats.removed_segments), (3, 1));
+    }
+
+    #[test]
+    fn normalize_collinear_run() {
+        let path = polyline(
+            &[(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (3.0, 3.0), (4.0, 0.0)],
+            false,
+        );
+        let (gpu_data, stats) = normalize(&[path], 0.0);
+        assert_eq!(
+            lines(&gpu_data),
+            [[0.0, 0.0, 3.0, 3.0], [3.0, 3.0, 4.0, 0.0]]
+        );
+        assert_eq!((stats.input_segments, stats.removed_segments), (4, 2));
+    }
+
+    #[test]
+    fn normalize_flattened_arc() {
+        // Each point is close to the chord between its neighbors, the arc as a whole isn't.
+        let points = (0..=30)
+            .map(|i| {
+                let angle = (45.0 + i as f64).to_radians();
+                (100.0 * angle.cos(), 100.0 * angle.sin())
+            })
+            .collect::<Vec<_>>();
+        let tolerance = 0.05;
+        let (gpu_data, _) = normalize(&[polyline(&points, false)], tolerance);
+        let lines = lines(&gpu_data);
+        assert!(lines.len() > 1 && lines.len() < 30);
+
+        for &(x, y) in &points {
+            let p = kurbo::Point::new(x, y);
+            let dist = lines
+                .iter()
+                .map(|line| {
+                    let a = kurbo::Point::new(line[0] as f64, line[1] as f64);
+                    let b = kurbo::Point::new(line[2] as f64, line[3] as f64);
+                    let t = ((p - a).dot(b - a) / (b - a).hypot2()).clamp(0.0, 1.0);
+                    (p - a.lerp(b, t)).hypot()
+                })
+                .fold(f64::INFINITY, f64::min);
+            assert!(
+                dist <= tolerance + 1e-4,
+                "point {:?} deviates by {}",
+                p,
+                dist
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_closed_contour() {
+        // Ends at its start point, the closing segment has zero length.
+        let path = polyline(&[(0.0, 0.0), (10.0, 5.0), (5.0, 10.0), (0.0, 0.0)], true);
+        let (gpu_data, stats) = normalize(&[path], 0.0);
+        assert_eq!(
+            lines(&gpu_data),
+            [
+                [0.0, 0.0, 10.0, 5.0],
+                [10.0, 5.0, 5.0, 10.0],
+                [5.0, 10.0, 0.0, 0.0],
+            ]
+        );
+        assert_eq!((stats.input_segments, stats.removed_segments), (4, 1));
+    }
+
+    #[test]
+    fn normalize_rect_edges() {
+        // Horizontal edges carry the coverage, vertical ones are dropped.
+        let (gpu_data, stats) = normalize(&[rect_path(0.0, 0.0, 10.0, 20.0)], 0.0);
+        assert_eq!(
+            lines(&gpu_data),
+            [[0.0, 0.0, 10.0, 0.0], [10.0, 20.0, 0.0, 20.0]]
+        );
+        assert_eq!((stats.input_segments, stats.removed_segments), (4, 2));
+    }
+
+    #[test]
+    fn normalize_stats() {
+        let paths = [
+            rect_path(0.0, 0.0, 10.0, 20.0),
+            polyline(&[(0.0, 0.0), (10.0, 5.0), (5.0, 10.0)], true),
+        ];
+        let (gpu_data, stats) = normalize(&paths, 0.0);
+        assert_eq!((stats.input_segments, stats.removed_segments), (7, 2));
+        assert_eq!(gpu_data.primitives.len(), 5);
+        assert_eq!(gpu_data.styles, [0, 1]);
+        assert_eq!(gpu_data.objects[0].primitives, [0, 2]);
+        assert_eq!(gpu_data.objects[1].primitives, [2, 5]);
+        assert_eq!(gpu_data.objects[1].offset_data, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid scale range")]
+    fn lod_scale_range_zero() {
+        PathLod::with_scale_range(Vec::new(), DEFAULT_SCREEN_TOLERANCE, 0.0, 4.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid view scale")]
+    fn lod_scale_nan() {
+        PathLod::new(Vec::new(), DEFAULT_SCREEN_TOLERANCE, &[1.0, f64::NAN]);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one view scale required")]
+    fn lod_no_scales() {
+        PathLod::new(Vec::new(), DEFAULT_SCREEN_TOLERANCE, &[]);
+    }
+}
@@ -0,0 +1,216 @@
+//! Write flattened paths and gpu data back to svg for debugging the importer.
+
+use crate::svg::PRIMITIVE_LINE;
+use crate::{kurbo, Error, FillRule, GpuData, Style};
+use kurbo::Shape;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct SvgExportDesc {
+    /// Draw the bounding box of each object as stroked overlay.
+    pub bbox_overlay: bool,
+    /// Override the view box, defaults to the union of all bounding boxes.
+    pub view_box: Option<kurbo::Rect>,
+}
+
+fn write_header<W: Write>(writer: &mut W, view_box: kurbo::Rect) -> io::Result<()> {
+    writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+        view_box.x0,
+        view_box.y0,
+        view_box.width(),
+        view_box.height()
+    )
+}
+
+fn write_style<W: Write>(writer: &mut W, style: &Style) -> io::Result<()> {
+    let [r, g, b, a] = style.color;
+    let to_u8 = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+    write!(
+        writer,
+        r#" fill="rgb({},{},{})" fill-opacity="{}" fill-rule="{}""#,
+        to_u8(r),
+        to_u8(g),
+        to_u8(b),
+        a,
+        match style.fill_rule {
+            FillRule::NonZero => "nonzero",
+            FillRule::EvenOdd => "evenodd",
+        }
+    )
+}
+
+fn write_bbox<W: Write>(writer: &mut W, bbox: kurbo::Rect) -> io::Result<()> {
+    writeln!(
+        writer,
+        r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="red" stroke-width="1" vector-effect="non-scaling-stroke"/>"#,
+        bbox.x0,
+        bbox.y0,
+        bbox.width(),
+        bbox.height()
+    )
+}
+
+fn union_bbox<I: Iterator<Item = kurbo::Rect>>(boxes: I) -> kurbo::Rect {
+    boxes
+        .fold(None, |acc: Option<kurbo::Rect>, bbox| {
+            Some(acc.map_or(bbox, |acc| acc.union(bbox)))
+        })
+        .unwrap_or(kurbo::Rect::new(0.0, 0.0, 1.0, 1.0))
+}
+
+/// Write the line data of each object as svg path.
+///
+/// Consecutive lines sharing an end point are chained into a single subpath.
+/// Gaps, for example from dropped horizontal segments, start a new subpath.
+/// As svg implicitly closes subpaths for filling, the exported fill may differ
+/// slightly in these cases while the line data itself is exact.
+///
+/// `styles` are looked up by the style index of each object (`GpuData::styles`),
+/// missing styles fall back to the default.
+pub fn write_svg_gpu_data<W: Write>(
+    mut writer: W,
+    gpu_data: &GpuData,
+    styles: &[Style],
+    desc: &SvgExportDesc,
+) -> io::Result<()> {
+    let object_bbox =
+        |bbox: [f32; 4]| kurbo::Rect::new(bbox[0] as _, bbox[1] as _, bbox[2] as _, bbox[3] as _);
+
+    let view_box = desc.view_box.unwrap_or_else(|| {
+        union_bbox(
+            gpu_data
+                .objects
+                .iter()
+                .map(|object| object_bbox(object.bbox)),
+        )
+    });
+    write_header(&mut writer, view_box)?;
+
+    for (object, &style) in gpu_data.objects.iter().zip(&gpu_data.styles) {
+        let style = styles.get(style as usize).copied().unwrap_or_default();
+
+        write!(writer, r#"<path d=""#)?;
+        let mut last = None;
+        let [start, end] = object.primitives;
+        for (j, primitive) in (start..end).enumerate() {
+            assert_eq!(gpu_data.primitives[primitive as usize], PRIMITIVE_LINE);
+
+            let offset = (object.offset_data as usize + j) * 4;
+            let line = &gpu_data.data[offset..offset + 4];
+            let p0 = (line[0], line[1]);
+            let p1 = (line[2], line[3]);
+            if last != Some(p0) {
+                write!(writer, "M{} {} ", p0.0, p0.1)?;
+            }
+            write!(writer, "L{} {} ", p1.0, p1.1)?;
+            last = Some(p1);
+        }
+        write!(writer, r#"""#)?;
+        write_style(&mut writer, &style)?;
+        writeln!(writer, "/>")?;
+
+        if desc.bbox_overlay {
+            write_bbox(&mut writer, object_bbox(object.bbox))?;
+        }
+    }
+
+    writeln!(writer, "</svg>")
+}
+
+/// Write paths as svg, `styles` are matched to paths by index.
+pub fn write_svg_paths<W: Write>(
+    mut writer: W,
+    paths: &[kurbo::BezPath],
+    styles: &[Style],
+    desc: &SvgExportDesc,
+) -> io::Result<()> {
+    let view_box = desc
+        .view_box
+        .unwrap_or_else(|| union_bbox(paths.iter().map(|path| path.bounding_box())));
+    write_header(&mut writer, view_box)?;
+
+    for (i, path) in paths.iter().enumerate() {
+        let style = styles.get(i).copied().unwrap_or_default();
+
+        write!(writer, r#"<path d=""#)?;
+        for elem in path {
+            match elem {
+                kurbo::PathEl::MoveTo(p) => write!(writer, "M{} {} ", p.x, p.y)?,
+                kurbo::PathEl::LineTo(p) => write!(writer, "L{} {} ", p.x, p.y)?,
+                kurbo::PathEl::QuadTo(p1, p2) => {
+                    write!(writer, "Q{} {} {} {} ", p1.x, p1.y, p2.x, p2.y)?
+                }
+                kurbo::PathEl::CurveTo(p1, p2, p3) => write!(
+                    writer,
+                    "C{} {} {} {} {} {} ",
+                    p1.x, p1.y, p2.x, p2.y, p3.x, p3.y
+                )?,
+                kurbo::PathEl::ClosePath => write!(writer, "Z ")?,
+            }
+        }
+        write!(writer, r#"""#)?;
+        write_style(&mut writer, &style)?;
+        writeln!(writer, "/>")?;
+
+        if desc.bbox_overlay {
+            write_bbox(&mut writer, path.bounding_box())?;
+        }
+    }
+
+    writeln!(writer, "</svg>")
+}
+
+/// Export gpu data to an svg file.
+pub fn export_svg<P: AsRef<Path>>(
+    path: P,
+    gpu_data: &GpuData,
+    styles: &[Style],
+    desc: &SvgExportDesc,
+) -> Result<(), Error> {
+    let file = BufWriter::new(File::create(path)?);
+    write_svg_gpu_data(file, gpu_data, styles, desc)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_gpu_data_culled;
+
+    fn triangle(x: f64) -> kurbo::BezPath {
+        let mut path = kurbo::BezPath::new();
+        path.move_to((x, 0.0));
+        path.line_to((x + 10.0, 10.0));
+        path.line_to((x, 10.0));
+        path.close_path();
+        path
+    }
+
+    #[test]
+    fn culled_styles() {
+        let paths = [triangle(-100.0), triangle(0.0)];
+        let styles = [
+            Style {
+                color: [1.0, 0.0, 0.0, 1.0],
+                fill_rule: FillRule::NonZero,
+            },
+            Style {
+                color: [0.0, 0.0, 1.0, 1.0],
+                fill_rule: FillRule::EvenOdd,
+            },
+        ];
+
+        let gpu_data = generate_gpu_data_culled(&paths, kurbo::Rect::new(0.0, 0.0, 50.0, 50.0));
+        assert_eq!(gpu_data.styles, [1]);
+
+        let mut svg = Vec::new();
+        write_svg_gpu_data(&mut svg, &gpu_data, &styles, &SvgExportDesc::default()).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains(r#"fill="rgb(0,0,255)" fill-opacity="1" fill-rule="evenodd""#));
+        assert!(!svg.contains("rgb(255,0,0)"));
+    }
+}
@@ -0,0 +1,269 @@
+//! Export of CPU and GPU timings to the Chrome `trace_event` format.
+//!
+//! GPU timestamps are mapped onto the CPU clock with a `ClockCalibration`,
+//! a pair of CPU and GPU timestamps sampled at the same time. The resulting
+//! JSON can be loaded in `chrome://tracing` or Perfetto.
+
+use crate::{Error, ProfileScope};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Corresponding CPU and GPU timestamps with the frequencies of both clocks.
+///
+/// Clocks drift apart over time, calibrations should be refreshed regularly.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ClockCalibration {
+    pub gpu_timestamp: u64,
+    /// GPU ticks per second.
+    pub gpu_frequency: u64,
+    pub cpu_timestamp: u64,
+    /// CPU ticks per second.
+    pub cpu_frequency: u64,
+}
+
+impl ClockCalibration {
+    /// CPU timestamp in microseconds.
+    pub fn cpu_to_us(&self, ticks: u64) -> f64 {
+        ticks as f64 / self.cpu_frequency as f64 * 1_000_000.0
+    }
+
+    /// GPU timestamp in microseconds on the CPU clock.
+    pub fn gpu_to_us(&self, ticks: u64) -> f64 {
+        let delta = ticks as i128 - self.gpu_timestamp as i128;
+        self.cpu_to_us(self.cpu_timestamp) + delta as f64 / self.gpu_frequency as f64 * 1_000_000.0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TrackId(usize);
+
+/// Complete event spanning `begin_us..begin_us + duration_us` on a track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub name: String,
+    pub category: &'static str,
+    pub track: TrackId,
+    pub begin_us: f64,
+    pub duration_us: f64,
+}
+
+/// Timeline of named tracks, e.g. CPU threads or GPU queues.
+#[derive(Debug, Clone, Default)]
+pub struct ChromeTrace {
+    tracks: Vec<String>,
+    events: Vec<TraceEvent>,
+}
+
+impl ChromeTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_track(&mut self, name: &str) -> TrackId {
+        self.tracks.push(name.to_string());
+        TrackId(self.tracks.len() - 1)
+    }
+
+    pub fn add_span(
+        &mut self,
+        track: TrackId,
+        name: &str,
+        category: &'static str,
+        begin_us: f64,
+        duration_us: f64,
+    ) {
+        assert!(track.0 < self.tracks.len(), "invalid track {:?}", track);
+        self.events.push(TraceEvent {
+            name: name.to_string(),
+            category,
+            track,
+            begin_us,
+            duration_us,
+        });
+    }
+
+    /// Add a span between two CPU timestamps.
+    pub fn add_cpu_span(
+        &mut self,
+        track: TrackId,
+        name: &str,
+        begin: u64,
+        end: u64,
+        calibration: &ClockCalibration,
+    ) {
+        let begin_us = calibration.cpu_to_us(begin);
+        let end_us = calibration.cpu_to_us(end);
+        self.add_span(track, name, "cpu", begin_us, end_us - begin_us);
+    }
+
+    /// Add resolved profiler scopes including their children.
+    pub fn add_gpu_scopes(
+        &mut self,
+        track: TrackId,
+        scopes: &[ProfileScope],
+        calibration: &ClockCalibration,
+    ) {
+        for scope in scopes {
+            let begin_us = calibration.gpu_to_us(scope.begin);
+            let end_us = calibration.gpu_to_us(scope.end);
+            self.add_span(track, &scope.name, "gpu", begin_us, end_us - begin_us);
+            self.add_gpu_scopes(track, &scope.children, calibration);
+        }
+    }
+
+    pub fn tracks(&self) -> &[String] {
+        &self.tracks
+    }
+
+    pub fn events(&self) -> &[TraceEvent] {
+        &self.events
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    write!(writer, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(writer, "\\\"")?,
+            '\\' => write!(writer, "\\\\")?,
+            '\n' => write!(writer, "\\n")?,
+            '\r' => write!(writer, "\\r")?,
+            '\t' => write!(writer, "\\t")?,
+            c if (c as u32) < 0x20 => write!(writer, "\\u{:04x}", c as u32)?,
+            c => write!(writer, "{}", c)?,
+        }
+    }
+    write!(writer, "\"")
+}
+
+/// Write the trace as JSON object with a `traceEvents` array.
+///
+/// Tracks are emitted as threads of a single process, named by metadata events.
+pub fn write_chrome_trace<W: Write>(writer: &mut W, trace: &ChromeTrace) -> io::Result<()> {
+    writeln!(writer, "{{\"traceEvents\":[")?;
+    let mut first = true;
+    for (tid, name) in trace.tracks.iter().enumerate() {
+        if !first {
+            writeln!(writer, ",")?;
+        }
+        first = false;
+        write!(
+            writer,
+            "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":{},\"args\":{{\"name\":",
+            tid
+        )?;
+        write_string(writer, name)?;
+        write!(writer, "}}}}")?;
+    }
+    for event in &trace.events {
+        if !first {
+            writeln!(writer, ",")?;
+        }
+        first = false;
+        write!(writer, "{{\"name\":")?;
+        write_string(writer, &event.name)?;
+        write!(writer, ",\"cat\":")?;
+        write_string(writer, event.category)?;
+        write!(
+            writer,
+            ",\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}}}",
+            event.track.0,
+            event.begin_us,
+            event.duration_us.max(0.0)
+        )?;
+    }
+    writeln!(writer, "\n]}}")
+}
+
+pub fn export_chrome_trace<P: AsRef<Path>>(path: P, trace: &ChromeTrace) -> Result<(), Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_chrome_trace(&mut file, trace)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DurationStats;
+
+    const CALIBRATION: ClockCalibration = ClockCalibration {
+        gpu_timestamp: 1_000,
+        gpu_frequency: 1_000_000,
+        cpu_timestamp: 50_000,
+        cpu_frequency: 10_000_000,
+    };
+
+    fn scope(name: &str, begin: u64, end: u64, children: Vec<ProfileScope>) -> ProfileScope {
+        ProfileScope {
+            name: name.to_string(),
+            begin,
+            end,
+            duration_ms: (end - begin) as f64 / 1000.0,
+            stats: DurationStats::default(),
+            children,
+        }
+    }
+
+    #[test]
+    fn clock_alignment() {
+        assert_eq!(CALIBRATION.cpu_to_us(50_000), 5_000.0);
+        assert_eq!(CALIBRATION.gpu_to_us(1_000), 5_000.0);
+        assert_eq!(CALIBRATION.gpu_to_us(1_250), 5_250.0);
+        assert_eq!(CALIBRATION.gpu_to_us(500), 4_500.0);
+    }
+
+    #[test]
+    fn nested_gpu_scopes() {
+        let mut trace = ChromeTrace::new();
+        let gpu = trace.add_track("gpu");
+        let scopes = [scope(
+            "frame",
+            1_000,
+            1_300,
+            vec![scope("raster", 1_010, 1_200, Vec::new())],
+        )];
+        trace.add_gpu_scopes(gpu, &scopes, &CALIBRATION);
+
+        let events = trace.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].name, "frame");
+        assert_eq!(events[0].begin_us, 5_000.0);
+        assert_eq!(events[0].duration_us, 300.0);
+        assert_eq!(events[1].name, "raster");
+        assert_eq!(events[1].begin_us, 5_010.0);
+        assert_eq!(events[1].duration_us, 190.0);
+    }
+
+    #[test]
+    fn json() {
+        let mut trace = ChromeTrace::new();
+        let cpu = trace.add_track("main");
+        let gpu = trace.add_track("direct \"queue\"");
+        trace.add_cpu_span(cpu, "record", 50_000, 52_000, &CALIBRATION);
+        trace.add_gpu_scopes(
+            gpu,
+            &[scope("frame", 1_100, 1_150, Vec::new())],
+            &CALIBRATION,
+        );
+
+        let mut json = Vec::new();
+        write_chrome_trace(&mut json, &trace).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            concat!(
+                "{\"traceEvents\":[\n",
+                "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":0,\"args\":{\"name\":\"main\"}},\n",
+                "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":1,\"args\":{\"name\":\"direct \\\"queue\\\"\"}},\n",
+                "{\"name\":\"record\",\"cat\":\"cpu\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\"ts\":5000.000,\"dur\":200.000},\n",
+                "{\"name\":\"frame\",\"cat\":\"gpu\",\"ph\":\"X\",\"pid\":0,\"tid\":1,\"ts\":5100.000,\"dur\":50.000}\n",
+                "]}\n",
+            )
+        );
+    }
+}
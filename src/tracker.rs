@@ -0,0 +1,404 @@
+//! Backend independent resource state tracking.
+//!
+//! A `CommandTracker` records the states required by the commands of a single
+//! command buffer and emits batched transitions between them. The state a
+//! resource needs on its first use isn't known at recording time, these
+//! first uses are reconciled against the queue level state on submission
+//! with `reconcile`.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+/// Subresource index addressing all subresources of a resource.
+pub const ALL_SUBRESOURCES: u32 = !0;
+
+/// Resource state of a backend, e.g a combination of D3D12 resource state flags.
+pub trait TrackedState: Copy + Eq + Debug {
+    /// Check if a resource in this state can be accessed as `required` without a transition.
+    fn satisfies(self, required: Self) -> bool;
+
+    /// Consecutive accesses in this state need to be ordered by a barrier, e.g unordered access.
+    fn is_unordered(self) -> bool;
+}
+
+/// Unique identifier of a tracked resource.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ResourceId(pub usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TrackedResource<S> {
+    pub id: ResourceId,
+    pub num_subresources: u32,
+    /// State the resource is implicitly promoted from on first use in a
+    /// command buffer and decays to after submission (e.g D3D12 buffers).
+    pub decay: Option<S>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Barrier<S> {
+    Transition {
+        resource: ResourceId,
+        /// Subresource index or `ALL_SUBRESOURCES`.
+        subresource: u32,
+        before: S,
+        after: S,
+    },
+    /// Orders accesses of a resource in unordered access state.
+    Unordered { resource: ResourceId },
+}
+
+/// Resource states of a single command buffer.
+#[derive(Debug, Clone)]
+pub struct ResourceUsage<S> {
+    pub resource: TrackedResource<S>,
+    /// State required by the first use of each subresource, `None` if unused.
+    pub first: Vec<Option<S>>,
+    /// State after the last use of each subresource.
+    pub last: Vec<Option<S>>,
+}
+
+// Combine per subresource transitions into a single transition of the whole
+// resource if all subresources transition between the same states.
+fn push_transitions<S: TrackedState>(
+    barriers: &mut Vec<Barrier<S>>,
+    resource: &TrackedResource<S>,
+    transitions: Vec<(u32, S, S)>,
+) {
+    let whole = transitions.len() == resource.num_subresources as usize
+        && transitions.len() > 1
+        && transitions
+            .iter()
+            .all(|&(_, before, after)| (before, after) == (transitions[0].1, transitions[0].2));
+
+    if whole {
+        barriers.push(Barrier::Transition {
+            resource: resource.id,
+            subresource: ALL_SUBRESOURCES,
+            before: transitions[0].1,
+            after: transitions[0].2,
+        });
+    } else {
+        barriers.extend(transitions.into_iter().map(|(subresource, before, after)| {
+            Barrier::Transition {
+                resource: resource.id,
+                subresource,
+                before,
+                after,
+            }
+        }));
+    }
+}
+
+fn subresources<S>(resource: &TrackedResource<S>, subresource: u32) -> std::ops::Range<u32> {
+    if subresource == ALL_SUBRESOURCES {
+        0..resource.num_subresources
+    } else {
+        assert!(
+            subresource < resource.num_subresources,
+            "subresource {} out of range, resource has {} subresources",
+            subresource,
+            resource.num_subresources
+        );
+        subresource..subresource + 1
+    }
+}
+
+/// State tracking of a single command buffer.
+#[derive(Debug)]
+pub struct CommandTracker<S> {
+    usages: Vec<ResourceUsage<S>>,
+    indices: HashMap<ResourceId, usize>,
+    pending: Vec<Barrier<S>>,
+}
+
+impl<S: TrackedState> Default for CommandTracker<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: TrackedState> CommandTracker<S> {
+    pub fn new() -> Self {
+        CommandTracker {
+            usages: Vec::new(),
+            indices: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Forget all recorded usages, e.g on command buffer reset.
+    pub fn reset(&mut self) {
+        self.usages.clear();
+        self.indices.clear();
+        self.pending.clear();
+    }
+
+    /// Require `state` for a subresource (or `ALL_SUBRESOURCES`) of the resource.
+    ///
+    /// Transitions are queued until the next `flush`.
+    pub fn require(&mut self, resource: &TrackedResource<S>, subresource: u32, state: S) {
+        let usages = &mut self.usages;
+        let index = *self.indices.entry(resource.id).or_insert_with(|| {
+            usages.push(ResourceUsage {
+                resource: *resource,
+                first: vec![None; resource.num_subresources as usize],
+                last: vec![None; resource.num_subresources as usize],
+            });
+            usages.len() - 1
+        });
+        let usage = &mut self.usages[index];
+        assert_eq!(usage.resource, *resource);
+
+        let mut transitions = Vec::new();
+        let mut unordered = false;
+        for i in subresources(resource, subresource) {
+            let i = i as usize;
+            match usage.last[i] {
+                None => {
+                    usage.first[i] = Some(state);
+                    usage.last[i] = Some(state);
+                }
+                Some(last) if last == state => unordered |= state.is_unordered(),
+                Some(last) if last.satisfies(state) => {}
+                Some(last) => {
+                    transitions.push((i as u32, last, state));
+                    usage.last[i] = Some(state);
+                }
+            }
+        }
+
+        push_transitions(&mut self.pending, resource, transitions);
+        if unordered {
+            let barrier = Barrier::Unordered {
+                resource: resource.id,
+            };
+            if !self.pending.contains(&barrier) {
+                self.pending.push(barrier);
+            }
+        }
+    }
+
+    /// Queue a barrier ordering unordered accesses of a resource used before,
+    /// e.g a barrier resolved ahead of recording.
+    pub fn order_unordered(&mut self, resource: ResourceId) {
+        assert!(
+            self.indices.contains_key(&resource),
+            "resource {:?} hasn't been used before",
+            resource
+        );
+        let barrier = Barrier::Unordered { resource };
+        if !self.pending.contains(&barrier) {
+            self.pending.push(barrier);
+        }
+    }
+
+    /// Take the queued barriers, to be recorded before the next command.
+    pub fn flush(&mut self) -> Vec<Barrier<S>> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Resources used by the command buffer in order of first use.
+    pub fn usages(&self) -> &[ResourceUsage<S>] {
+        &self.usages
+    }
+}
+
+/// Reconcile the first uses of a command buffer with the queue level state.
+///
+/// `queue` holds the state of each subresource before the command buffer is
+/// executed and is updated to the state after execution. Returns the
+/// transitions required before executing the command buffer.
+pub fn reconcile<S: TrackedState>(usage: &ResourceUsage<S>, queue: &mut [S]) -> Vec<Barrier<S>> {
+    assert_eq!(queue.len(), usage.resource.num_subresources as usize);
+
+    let mut transitions = Vec::new();
+    for (i, (first, last)) in usage.first.iter().zip(&usage.last).enumerate() {
+        let (first, last) = match (*first, *last) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+
+        let current = queue[i];
+        let promoted = usage.resource.decay == Some(current);
+        if !promoted && !current.satisfies(first) {
+            transitions.push((i as u32, current, first));
+        }
+        queue[i] = usage.resource.decay.unwrap_or(last);
+    }
+
+    let mut barriers = Vec::new();
+    push_transitions(&mut barriers, &usage.resource, transitions);
+    barriers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    enum State {
+        Common,
+        CopySrc,
+        CopyDst,
+        Unordered,
+    }
+
+    impl TrackedState for State {
+        fn satisfies(self, required: Self) -> bool {
+            self == required
+        }
+
+        fn is_unordered(self) -> bool {
+            self == State::Unordered
+        }
+    }
+
+    fn resource(id: usize, num_subresources: u32, decay: Option<State>) -> TrackedResource<State> {
+        TrackedResource {
+            id: ResourceId(id),
+            num_subresources,
+            decay,
+        }
+    }
+
+    fn transition(id: usize, subresource: u32, before: State, after: State) -> Barrier<State> {
+        Barrier::Transition {
+            resource: ResourceId(id),
+            subresource,
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn flush_batching() {
+        let a = resource(0, 1, None);
+        let b = resource(1, 1, None);
+        let mut tracker = CommandTracker::new();
+
+        // First uses are left to reconciliation.
+        tracker.require(&a, 0, State::CopySrc);
+        tracker.require(&b, 0, State::CopyDst);
+        assert!(tracker.flush().is_empty());
+
+        tracker.require(&a, 0, State::CopyDst);
+        tracker.require(&b, 0, State::CopySrc);
+        assert_eq!(
+            tracker.flush(),
+            [
+                transition(0, 0, State::CopySrc, State::CopyDst),
+                transition(1, 0, State::CopyDst, State::CopySrc),
+            ]
+        );
+        assert!(tracker.flush().is_empty());
+
+        // Requiring the current state again doesn't queue anything.
+        tracker.require(&a, 0, State::CopyDst);
+        assert!(tracker.flush().is_empty());
+    }
+
+    #[test]
+    fn merge_subresources() {
+        let image = resource(0, 4, None);
+        let mut tracker = CommandTracker::new();
+
+        tracker.require(&image, ALL_SUBRESOURCES, State::CopyDst);
+        tracker.require(&image, ALL_SUBRESOURCES, State::CopySrc);
+        assert_eq!(
+            tracker.flush(),
+            [transition(
+                0,
+                ALL_SUBRESOURCES,
+                State::CopyDst,
+                State::CopySrc
+            )]
+        );
+    }
+
+    #[test]
+    fn split_subresources() {
+        let image = resource(0, 4, None);
+        let mut tracker = CommandTracker::new();
+
+        tracker.require(&image, ALL_SUBRESOURCES, State::CopySrc);
+        tracker.require(&image, 2, State::CopyDst);
+        assert_eq!(
+            tracker.flush(),
+            [transition(0, 2, State::CopySrc, State::CopyDst)]
+        );
+
+        // Subresources in different states transition individually.
+        tracker.require(&image, ALL_SUBRESOURCES, State::Unordered);
+        assert_eq!(
+            tracker.flush(),
+            [
+                transition(0, 0, State::CopySrc, State::Unordered),
+                transition(0, 1, State::CopySrc, State::Unordered),
+                transition(0, 2, State::CopyDst, State::Unordered),
+                transition(0, 3, State::CopySrc, State::Unordered),
+            ]
+        );
+    }
+
+    #[test]
+    fn unordered_barriers() {
+        let buffer = resource(0, 1, None);
+        let mut tracker = CommandTracker::new();
+
+        tracker.require(&buffer, 0, State::Unordered);
+        assert!(tracker.flush().is_empty());
+
+        // Consecutive unordered accesses are separated by a single barrier per flush.
+        tracker.require(&buffer, 0, State::Unordered);
+        tracker.require(&buffer, 0, State::Unordered);
+        assert_eq!(
+            tracker.flush(),
+            [Barrier::Unordered {
+                resource: ResourceId(0)
+            }]
+        );
+
+        tracker.require(&buffer, 0, State::CopySrc);
+        assert_eq!(
+            tracker.flush(),
+            [transition(0, 0, State::Unordered, State::CopySrc)]
+        );
+    }
+
+    #[test]
+    fn reconcile_queue_state() {
+        let image = resource(0, 2, None);
+        let mut tracker = CommandTracker::new();
+        tracker.require(&image, 1, State::CopyDst);
+        tracker.require(&image, 1, State::CopySrc);
+
+        let mut queue = [State::Common, State::Common];
+        assert_eq!(
+            reconcile(&tracker.usages()[0], &mut queue),
+            [transition(0, 1, State::Common, State::CopyDst)]
+        );
+        // Unused subresources keep their state.
+        assert_eq!(queue, [State::Common, State::CopySrc]);
+    }
+
+    #[test]
+    fn reconcile_decay() {
+        let buffer = resource(0, 1, Some(State::Common));
+        let mut tracker = CommandTracker::new();
+        tracker.require(&buffer, 0, State::CopyDst);
+        tracker.require(&buffer, 0, State::Unordered);
+
+        // Promoted from the decay state without a transition and decays afterwards.
+        let mut queue = [State::Common];
+        assert!(reconcile(&tracker.usages()[0], &mut queue).is_empty());
+        assert_eq!(queue, [State::Common]);
+
+        // Other states still require a transition.
+        let mut queue = [State::CopySrc];
+        assert_eq!(
+            reconcile(&tracker.usages()[0], &mut queue),
+            [transition(0, 0, State::CopySrc, State::CopyDst)]
+        );
+        assert_eq!(queue, [State::Common]);
+    }
+}
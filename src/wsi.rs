@@ -2,13 +2,18 @@
 //!
 //! Currently only supporting winit.
 
-use crate::{Device, Error, Image, Queue};
+use crate::com::Owned;
+use crate::lost::DeviceState;
+use crate::resource::Tracking;
+use crate::{Device, Error, Image, Queue, RESOURCE_STATE_PRESENT};
+use std::sync::Arc;
 use winapi::shared::{dxgiformat, dxgitype};
 use winit::{platform::windows::WindowExtWindows, window::Window};
 
 pub struct Swapchain {
     swapchain: d3d12::SwapChain3,
     render_targets: Vec<Image>,
+    state: Arc<DeviceState>,
 }
 
 impl Device {
@@ -37,24 +42,41 @@ impl Device {
             alpha_mode: d3d12::AlphaMode::Ignore,
             flags: 0,
         };
-        let (swapchain, _) = self.factory.as_factory2().create_swapchain_for_hwnd(
+        let (swapchain1, hr) = self.factory.as_factory2().create_swapchain_for_hwnd(
             present_queue.queue,
             hwnd as _,
             &desc,
         );
+        self.check(hr)?;
 
         let render_targets = (0..buffer_size)
             .map(|i| {
-                let (image, _) = swapchain.as_swapchain0().get_buffer(i);
-                Image(image)
+                let (image, hr) = swapchain1.as_swapchain0().get_buffer(i);
+                self.check(hr)?;
+                let image = unsafe { Owned::new(image) };
+                Ok(Image(
+                    *image,
+                    Tracking::new(
+                        image,
+                        1,
+                        None,
+                        RESOURCE_STATE_PRESENT,
+                        self.deletion.clone(),
+                    ),
+                ))
             })
-            .collect();
+            .collect::<Result<_, Error>>()?;
 
-        let (swapchain, _) = unsafe { swapchain.cast() };
+        let (swapchain, hr) = unsafe { swapchain1.cast() };
+        unsafe {
+            swapchain1.destroy();
+        }
+        self.check(hr)?;
 
         Ok(Swapchain {
             swapchain,
             render_targets,
+            state: self.state.clone(),
         })
     }
 }
@@ -68,9 +90,9 @@ impl Swapchain {
         self.swapchain.get_current_back_buffer_index() as _
     }
 
-    pub fn present(&self) {
-        unsafe {
-            self.swapchain.as_swapchain0().Present(0, 0);
-        }
+    pub fn present(&self) -> Result<(), Error> {
+        self.state.check_lost()?;
+        let hr = unsafe { self.swapchain.as_swapchain0().Present(0, 0) };
+        self.state.check(hr)
     }
 }